@@ -1,6 +1,7 @@
 use ::gfx_backend_vulkan as back;
 use swf_renderer::asset::ClientAssetStore;
-use swf_renderer::stage::{DisplayPrimitive, Matrix2D, Stage, StoredShape};
+use swf_renderer::recording::Recorder;
+use swf_renderer::stage::{ColorTransform, DisplayPrimitive, Matrix2D, Stage, StoredShape};
 use swf_renderer::GfxRenderer;
 use swf_renderer::SwfRenderer;
 use swf_tree::tags::DefineShape;
@@ -9,7 +10,7 @@ use swf_tree::StraightSRgba8;
 fn main() {
   let args: Vec<String> = std::env::args().collect();
   if args.len() < 2 {
-    eprintln!("Missing input path");
+    eprintln!("Usage: swf-renderer <define-shape.json> [recording-output]");
     return;
   }
 
@@ -31,7 +32,7 @@ fn main() {
     ))
     .with_title("swf-renderer".to_string());
 
-  let (_window, adapter, surface) = {
+  let (window, adapter, surface) = {
     let window = wb.build(&event_loop).unwrap();
     let instance = back::Instance::create("ofl-swf-renderer", 1).expect("Failed to create instance");
     let surface = instance.create_surface(&window).expect("Failed to create surface");
@@ -39,8 +40,19 @@ fn main() {
     // Return `window` so it is not dropped: dropping it invalidates `surface`.
     (window, adapter, surface)
   };
-  let mut renderer: GfxRenderer<back::Backend> = GfxRenderer::new(adapter, surface);
-  let shape_id = renderer.register_shape(&tag);
+  let renderer: GfxRenderer<back::Backend> = GfxRenderer::new(adapter, surface).expect("Failed to create renderer");
+  // Record the session if an output path is provided, it can be replayed with `swf-replay`
+  let recording: Box<dyn ::std::io::Write> = match args.get(2) {
+    Some(path) => Box::new(::std::io::BufWriter::new(
+      ::std::fs::File::create(path).expect("Failed to create recording"),
+    )),
+    None => Box::new(::std::io::sink()),
+  };
+  let (width, height) = renderer.size();
+  let viewport = renderer.viewport.clone();
+  let mut renderer = Recorder::new(renderer, recording, width, height, viewport);
+  let movie = renderer.create_movie();
+  let shape_id = renderer.register_shape(movie, &tag).expect("Failed to register shape");
 
   event_loop.run(move |event, _, control_flow| {
     *control_flow = winit::event_loop::ControlFlow::Wait;
@@ -57,7 +69,10 @@ fn main() {
           ..
         } => *control_flow = winit::event_loop::ControlFlow::Exit,
         winit::event::WindowEvent::Resized(dims) => {
-          println!("resized to {:?}", dims);
+          let size = dims.to_physical(window.hidpi_factor());
+          renderer
+            .resize(size.width.round() as u32, size.height.round() as u32)
+            .expect("Failed to resize renderer");
         }
         _ => {}
       },
@@ -72,9 +87,11 @@ fn main() {
           display_root: vec![DisplayPrimitive::Shape(StoredShape {
             id: shape_id,
             matrix: Matrix2D::default(),
+            color_transform: ColorTransform::default(),
+            name: None,
           })],
         };
-        renderer.render(stage);
+        renderer.render(stage).expect("Failed to render stage");
       }
       _ => {}
     }
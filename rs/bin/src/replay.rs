@@ -0,0 +1,41 @@
+use ::gfx_backend_vulkan as back;
+use swf_renderer::headless_renderer::HeadlessGfxRenderer;
+use swf_renderer::pam;
+use swf_renderer::recording::replay;
+
+/// Initial size of the output, the recording starts by resizing it to the size of the recorded session
+const INITIAL_WIDTH: usize = 550;
+const INITIAL_HEIGHT: usize = 400;
+
+fn main() {
+  let args: Vec<String> = std::env::args().collect();
+  if args.len() < 3 {
+    eprintln!("Usage: swf-replay <recording> <output-dir>");
+    return;
+  }
+
+  let recording_path = &args[1];
+  let output_dir = ::std::path::Path::new(&args[2]);
+
+  env_logger::init();
+  let recording = ::std::fs::File::open(recording_path).expect("Failed to open recording");
+  let mut reader = ::std::io::BufReader::new(recording);
+  ::std::fs::create_dir_all(output_dir).expect("Failed to create output directory");
+
+  let instance = back::Instance::create("ofl-swf-replay", 1).expect("Failed to create instance");
+  let renderer: HeadlessGfxRenderer<back::Backend> =
+    HeadlessGfxRenderer::new(&instance, INITIAL_WIDTH, INITIAL_HEIGHT).expect("Failed to create renderer");
+
+  let mut frame_count: usize = 0;
+  replay(&mut reader, renderer, |renderer, frame| {
+    let image = renderer.download_image()?;
+    let path = output_dir.join(format!("frame-{:06}.pam", frame));
+    let mut file = ::std::fs::File::create(&path).expect("Failed to create frame file");
+    pam::write_pam(&mut file, &image).expect("Failed to write frame");
+    frame_count += 1;
+    Ok(())
+  })
+  .expect("Failed to replay recording");
+
+  println!("Replayed {} frames to {}", frame_count, output_dir.display());
+}
@@ -0,0 +1,86 @@
+//! Recompiles the shader variants when the `compile-shaders` feature is enabled.
+//!
+//! For each variant listed in `VARIANTS`, the GLSL sources of `src/` are compiled to SPIR-V, and the SPIR-V
+//! is translated to GLSL ES 3.0 for the GL backend. The results are written to `src/shaders/`, where
+//! `src/shaders.rs` embeds them. The compiled shaders are checked in, so the regular
+//! builds do not need a shader compiler: run `cargo build --features compile-shaders` after changing a source
+//! or a variant, and commit the result.
+
+#[cfg(feature = "compile-shaders")]
+mod compile {
+  use std::fs;
+  use std::path::Path;
+
+  use spirv_cross::{glsl, spirv};
+
+  /// Shader variants, as `(name, macro definitions)`: keep in sync with `ShaderVariant::name`.
+  const VARIANTS: &[(&str, &[&str])] = &[
+    ("solid", &["FILL_SOLID"]),
+    ("solid-curves", &["FILL_SOLID", "ANALYTIC_CURVES"]),
+  ];
+
+  const STAGES: &[(&str, shaderc::ShaderKind)] = &[
+    ("vert", shaderc::ShaderKind::Vertex),
+    ("frag", shaderc::ShaderKind::Fragment),
+  ];
+
+  pub fn compile_shaders() -> () {
+    let out_dir = Path::new("src").join("shaders");
+    fs::create_dir_all(&out_dir).expect("Failed to create shader output directory");
+
+    let mut compiler = shaderc::Compiler::new().expect("Failed to create shader compiler");
+
+    for (stage, kind) in STAGES.iter() {
+      let source_path = Path::new("src").join(format!("shader.{}.glsl", stage));
+      println!("cargo:rerun-if-changed={}", source_path.display());
+      let source = fs::read_to_string(&source_path).expect("Failed to read shader source");
+
+      for (name, definitions) in VARIANTS.iter() {
+        let mut options = shaderc::CompileOptions::new().expect("Failed to create shader compile options");
+        for definition in definitions.iter() {
+          options.add_macro_definition(definition, None);
+        }
+        let artifact = compiler
+          .compile_into_spirv(&source, *kind, &source_path.to_string_lossy(), "main", Some(&options))
+          .unwrap_or_else(|e| panic!("Failed to compile {} shader ({}): {}", stage, name, e));
+
+        fs::write(out_dir.join(format!("{}.{}.spv", name, stage)), artifact.as_binary_u8())
+          .expect("Failed to write SPIR-V shader");
+      }
+    }
+  }
+
+  /// Translates a SPIR-V module to GLSL ES 3.0.
+  ///
+  /// GLSL ES 3.0 matches the varyings by name instead of location: they are renamed `v_<location>` in both
+  /// stages.
+  fn to_glsl_es(words: &[u32], kind: shaderc::ShaderKind) -> String {
+    let module = spirv::Module::from_words(words);
+    let mut ast = spirv::Ast::<glsl::Target>::parse(&module).expect("Failed to parse SPIR-V");
+    let mut options = glsl::CompilerOptions::default();
+    options.version = glsl::Version::V3_00Es;
+    ast.set_compiler_options(&options).expect("Failed to set GLSL options");
+
+    let resources = ast.get_shader_resources().expect("Failed to read shader resources");
+    let varyings = match kind {
+      shaderc::ShaderKind::Vertex => resources.stage_outputs,
+      _ => resources.stage_inputs,
+    };
+    for varying in varyings.iter() {
+      let location = ast
+        .get_decoration(varying.id, spirv::Decoration::Location)
+        .expect("Failed to read varying location");
+      ast
+        .rename_interface_variable(&varyings, location, &format!("v_{}", location))
+        .expect("Failed to rename varying");
+    }
+
+    ast.compile().expect("Failed to translate SPIR-V to GLSL ES")
+  }
+}
+
+fn main() -> () {
+  println!("cargo:rerun-if-changed=build.rs");
+  #[cfg(feature = "compile-shaders")]
+  compile::compile_shaders();
+}
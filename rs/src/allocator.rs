@@ -0,0 +1,74 @@
+use std::ops::Range;
+
+/// Allocates ranges of elements inside a buffer, using a first-fit free list.
+#[derive(Debug, Clone)]
+pub struct RangeAllocator {
+  capacity: u32,
+  /// Free ranges, sorted and disjoint (adjacent ranges are merged)
+  free: Vec<Range<u32>>,
+}
+
+impl RangeAllocator {
+  pub fn new(capacity: u32) -> Self {
+    let mut allocator = Self {
+      capacity: 0,
+      free: Vec::new(),
+    };
+    allocator.grow(capacity);
+    allocator
+  }
+
+  pub fn capacity(&self) -> u32 {
+    self.capacity
+  }
+
+  /// Number of allocated elements.
+  pub fn used(&self) -> u32 {
+    self.capacity - self.free.iter().map(|range| range.end - range.start).sum::<u32>()
+  }
+
+  /// Returns the first free range of `size` elements, or `None` if there is not enough contiguous space.
+  pub fn allocate(&mut self, size: u32) -> Option<Range<u32>> {
+    if size == 0 {
+      return Some(0..0);
+    }
+    let index = self.free.iter().position(|range| range.end - range.start >= size)?;
+    let start = self.free[index].start;
+    if self.free[index].end - start == size {
+      self.free.remove(index);
+    } else {
+      self.free[index].start += size;
+    }
+    Some(start..(start + size))
+  }
+
+  /// Returns a range obtained from `allocate` to the free list.
+  pub fn free(&mut self, range: Range<u32>) -> () {
+    if range.start == range.end {
+      return;
+    }
+    debug_assert!(range.end <= self.capacity);
+    let index = self
+      .free
+      .iter()
+      .position(|free| free.start > range.start)
+      .unwrap_or(self.free.len());
+    debug_assert!(index == 0 || self.free[index - 1].end <= range.start);
+    debug_assert!(index == self.free.len() || range.end <= self.free[index].start);
+    self.free.insert(index, range);
+    if index + 1 < self.free.len() && self.free[index].end == self.free[index + 1].start {
+      self.free[index].end = self.free.remove(index + 1).end;
+    }
+    if index > 0 && self.free[index - 1].end == self.free[index].start {
+      self.free[index - 1].end = self.free.remove(index).end;
+    }
+  }
+
+  /// Extends the managed buffer to `capacity` elements, keeping the current allocations.
+  pub fn grow(&mut self, capacity: u32) -> () {
+    debug_assert!(capacity >= self.capacity);
+    let old_capacity = self.capacity;
+    self.capacity = capacity;
+    self.free(old_capacity..capacity);
+  }
+}
@@ -1,14 +1,50 @@
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
 use swf_tree::tags::{DefineMorphShape, DefineShape};
 
-#[derive(Debug, Clone, Copy)]
-pub struct ShapeId(pub usize);
+use crate::error::RendererError;
+
+/// Renderer-global handle of a registered shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct ShapeId(pub(crate) usize);
 
-#[derive(Debug, Clone, Copy)]
-pub struct MorphShapeId(pub usize);
+/// Renderer-global handle of a registered morph shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MorphShapeId(pub(crate) usize);
+
+/// Handle of a movie namespace: the character ids of a movie are only unique within its namespace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct MovieId(usize);
 
 pub trait ClientAssetStore {
-  fn register_shape(&mut self, tag: &DefineShape) -> ShapeId;
-  fn register_morph_shape(&mut self, tag: &DefineMorphShape) -> MorphShapeId;
+  /// Creates the namespace of the assets of a new movie.
+  fn create_movie(&mut self) -> MovieId;
+  /// Releases all the assets registered in a movie namespace: they must no longer be displayed.
+  fn release_movie(&mut self, movie: MovieId) -> ();
+
+  /// Registers a shape of `movie`, replacing the shape with the same character id if there is one.
+  ///
+  /// On failure (e.g. a malformed shape), the previous definition is kept.
+  fn register_shape(&mut self, movie: MovieId, tag: &DefineShape) -> Result<ShapeId, RendererError>;
+  /// Registers a morph shape of `movie`, replacing the morph shape with the same character id if there is one.
+  ///
+  /// On failure, the previous definition is kept.
+  fn register_morph_shape(&mut self, movie: MovieId, tag: &DefineMorphShape) -> Result<MorphShapeId, RendererError>;
+
+  /// Replaces the definition of a registered shape, releasing the resources of the previous one.
+  ///
+  /// On failure, the previous definition is kept.
+  fn redefine_shape(&mut self, id: ShapeId, tag: &DefineShape) -> Result<(), RendererError>;
+  /// Replaces the definition of a registered morph shape, releasing the resources of the previous one.
+  ///
+  /// On failure, the previous definition is kept.
+  fn redefine_morph_shape(&mut self, id: MorphShapeId, tag: &DefineMorphShape) -> Result<(), RendererError>;
+
+  /// Releases a shape and its GPU resources: the id must no longer be displayed.
+  fn release_shape(&mut self, id: ShapeId) -> ();
+  /// Releases a morph shape and its GPU resources: the id must no longer be displayed.
+  fn release_morph_shape(&mut self, id: MorphShapeId) -> ();
 }
 
 pub trait ServerAssetStore {
@@ -18,3 +54,60 @@ pub trait ServerAssetStore {
   fn get_shape(&mut self, id: ShapeId) -> Option<Self::Shape>;
   fn get_morph_shape(&mut self, id: MorphShapeId) -> Option<Self::MorphShape>;
 }
+
+/// Allocates the movie namespaces and the renderer-global asset ids, for the `ClientAssetStore`
+/// implementations.
+#[derive(Debug)]
+pub struct AssetIds {
+  next_movie: usize,
+  next_asset: usize,
+  /// Global id of each character, by movie and character id
+  assets: HashMap<(MovieId, u16), usize>,
+}
+
+impl AssetIds {
+  pub fn new() -> Self {
+    Self {
+      next_movie: 0,
+      next_asset: 0,
+      assets: HashMap::new(),
+    }
+  }
+
+  pub fn create_movie(&mut self) -> MovieId {
+    let movie = MovieId(self.next_movie);
+    self.next_movie += 1;
+    movie
+  }
+
+  /// Returns the global id of a character of `movie`, allocating it on first use.
+  pub fn asset_id(&mut self, movie: MovieId, character_id: u16) -> usize {
+    let next_asset = &mut self.next_asset;
+    *self.assets.entry((movie, character_id)).or_insert_with(|| {
+      let id = *next_asset;
+      *next_asset += 1;
+      id
+    })
+  }
+
+  /// Forgets the characters of `movie` and returns their global ids.
+  pub fn release_movie(&mut self, movie: MovieId) -> Vec<usize> {
+    let mut released: Vec<usize> = Vec::new();
+    self.assets.retain(|(asset_movie, _), id| {
+      if *asset_movie == movie {
+        released.push(*id);
+        false
+      } else {
+        true
+      }
+    });
+    released.sort();
+    released
+  }
+}
+
+impl ::std::default::Default for AssetIds {
+  fn default() -> Self {
+    Self::new()
+  }
+}
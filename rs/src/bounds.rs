@@ -0,0 +1,231 @@
+use lyon::math::Point;
+use lyon::path::PathEvent;
+
+use crate::renderer::{GfxSymbol, ShapeStore};
+use crate::stage::{DisplayPrimitive, Stage};
+use crate::Shape;
+
+/// Width used for hairline strokes (width `0`), in twips.
+///
+/// Hairlines are drawn one pixel wide at any scale: this is their width at the original size of the movie.
+pub(crate) const HAIRLINE_WIDTH: f32 = 20.0;
+
+/// Represents an axis-aligned bounding box, in twips.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+  pub x_min: f32,
+  pub x_max: f32,
+  pub y_min: f32,
+  pub y_max: f32,
+}
+
+impl Bounds {
+  pub fn from_point(point: Point) -> Self {
+    Self {
+      x_min: point.x,
+      x_max: point.x,
+      y_min: point.y,
+      y_max: point.y,
+    }
+  }
+
+  pub fn width(&self) -> f32 {
+    self.x_max - self.x_min
+  }
+
+  pub fn height(&self) -> f32 {
+    self.y_max - self.y_min
+  }
+
+  /// Grows the bounds to include `point`.
+  pub fn extend(&mut self, point: Point) -> () {
+    self.x_min = f32::min(self.x_min, point.x);
+    self.x_max = f32::max(self.x_max, point.x);
+    self.y_min = f32::min(self.y_min, point.y);
+    self.y_max = f32::max(self.y_max, point.y);
+  }
+
+  /// Grows the bounds by `margin` in every direction.
+  pub fn inflate(&self, margin: f32) -> Self {
+    Self {
+      x_min: self.x_min - margin,
+      x_max: self.x_max + margin,
+      y_min: self.y_min - margin,
+      y_max: self.y_max + margin,
+    }
+  }
+
+  pub fn union(&self, other: &Bounds) -> Self {
+    Self {
+      x_min: f32::min(self.x_min, other.x_min),
+      x_max: f32::max(self.x_max, other.x_max),
+      y_min: f32::min(self.y_min, other.y_min),
+      y_max: f32::max(self.y_max, other.y_max),
+    }
+  }
+
+  /// Returns the overlapping area, or `None` if the bounds are disjoint.
+  pub fn intersection(&self, other: &Bounds) -> Option<Self> {
+    let result = Self {
+      x_min: f32::max(self.x_min, other.x_min),
+      x_max: f32::min(self.x_max, other.x_max),
+      y_min: f32::max(self.y_min, other.y_min),
+      y_max: f32::min(self.y_max, other.y_max),
+    };
+    if result.x_min <= result.x_max && result.y_min <= result.y_max {
+      Some(result)
+    } else {
+      None
+    }
+  }
+}
+
+impl From<swf_tree::Rect> for Bounds {
+  fn from(rect: swf_tree::Rect) -> Self {
+    Self {
+      x_min: rect.x_min as f32,
+      x_max: rect.x_max as f32,
+      y_min: rect.y_min as f32,
+      y_max: rect.y_max as f32,
+    }
+  }
+}
+
+/// Returns the tight bounds of a shape, in its local coordinates.
+///
+/// The bounds include the curve extrema and half of the stroke widths (`HAIRLINE_WIDTH` for hairlines).
+/// Returns `None` for empty shapes.
+pub fn shape_bounds(shape: &Shape) -> Option<Bounds> {
+  let mut result: Option<Bounds> = None;
+  for styled_path in shape.paths.iter() {
+    let mut path_bounds: Option<Bounds> = None;
+    {
+      let mut extend = |point: Point| match path_bounds {
+        Some(ref mut bounds) => bounds.extend(point),
+        None => path_bounds = Some(Bounds::from_point(point)),
+      };
+      for event in styled_path.path.iter() {
+        match event {
+          PathEvent::Begin { at } => extend(at),
+          PathEvent::Line { to, .. } => extend(to),
+          PathEvent::Quadratic { from, ctrl, to } => {
+            extend(to);
+            for t in quadratic_extrema(from, ctrl, to).iter().filter_map(|t| *t) {
+              let u = 1.0 - t;
+              extend(Point::new(
+                u * u * from.x + 2.0 * u * t * ctrl.x + t * t * to.x,
+                u * u * from.y + 2.0 * u * t * ctrl.y + t * t * to.y,
+              ));
+            }
+          }
+          PathEvent::Cubic { from, ctrl1, ctrl2, to } => {
+            extend(to);
+            for t in cubic_extrema(from, ctrl1, ctrl2, to).iter().filter_map(|t| *t) {
+              let u = 1.0 - t;
+              extend(Point::new(
+                u * u * u * from.x + 3.0 * u * u * t * ctrl1.x + 3.0 * u * t * t * ctrl2.x + t * t * t * to.x,
+                u * u * u * from.y + 3.0 * u * u * t * ctrl1.y + 3.0 * u * t * t * ctrl2.y + t * t * t * to.y,
+              ));
+            }
+          }
+          PathEvent::End { .. } => {}
+        }
+      }
+    }
+    let path_bounds = match (path_bounds, &styled_path.line) {
+      (Some(bounds), Some(ref line)) => Some(bounds.inflate(f32::max(f32::from(line.width), HAIRLINE_WIDTH) / 2.0)),
+      (path_bounds, _) => path_bounds,
+    };
+    result = match (result, path_bounds) {
+      (Some(result), Some(path_bounds)) => Some(result.union(&path_bounds)),
+      (result, path_bounds) => result.or(path_bounds),
+    };
+  }
+  result
+}
+
+/// Returns the bounds of a primitive in the coordinates of its parent, including its own matrix.
+///
+/// Morph shapes are not supported yet and have no bounds. Clip layers with such a mask use the bounds of
+/// their children.
+pub fn primitive_bounds(primitive: &DisplayPrimitive, shapes: &ShapeStore) -> Option<Bounds> {
+  match primitive {
+    DisplayPrimitive::Shape(ref shape) => match shapes.get(shape.id.0) {
+      Some(GfxSymbol::Shape(ref symbol)) => symbol.tight_bounds.map(|bounds| shape.matrix.transform_bounds(&bounds)),
+      _ => None,
+    },
+    DisplayPrimitive::MorphShape(_) => None,
+    DisplayPrimitive::Container(ref container) => {
+      list_bounds(&container.children, shapes).map(|bounds| container.matrix.transform_bounds(&bounds))
+    }
+    DisplayPrimitive::Button(ref button) => {
+      list_bounds(&button.children, shapes).map(|bounds| button.matrix.transform_bounds(&bounds))
+    }
+    DisplayPrimitive::Clip(ref clip) => {
+      let children_bounds = list_bounds(&clip.children, shapes)?;
+      match primitive_bounds(&clip.mask, shapes) {
+        Some(mask_bounds) => mask_bounds.intersection(&children_bounds),
+        // The area of the mask is unknown (e.g. a morph shape): it may reveal all the children
+        None => Some(children_bounds),
+      }
+    }
+  }
+}
+
+/// Returns the bounds of all the primitives of the stage, in stage coordinates.
+pub fn stage_bounds(stage: &Stage, shapes: &ShapeStore) -> Option<Bounds> {
+  list_bounds(&stage.display_root, shapes)
+}
+
+fn list_bounds(primitives: &[DisplayPrimitive], shapes: &ShapeStore) -> Option<Bounds> {
+  primitives
+    .iter()
+    .filter_map(|primitive| primitive_bounds(primitive, shapes))
+    .fold(None, |result: Option<Bounds>, bounds| match result {
+      Some(result) => Some(result.union(&bounds)),
+      None => Some(bounds),
+    })
+}
+
+/// Returns the parameters (in `(0, 1)`) where the derivative of a quadratic curve is zero on each axis.
+fn quadratic_extrema(from: Point, ctrl: Point, to: Point) -> [Option<f32>; 2] {
+  let solve = |p0: f32, p1: f32, p2: f32| {
+    let denominator = p0 - 2.0 * p1 + p2;
+    if denominator == 0.0 {
+      return None;
+    }
+    let t = (p0 - p1) / denominator;
+    if 0.0 < t && t < 1.0 {
+      Some(t)
+    } else {
+      None
+    }
+  };
+  [solve(from.x, ctrl.x, to.x), solve(from.y, ctrl.y, to.y)]
+}
+
+/// Returns the parameters (in `(0, 1)`) where the derivative of a cubic curve is zero on each axis.
+fn cubic_extrema(from: Point, ctrl1: Point, ctrl2: Point, to: Point) -> [Option<f32>; 4] {
+  let solve = |p0: f32, p1: f32, p2: f32, p3: f32| -> [Option<f32>; 2] {
+    // Derivative: `a * t^2 + b * t + c`
+    let a = 3.0 * (-p0 + 3.0 * p1 - 3.0 * p2 + p3);
+    let b = 6.0 * (p0 - 2.0 * p1 + p2);
+    let c = 3.0 * (p1 - p0);
+    let in_range = |t: f32| if 0.0 < t && t < 1.0 { Some(t) } else { None };
+    if a == 0.0 {
+      if b == 0.0 {
+        return [None, None];
+      }
+      return [in_range(-c / b), None];
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+      return [None, None];
+    }
+    let root = discriminant.sqrt();
+    [in_range((-b + root) / (2.0 * a)), in_range((-b - root) / (2.0 * a))]
+  };
+  let [x0, x1] = solve(from.x, ctrl1.x, ctrl2.x, to.x);
+  let [y0, y1] = solve(from.y, ctrl1.y, ctrl2.y, to.y);
+  [x0, x1, y0, y1]
+}
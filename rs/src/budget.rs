@@ -0,0 +1,121 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+/// Counters describing the GPU memory used by the renderer.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+  /// Number of resident resources
+  pub resident_count: usize,
+  /// Size of the resident resources, in bytes
+  pub resident_bytes: u64,
+  /// Number of uploaded resources, including the uploads following an eviction
+  pub uploads: u64,
+  /// Number of resources evicted to stay within the budget
+  pub evictions: u64,
+  /// Size of the evicted resources, in bytes
+  pub evicted_bytes: u64,
+}
+
+/// Tracks the resident GPU resources and selects the least recently drawn ones when they exceed a budget.
+///
+/// The resources drawn in the current frame are never evicted: the budget may be exceeded by a single frame
+/// using more memory than allowed.
+pub struct MemoryBudget<K> {
+  /// Maximum size of the resident resources, in bytes (`None` for no limit)
+  budget: Option<u64>,
+  /// Index of the current frame
+  frame: u64,
+  /// Size and last frame of use of each resident resource
+  resident: HashMap<K, (u64, u64)>,
+  stats: MemoryStats,
+}
+
+impl<K: Eq + Hash + Clone> MemoryBudget<K> {
+  pub fn new(budget: Option<u64>) -> Self {
+    Self {
+      budget,
+      frame: 0,
+      resident: HashMap::new(),
+      stats: MemoryStats::default(),
+    }
+  }
+
+  pub fn budget(&self) -> Option<u64> {
+    self.budget
+  }
+
+  /// Changes the budget, it applies at the next call to `evict`.
+  pub fn set_budget(&mut self, budget: Option<u64>) -> () {
+    self.budget = budget;
+  }
+
+  pub fn stats(&self) -> MemoryStats {
+    MemoryStats {
+      resident_count: self.resident.len(),
+      ..self.stats
+    }
+  }
+
+  /// Starts a new frame.
+  pub fn next_frame(&mut self) -> () {
+    self.frame += 1;
+  }
+
+  /// Records a resource uploaded for the current frame, replacing any previous resource with the same key.
+  pub fn insert(&mut self, key: K, size: u64) -> () {
+    if let Some((old_size, _)) = self.resident.insert(key, (size, self.frame)) {
+      self.stats.resident_bytes -= old_size;
+    }
+    self.stats.resident_bytes += size;
+    self.stats.uploads += 1;
+  }
+
+  /// Marks a resident resource as drawn in the current frame.
+  pub fn touch(&mut self, key: &K) -> () {
+    if let Some((_, last_used)) = self.resident.get_mut(key) {
+      *last_used = self.frame;
+    }
+  }
+
+  /// Forgets a resource released by the renderer, it is not counted as an eviction.
+  pub fn remove(&mut self, key: &K) -> () {
+    if let Some((size, _)) = self.resident.remove(key) {
+      self.stats.resident_bytes -= size;
+    }
+  }
+
+  /// Forgets all the resources.
+  pub fn clear(&mut self) -> () {
+    self.resident.clear();
+    self.stats.resident_bytes = 0;
+  }
+
+  /// Removes the least recently drawn resources until the resident size fits in the budget, and returns
+  /// their keys so the renderer frees them.
+  pub fn evict(&mut self) -> Vec<K> {
+    let budget = match self.budget {
+      Some(budget) if self.stats.resident_bytes > budget => budget,
+      _ => return Vec::new(),
+    };
+    let mut candidates: Vec<(u64, K)> = self
+      .resident
+      .iter()
+      .filter(|(_, (_, last_used))| *last_used < self.frame)
+      .map(|(key, (_, last_used))| (*last_used, key.clone()))
+      .collect();
+    candidates.sort_by_key(|(last_used, _)| *last_used);
+
+    let mut evicted: Vec<K> = Vec::new();
+    for (_, key) in candidates.into_iter() {
+      if self.stats.resident_bytes <= budget {
+        break;
+      }
+      let (size, _) = self.resident.remove(&key).unwrap();
+      self.stats.resident_bytes -= size;
+      self.stats.evictions += 1;
+      self.stats.evicted_bytes += size;
+      evicted.push(key);
+    }
+    evicted
+  }
+}
@@ -0,0 +1,88 @@
+use lyon::math::Point;
+use lyon::path::PathEvent;
+use lyon::tessellation::{BuffersBuilder, FillOptions, FillTessellator, FillVertex, TessellationError, VertexBuffers};
+
+use crate::hit_test::{contains_fill, flatten_chords};
+use crate::renderer::fill_color;
+use crate::swf_renderer::Vertex;
+use crate::Shape;
+
+/// Represents how the curves of the shapes are rendered.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CurveMode {
+  /// Flatten the curves on the CPU, with a tolerance depending on the zoom level.
+  Tessellated,
+  /// Evaluate the curves in the fragment shader (Loop-Blinn), the mesh does not depend on the zoom level.
+  Analytic,
+}
+
+/// Curve coordinates `(u, v, sign)` of the vertices of the interior mesh: always covered.
+pub const INTERIOR_CURVE: [f32; 3] = [0.0, 1.0, 1.0];
+
+/// Curve coordinates of the start, control and end points of a quadratic curve.
+///
+/// The curve is the zero set of `u^2 - v` and `sign` selects the covered side (negative values are covered).
+const HULL_CURVE: [[f32; 2]; 3] = [[0.0, 0.0], [0.5, 0.0], [1.0, 1.0]];
+
+/// Builds the mesh of a shape for the analytic curve mode.
+///
+/// The interior of each fill is tessellated with its curves replaced by straight lines, and each curve adds
+/// the triangle of its control points, where the fragment shader keeps the covered side of the curve.
+/// Convex curves use their chord in the interior and cover the area between the chord and the curve.
+/// Concave curves use their control point in the interior and cover the area between the curve and the
+/// control point.
+pub fn build_curve_mesh(shape: &Shape) -> Result<VertexBuffers<Vertex, u32>, TessellationError> {
+  let mut mesh: VertexBuffers<Vertex, u32> = VertexBuffers::new();
+  let mut tessellator = FillTessellator::new();
+  let options = FillOptions::default().with_fill_rule(shape.fill_rule);
+
+  for path in shape.paths.iter() {
+    let color = fill_color(path);
+    let chords = flatten_chords(&path.path);
+
+    let mut interior = lyon::path::Path::builder();
+    let mut hulls: Vec<([Point; 3], f32)> = Vec::new();
+    for event in path.path.iter() {
+      match event {
+        PathEvent::Begin { at } => interior.move_to(at),
+        PathEvent::Line { to, .. } => interior.line_to(to),
+        PathEvent::Quadratic { from, ctrl, to } => {
+          let middle = from.lerp(ctrl, 0.5).lerp(ctrl.lerp(to, 0.5), 0.5);
+          let is_concave = contains_fill(&chords, middle, shape.fill_rule);
+          if is_concave {
+            interior.line_to(ctrl);
+          }
+          interior.line_to(to);
+          hulls.push(([from, ctrl, to], if is_concave { -1.0 } else { 1.0 }));
+        }
+        // SWF shapes only contain quadratic curves
+        PathEvent::Cubic { to, .. } => interior.line_to(to),
+        PathEvent::End { .. } => {}
+      }
+    }
+
+    tessellator.tessellate_path(
+      &interior.build(),
+      &options,
+      &mut BuffersBuilder::new(&mut mesh, |vertex: FillVertex| Vertex {
+        position: [vertex.position.x, vertex.position.y, 0.0],
+        color,
+        curve: INTERIOR_CURVE,
+      }),
+    )?;
+
+    for (points, sign) in hulls.into_iter() {
+      let first = mesh.vertices.len() as u32;
+      for (point, [u, v]) in points.iter().zip(HULL_CURVE.iter()) {
+        mesh.vertices.push(Vertex {
+          position: [point.x, point.y, 0.0],
+          color,
+          curve: [*u, *v, sign],
+        });
+      }
+      mesh.indices.extend_from_slice(&[first, first + 1, first + 2]);
+    }
+  }
+
+  Ok(mesh)
+}
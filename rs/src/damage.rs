@@ -0,0 +1,175 @@
+//! Detection of the area changed between two consecutive stages, for incremental rendering.
+//!
+//! The primitives are matched by their position in the display tree: a primitive is unchanged if it has the
+//! same kind, asset id, matrix, color transform and morph ratio as the primitive at the same position in the
+//! previous stage.
+
+use crate::bounds::{primitive_bounds, Bounds};
+use crate::renderer::ShapeStore;
+use crate::stage::{DisplayPrimitive, Matrix2D, Stage};
+
+/// Area of the output to redraw for a new stage.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Damage {
+  /// The stage is unchanged, the previous frame can be reused
+  Unchanged,
+  /// Only the area inside the bounds changed, in stage coordinates (twips)
+  Region(Bounds),
+  /// The whole output must be redrawn
+  Full,
+}
+
+impl Damage {
+  pub fn union(self, other: Damage) -> Damage {
+    match (self, other) {
+      (Damage::Full, _) | (_, Damage::Full) => Damage::Full,
+      (Damage::Unchanged, damage) | (damage, Damage::Unchanged) => damage,
+      (Damage::Region(left), Damage::Region(right)) => Damage::Region(left.union(&right)),
+    }
+  }
+}
+
+/// Returns the area changed between `previous` and `next`.
+///
+/// The bounds of the shapes are read from `shapes`: redefining or releasing a displayed shape changes its
+/// content without changing the stage, the caller must redraw the whole output in this case.
+pub fn stage_damage(previous: &Stage, next: &Stage, shapes: &ShapeStore) -> Damage {
+  if previous.background_color != next.background_color {
+    return Damage::Full;
+  }
+  list_damage(&previous.display_root, &next.display_root, &Matrix2D::default(), shapes)
+}
+
+/// Returns the damage between two lists of primitives, `matrix` maps their coordinates to the stage.
+fn list_damage(
+  previous: &[DisplayPrimitive],
+  next: &[DisplayPrimitive],
+  matrix: &Matrix2D,
+  shapes: &ShapeStore,
+) -> Damage {
+  let mut damage = Damage::Unchanged;
+  for i in 0..usize::max(previous.len(), next.len()) {
+    damage = damage.union(match (previous.get(i), next.get(i)) {
+      (Some(previous), Some(next)) => primitive_damage(previous, next, matrix, shapes),
+      (Some(primitive), None) | (None, Some(primitive)) => covered_damage(primitive, matrix, shapes),
+      (None, None) => Damage::Unchanged,
+    });
+    if damage == Damage::Full {
+      break;
+    }
+  }
+  damage
+}
+
+fn primitive_damage(
+  previous: &DisplayPrimitive,
+  next: &DisplayPrimitive,
+  matrix: &Matrix2D,
+  shapes: &ShapeStore,
+) -> Damage {
+  match (previous, next) {
+    (DisplayPrimitive::Shape(ref previous_shape), DisplayPrimitive::Shape(ref next_shape))
+      if previous_shape.id == next_shape.id
+        && previous_shape.matrix == next_shape.matrix
+        && previous_shape.color_transform == next_shape.color_transform =>
+    {
+      Damage::Unchanged
+    }
+    (DisplayPrimitive::MorphShape(ref previous_shape), DisplayPrimitive::MorphShape(ref next_shape))
+      if previous_shape.id == next_shape.id
+        && previous_shape.matrix == next_shape.matrix
+        && previous_shape.color_transform == next_shape.color_transform
+        && previous_shape.ratio == next_shape.ratio =>
+    {
+      Damage::Unchanged
+    }
+    (DisplayPrimitive::Container(ref previous_container), DisplayPrimitive::Container(ref next_container))
+      if previous_container.matrix == next_container.matrix
+        && previous_container.color_transform == next_container.color_transform
+        && previous_container.blend_mode == next_container.blend_mode
+        && previous_container.filters == next_container.filters =>
+    {
+      let children_damage = list_damage(
+        &previous_container.children,
+        &next_container.children,
+        &(matrix * &next_container.matrix),
+        shapes,
+      );
+      match children_damage {
+        // The filters may spread the changes of the children outside of their bounds
+        Damage::Region(_) if !next_container.filters.is_empty() => Damage::Full,
+        damage => damage,
+      }
+    }
+    (DisplayPrimitive::Button(ref previous_button), DisplayPrimitive::Button(ref next_button))
+      if previous_button.matrix == next_button.matrix
+        && previous_button.color_transform == next_button.color_transform =>
+    {
+      // The hit area is never drawn
+      list_damage(
+        &previous_button.children,
+        &next_button.children,
+        &(matrix * &next_button.matrix),
+        shapes,
+      )
+    }
+    (DisplayPrimitive::Clip(ref previous_clip), DisplayPrimitive::Clip(ref next_clip))
+      if primitive_damage(&previous_clip.mask, &next_clip.mask, matrix, shapes) == Damage::Unchanged =>
+    {
+      list_damage(&previous_clip.children, &next_clip.children, matrix, shapes)
+    }
+    (previous, next) => covered_damage(previous, matrix, shapes).union(covered_damage(next, matrix, shapes)),
+  }
+}
+
+/// Returns the damage of adding or removing a primitive: the area it covers.
+fn covered_damage(primitive: &DisplayPrimitive, matrix: &Matrix2D, shapes: &ShapeStore) -> Damage {
+  if is_unbounded(primitive) {
+    return Damage::Full;
+  }
+  match drawn_bounds(primitive, shapes) {
+    Some(bounds) => Damage::Region(matrix.transform_bounds(&bounds)),
+    None => Damage::Unchanged,
+  }
+}
+
+/// Returns the bounds of the area drawn by a primitive, in the coordinates of its parent.
+///
+/// Unlike `primitive_bounds`, the children of the clip layers are not limited to their mask: the renderer draws
+/// them unclipped.
+fn drawn_bounds(primitive: &DisplayPrimitive, shapes: &ShapeStore) -> Option<Bounds> {
+  match primitive {
+    DisplayPrimitive::Container(ref container) => {
+      drawn_list_bounds(&container.children, shapes).map(|bounds| container.matrix.transform_bounds(&bounds))
+    }
+    DisplayPrimitive::Button(ref button) => {
+      drawn_list_bounds(&button.children, shapes).map(|bounds| button.matrix.transform_bounds(&bounds))
+    }
+    DisplayPrimitive::Clip(ref clip) => drawn_list_bounds(&clip.children, shapes),
+    primitive => primitive_bounds(primitive, shapes),
+  }
+}
+
+fn drawn_list_bounds(primitives: &[DisplayPrimitive], shapes: &ShapeStore) -> Option<Bounds> {
+  primitives
+    .iter()
+    .filter_map(|primitive| drawn_bounds(primitive, shapes))
+    .fold(None, |result: Option<Bounds>, bounds| match result {
+      Some(result) => Some(result.union(&bounds)),
+      None => Some(bounds),
+    })
+}
+
+/// Tests if the area covered by a primitive is unknown: morph shapes have no bounds yet, and filters draw
+/// outside of the bounds of their children.
+fn is_unbounded(primitive: &DisplayPrimitive) -> bool {
+  match primitive {
+    DisplayPrimitive::Shape(_) => false,
+    DisplayPrimitive::MorphShape(_) => true,
+    DisplayPrimitive::Container(ref container) => {
+      !container.filters.is_empty() || container.children.iter().any(is_unbounded)
+    }
+    DisplayPrimitive::Button(ref button) => button.children.iter().any(is_unbounded),
+    DisplayPrimitive::Clip(ref clip) => is_unbounded(&clip.mask) || clip.children.iter().any(is_unbounded),
+  }
+}
@@ -1,11 +1,18 @@
 use std::collections::vec_deque::VecDeque;
+use std::collections::HashMap;
+use std::fmt;
 
+use log::warn;
+use lyon::tessellation::FillRule;
 use swf_tree::shape_records::{Edge, StyleChange};
+use swf_tree::tags::DefineShape;
 use swf_tree::{FillStyle, LineStyle, Shape as SwfShape, ShapeRecord, ShapeStyles, Vector2D};
 
 #[derive(Debug, Clone)]
 pub struct Shape {
   pub paths: Vec<StyledPath>,
+  /// Rule used to fill the contours of each fill style
+  pub fill_rule: FillRule,
 }
 
 #[derive(Debug, Clone)]
@@ -15,8 +22,71 @@ pub struct StyledPath {
   pub line: Option<LineStyle>,
 }
 
-pub fn decode_shape(swf_shape: &SwfShape) -> Shape {
-  let mut decoder = ShapeDecoder::new(&swf_shape.initial_styles);
+/// Error found while decoding a malformed shape.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShapeDecodeError {
+  /// A style change selects a fill style (1-based) missing from the current styles
+  FillStyleOutOfRange { index: usize, count: usize },
+  /// A style change selects a line style (1-based) missing from the current styles
+  LineStyleOutOfRange { index: usize, count: usize },
+  /// A coordinate (in twips) cannot be represented exactly as an `f32`
+  PrecisionLoss(Vector2D),
+  /// The contours of a fill are not closed: the point starts or ends more edges than the other
+  UnclosedFill(Vector2D),
+}
+
+impl fmt::Display for ShapeDecodeError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ShapeDecodeError::FillStyleOutOfRange { index, count } => {
+        write!(f, "Fill style index {} out of range (style count: {})", index, count)
+      }
+      ShapeDecodeError::LineStyleOutOfRange { index, count } => {
+        write!(f, "Line style index {} out of range (style count: {})", index, count)
+      }
+      ShapeDecodeError::PrecisionLoss(point) => {
+        write!(f, "Coordinates ({}, {}) lose precision as f32", point.x, point.y)
+      }
+      ShapeDecodeError::UnclosedFill(point) => write!(f, "Unclosed fill contour at ({}, {})", point.x, point.y),
+    }
+  }
+}
+
+impl ::std::error::Error for ShapeDecodeError {}
+
+/// Defines how malformed shapes are handled.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DecodeMode {
+  /// Reject the shape on the first error.
+  Strict,
+  /// Mimic the Flash Player: ignore out-of-range styles, round coordinates to the nearest `f32` and
+  /// implicitly close the fills.
+  Lenient,
+}
+
+/// Decodes the shape of a `DefineShape` tag, using the fill rule from its flags.
+///
+/// Shapes use the even-odd rule, except `DefineShape4` shapes with the fill winding flag which use
+/// the non-zero rule.
+pub fn decode_define_shape(tag: &DefineShape, mode: DecodeMode) -> Result<Shape, ShapeDecodeError> {
+  let mut shape = decode_shape_with_mode(&tag.shape, mode)?;
+  if tag.has_fill_winding {
+    shape.fill_rule = FillRule::NonZero;
+  }
+  Ok(shape)
+}
+
+/// Decodes a shape, rejecting malformed shapes.
+pub fn decode_shape(swf_shape: &SwfShape) -> Result<Shape, ShapeDecodeError> {
+  decode_shape_with_mode(swf_shape, DecodeMode::Strict)
+}
+
+/// Decodes a shape, handling malformed shapes according to `mode`.
+///
+/// The lenient mode never returns an error.
+/// The shape uses the even-odd fill rule.
+pub fn decode_shape_with_mode(swf_shape: &SwfShape, mode: DecodeMode) -> Result<Shape, ShapeDecodeError> {
+  let mut decoder = ShapeDecoder::new(&swf_shape.initial_styles, mode);
 
   for record in swf_shape.records.iter() {
     match record {
@@ -24,7 +94,7 @@ pub fn decode_shape(swf_shape: &SwfShape) -> Shape {
         decoder.apply_edge(record);
       }
       ShapeRecord::StyleChange(ref record) => {
-        decoder.apply_style_change(record);
+        decoder.apply_style_change(record)?;
       }
     }
   }
@@ -32,14 +102,28 @@ pub fn decode_shape(swf_shape: &SwfShape) -> Shape {
   decoder.get_shape()
 }
 
-fn vec_to_point(vec: Vector2D) -> Option<lyon::math::Point> {
-  // TODO: Catch precision errors and return `None`.
+/// Returns the error in strict mode, or logs it and continues in lenient mode.
+fn tolerate(mode: DecodeMode, result: Result<(), ShapeDecodeError>) -> Result<(), ShapeDecodeError> {
+  match (mode, result) {
+    (DecodeMode::Lenient, Err(e)) => {
+      warn!("Ignoring malformed shape: {}", e);
+      Ok(())
+    }
+    (_, result) => result,
+  }
+}
+
+fn vec_to_point(vec: Vector2D, mode: DecodeMode) -> Result<lyon::math::Point, ShapeDecodeError> {
   let x: f32 = vec.x as f32;
   let y: f32 = vec.y as f32;
-  Some(lyon::math::Point::new(x, y))
+  // `f32` represents every integer up to `2^24` exactly: bigger coordinates may be rounded.
+  if (x as i64) != i64::from(vec.x) || (y as i64) != i64::from(vec.y) {
+    tolerate(mode, Err(ShapeDecodeError::PrecisionLoss(vec)))?;
+  }
+  Ok(lyon::math::Point::new(x, y))
 }
 
-fn segments_to_path(mut open_set: VecDeque<Segment>) -> lyon::path::Path {
+fn segments_to_path(mut open_set: VecDeque<Segment>, mode: DecodeMode) -> Result<lyon::path::Path, ShapeDecodeError> {
   let mut builder = lyon::path::Path::builder();
   while open_set.len() > 0 {
     let (next_open_set, continuous) = extract_continuous(open_set);
@@ -47,13 +131,37 @@ fn segments_to_path(mut open_set: VecDeque<Segment>) -> lyon::path::Path {
     let mut first: bool = true;
     for segment in continuous.into_iter() {
       if first {
-        builder.move_to(vec_to_point(segment.start).unwrap());
+        builder.move_to(vec_to_point(segment.start, mode)?);
         first = false;
       }
-      builder.line_to(vec_to_point(segment.end).unwrap());
+      match segment.control {
+        Some(control) => {
+          builder.quadratic_bezier_to(vec_to_point(control, mode)?, vec_to_point(segment.end, mode)?);
+        }
+        None => {
+          builder.line_to(vec_to_point(segment.end, mode)?);
+        }
+      }
     }
   }
-  builder.build()
+  Ok(builder.build())
+}
+
+/// Checks that the segments of a fill form closed contours.
+///
+/// The contours are closed if every point starts as many segments as it ends.
+fn check_closed(segments: &VecDeque<Segment>) -> Result<(), ShapeDecodeError> {
+  let mut balances: HashMap<(i32, i32), i32> = HashMap::new();
+  for segment in segments.iter() {
+    *balances.entry((segment.start.x, segment.start.y)).or_insert(0) += 1;
+    *balances.entry((segment.end.x, segment.end.y)).or_insert(0) -= 1;
+  }
+  for segment in segments.iter() {
+    if balances[&(segment.start.x, segment.start.y)] != 0 {
+      return Err(ShapeDecodeError::UnclosedFill(segment.start));
+    }
+  }
+  Ok(())
 }
 
 fn extract_continuous(mut open_set: VecDeque<Segment>) -> (VecDeque<Segment>, VecDeque<Segment>) {
@@ -85,14 +193,16 @@ const fn add_vec2(left: Vector2D, right: Vector2D) -> Vector2D {
 }
 
 struct ShapeDecoder {
+  mode: DecodeMode,
   layers: Vec<StyleLayer>,
   top_layer: StyleLayerBuilder,
   pos: Vector2D,
 }
 
 impl ShapeDecoder {
-  pub fn new(styles: &ShapeStyles) -> Self {
+  pub fn new(styles: &ShapeStyles, mode: DecodeMode) -> Self {
     Self {
+      mode,
       layers: Vec::new(),
       top_layer: StyleLayerBuilder::new(styles),
       pos: Vector2D { x: 0, y: 0 },
@@ -106,25 +216,27 @@ impl ShapeDecoder {
     self.pos = end;
   }
 
-  pub fn apply_style_change(&mut self, record: &StyleChange) -> () {
+  pub fn apply_style_change(&mut self, record: &StyleChange) -> Result<(), ShapeDecodeError> {
     if let Some(ref new_styles) = record.new_styles {
       self.set_new_styles(new_styles);
     }
     if let Some(left_fill) = record.left_fill {
-      self.top_layer.set_left_fill(left_fill);
+      tolerate(self.mode, self.top_layer.set_left_fill(left_fill))?;
     }
     if let Some(right_fill) = record.right_fill {
-      self.top_layer.set_right_fill(right_fill);
+      tolerate(self.mode, self.top_layer.set_right_fill(right_fill))?;
     }
     if let Some(line_fill) = record.line_style {
-      self.top_layer.set_line_fill(line_fill);
+      tolerate(self.mode, self.top_layer.set_line_fill(line_fill))?;
     }
     if let Some(move_to) = record.move_to {
       self.pos = move_to;
     }
+    Ok(())
   }
 
-  pub fn get_shape(self) -> Shape {
+  pub fn get_shape(self) -> Result<Shape, ShapeDecodeError> {
+    let mode = self.mode;
     let (top_layer, mut layers) = (self.top_layer, self.layers);
     layers.push(top_layer.build());
     let mut paths: Vec<StyledPath> = Vec::new();
@@ -135,7 +247,8 @@ impl ShapeDecoder {
           continue;
         }
         let (style, segments) = (segment_set.style, segment_set.segments);
-        let path = segments_to_path(segments);
+        tolerate(mode, check_closed(&segments))?;
+        let path = segments_to_path(segments, mode)?;
         paths.push(StyledPath {
           path,
           fill: Some(style),
@@ -147,7 +260,7 @@ impl ShapeDecoder {
           continue;
         }
         let (style, segments) = (segment_set.style, segment_set.segments);
-        let path = segments_to_path(segments);
+        let path = segments_to_path(segments, mode)?;
         paths.push(StyledPath {
           path,
           fill: None,
@@ -155,7 +268,10 @@ impl ShapeDecoder {
         });
       }
     }
-    Shape { paths }
+    Ok(Shape {
+      paths,
+      fill_rule: FillRule::EvenOdd,
+    })
   }
 
   fn set_new_styles(&mut self, styles: &ShapeStyles) -> () {
@@ -225,19 +341,36 @@ impl StyleLayerBuilder {
     }
   }
 
-  pub fn set_left_fill(&mut self, id: usize) -> () {
-    debug_assert!(id < self.fills.len() + 1);
+  /// Sets the fill style on the left of the next edges (`0` for none).
+  ///
+  /// Out-of-range styles are replaced by `0`.
+  pub fn set_left_fill(&mut self, id: usize) -> Result<(), ShapeDecodeError> {
+    let (id, result) = check_style_index(id, self.fills.len(), false);
     self.left_fill = id;
+    result
   }
 
-  pub fn set_right_fill(&mut self, id: usize) -> () {
-    debug_assert!(id < self.fills.len() + 1);
+  pub fn set_right_fill(&mut self, id: usize) -> Result<(), ShapeDecodeError> {
+    let (id, result) = check_style_index(id, self.fills.len(), false);
     self.right_fill = id;
+    result
   }
 
-  pub fn set_line_fill(&mut self, id: usize) -> () {
-    debug_assert!(id < self.lines.len() + 1);
+  pub fn set_line_fill(&mut self, id: usize) -> Result<(), ShapeDecodeError> {
+    let (id, result) = check_style_index(id, self.lines.len(), true);
     self.line_fill = id;
+    result
+  }
+}
+
+/// Returns the style index to use (`0` if out of range), with the corresponding error.
+fn check_style_index(index: usize, count: usize, is_line: bool) -> (usize, Result<(), ShapeDecodeError>) {
+  if index <= count {
+    (index, Ok(()))
+  } else if is_line {
+    (0, Err(ShapeDecodeError::LineStyleOutOfRange { index, count }))
+  } else {
+    (0, Err(ShapeDecodeError::FillStyleOutOfRange { index, count }))
   }
 }
 
@@ -0,0 +1,91 @@
+use std::fmt;
+
+use lyon::tessellation::TessellationError;
+
+use crate::protocol::ProtocolError;
+use crate::ShapeDecodeError;
+
+/// Error returned by the renderers.
+///
+/// Except for `DeviceLost`, the renderer remains usable after an error.
+#[derive(Debug)]
+pub enum RendererError {
+  /// No adapter (or queue family, or format) satisfies the requirements of the renderer
+  AdapterNotFound,
+  /// The host or device memory is exhausted
+  OutOfMemory,
+  /// The asset id is not defined in the store
+  UnknownAsset(usize),
+  /// The shape definition is malformed
+  ShapeDecode(ShapeDecodeError),
+  /// Failed to tessellate a shape
+  Tessellation(TessellationError),
+  /// The logical device was lost: the renderer must be recreated
+  DeviceLost,
+  /// Nothing to render: the stage is not set
+  MissingStage,
+  /// Any other failure of the graphics API
+  Gfx(&'static str),
+  /// Failed to communicate with the render server
+  Protocol(ProtocolError),
+  /// The render server failed to execute a command
+  Remote(String),
+}
+
+impl fmt::Display for RendererError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      RendererError::AdapterNotFound => write!(f, "Failed to find a compatible GPU adapter"),
+      RendererError::OutOfMemory => write!(f, "Out of memory"),
+      RendererError::UnknownAsset(id) => write!(f, "Unknown asset id: {}", id),
+      RendererError::ShapeDecode(e) => write!(f, "Failed to decode shape: {}", e),
+      RendererError::Tessellation(e) => write!(f, "Failed to tessellate shape: {:?}", e),
+      RendererError::DeviceLost => write!(f, "Device lost"),
+      RendererError::MissingStage => write!(f, "Failed to render: stage is not set"),
+      RendererError::Gfx(message) => write!(f, "{}", message),
+      RendererError::Protocol(e) => write!(f, "Render protocol error: {}", e),
+      RendererError::Remote(message) => write!(f, "Render server error: {}", message),
+    }
+  }
+}
+
+impl ::std::error::Error for RendererError {}
+
+impl From<gfx_hal::device::OutOfMemory> for RendererError {
+  fn from(_: gfx_hal::device::OutOfMemory) -> Self {
+    RendererError::OutOfMemory
+  }
+}
+
+impl From<gfx_hal::device::DeviceLost> for RendererError {
+  fn from(_: gfx_hal::device::DeviceLost) -> Self {
+    RendererError::DeviceLost
+  }
+}
+
+impl From<gfx_hal::device::OomOrDeviceLost> for RendererError {
+  fn from(e: gfx_hal::device::OomOrDeviceLost) -> Self {
+    match e {
+      gfx_hal::device::OomOrDeviceLost::OutOfMemory(_) => RendererError::OutOfMemory,
+      gfx_hal::device::OomOrDeviceLost::DeviceLost(_) => RendererError::DeviceLost,
+    }
+  }
+}
+
+impl From<TessellationError> for RendererError {
+  fn from(e: TessellationError) -> Self {
+    RendererError::Tessellation(e)
+  }
+}
+
+impl From<ShapeDecodeError> for RendererError {
+  fn from(e: ShapeDecodeError) -> Self {
+    RendererError::ShapeDecode(e)
+  }
+}
+
+impl From<ProtocolError> for RendererError {
+  fn from(e: ProtocolError) -> Self {
+    RendererError::Protocol(e)
+  }
+}
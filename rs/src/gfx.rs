@@ -12,6 +12,8 @@ macro_rules! offset_of {
   }};
 }
 
+use crate::error::RendererError;
+
 pub struct AttachedBuffer<B: gfx_hal::Backend> {
   /// Buffer attached to memory
   pub buffer: B::Buffer,
@@ -29,28 +31,35 @@ pub unsafe fn create_buffer<B: gfx_hal::Backend>(
   memory_properties: gfx_hal::memory::Properties,
   size: u64,
   memories: &gfx_hal::adapter::MemoryProperties,
-) -> Result<AttachedBuffer<B>, &'static str> {
+) -> Result<AttachedBuffer<B>, RendererError> {
   use gfx_hal::device::Device;
 
-  let mut buffer = device
-    .create_buffer(size, usage)
-    .map_err(|_| "Failed to create buffer")?;
+  let mut buffer = device.create_buffer(size, usage).map_err(|e| match e {
+    gfx_hal::buffer::CreationError::OutOfMemory(_) => RendererError::OutOfMemory,
+    _ => RendererError::Gfx("Failed to create buffer"),
+  })?;
 
   let requirements: gfx_hal::memory::Requirements = device.get_buffer_requirements(&buffer);
 
   let mem_type: gfx_hal::MemoryTypeId =
-    get_memory_type_id(&memories.memory_types, memory_properties, requirements.type_mask);
+    match get_memory_type_id(&memories.memory_types, memory_properties, requirements.type_mask) {
+      Some(mem_type) => mem_type,
+      None => {
+        device.destroy_buffer(buffer);
+        return Err(RendererError::Gfx("Failed to find compatible memory type"));
+      }
+    };
 
   match device.allocate_memory(mem_type, requirements.size) {
-    Err(_) => {
+    Err(e) => {
       device.destroy_buffer(buffer);
-      Err("Failed to allocate buffer memory")
+      Err(allocation_error(e, "Failed to allocate buffer memory"))
     }
     Ok(memory) => match device.bind_buffer_memory(&memory, 0, &mut buffer) {
-      Err(_) => {
+      Err(e) => {
         device.free_memory(memory);
         device.destroy_buffer(buffer);
-        Err("Failed to bind buffer to memory")
+        Err(bind_error(e, "Failed to bind buffer to memory"))
       }
       Ok(_) => Ok(AttachedBuffer {
         buffer,
@@ -86,27 +95,36 @@ pub unsafe fn create_image<B: gfx_hal::Backend>(
   view_caps: ::gfx_hal::image::ViewCapabilities,
   memory_properties: gfx_hal::memory::Properties,
   memories: &gfx_hal::adapter::MemoryProperties,
-) -> Result<AttachedImage<B>, &'static str> {
+) -> Result<AttachedImage<B>, RendererError> {
   use gfx_hal::device::Device;
 
   let mut image = device
     .create_image(kind, mip_levels, format, tiling, usage, view_caps)
-    .map_err(|_| "Failed to create image")?;
+    .map_err(|e| match e {
+      gfx_hal::image::CreationError::OutOfMemory(_) => RendererError::OutOfMemory,
+      _ => RendererError::Gfx("Failed to create image"),
+    })?;
 
   let image_requirements = device.get_image_requirements(&image);
   let image_memory_type_id =
-    get_memory_type_id(&memories.memory_types, memory_properties, image_requirements.type_mask);
+    match get_memory_type_id(&memories.memory_types, memory_properties, image_requirements.type_mask) {
+      Some(mem_type) => mem_type,
+      None => {
+        device.destroy_image(image);
+        return Err(RendererError::Gfx("Failed to find compatible memory type"));
+      }
+    };
 
   match device.allocate_memory(image_memory_type_id, image_requirements.size) {
-    Err(_) => {
+    Err(e) => {
       device.destroy_image(image);
-      Err("Failed to allocate image memory")
+      Err(allocation_error(e, "Failed to allocate image memory"))
     }
     Ok(memory) => match device.bind_image_memory(&memory, 0, &mut image) {
-      Err(_) => {
+      Err(e) => {
         device.free_memory(memory);
         device.destroy_image(image);
-        Err("Failed to bind image to memory")
+        Err(bind_error(e, "Failed to bind image to memory"))
       }
       Ok(_) => Ok(AttachedImage { image, memory }),
     },
@@ -150,7 +168,7 @@ pub fn get_memory_type_id(
   memory_types: &[gfx_hal::adapter::MemoryType],
   memory_properties: gfx_hal::memory::Properties,
   mem_type_mask: u64,
-) -> gfx_hal::MemoryTypeId {
+) -> Option<gfx_hal::MemoryTypeId> {
   memory_types
     .into_iter()
     .enumerate()
@@ -159,8 +177,28 @@ pub fn get_memory_type_id(
       // the corresponding `id`.
       (mem_type_mask & (1 << id) != 0) & &memory_type.properties.contains(memory_properties)
     })
-    .expect("Failed to find compatible memory type")
-    .into()
+    .map(|id| id.into())
+}
+
+fn allocation_error(error: gfx_hal::device::AllocationError, message: &'static str) -> RendererError {
+  match error {
+    gfx_hal::device::AllocationError::OutOfMemory(_) => RendererError::OutOfMemory,
+    _ => RendererError::Gfx(message),
+  }
+}
+
+fn bind_error(error: gfx_hal::device::BindError, message: &'static str) -> RendererError {
+  match error {
+    gfx_hal::device::BindError::OutOfMemory(_) => RendererError::OutOfMemory,
+    _ => RendererError::Gfx(message),
+  }
+}
+
+fn view_error(error: gfx_hal::image::ViewError, message: &'static str) -> RendererError {
+  match error {
+    gfx_hal::image::ViewError::OutOfMemory(_) => RendererError::OutOfMemory,
+    _ => RendererError::Gfx(message),
+  }
 }
 
 /// Creates the images backing the framebuffer
@@ -170,7 +208,7 @@ pub unsafe fn create_images<B: gfx_hal::Backend>(
   color_format: gfx_hal::format::Format,
   depth_format: gfx_hal::format::Format,
   memories: &gfx_hal::adapter::MemoryProperties,
-) -> Result<((AttachedImage<B>, B::ImageView), (AttachedImage<B>, B::ImageView)), &'static str> {
+) -> Result<((AttachedImage<B>, B::ImageView), (AttachedImage<B>, B::ImageView)), RendererError> {
   use gfx_hal::device::Device;
 
   let color_image = create_image::<B>(
@@ -183,8 +221,7 @@ pub unsafe fn create_images<B: gfx_hal::Backend>(
     gfx_hal::image::ViewCapabilities::empty(),
     gfx_hal::memory::Properties::DEVICE_LOCAL,
     memories,
-  )
-  .map_err(|_| "Failed to create color image")?;
+  )?;
 
   let color_image_view = device.create_image_view(
     &color_image.image,
@@ -199,9 +236,9 @@ pub unsafe fn create_images<B: gfx_hal::Backend>(
   );
 
   match color_image_view {
-    Err(_) => {
+    Err(e) => {
       destroy_image(device, color_image);
-      Err("Failed to create color image view")
+      Err(view_error(e, "Failed to create color image view"))
     }
     Ok(color_image_view) => {
       let depth_image = create_image::<B>(
@@ -217,10 +254,10 @@ pub unsafe fn create_images<B: gfx_hal::Backend>(
       );
 
       match depth_image {
-        Err(_) => {
+        Err(e) => {
           device.destroy_image_view(color_image_view);
           destroy_image(device, color_image);
-          Err("Failed to create depth image")
+          Err(e)
         }
         Ok(depth_image) => {
           let depth_image_view = device.create_image_view(
@@ -236,11 +273,11 @@ pub unsafe fn create_images<B: gfx_hal::Backend>(
           );
 
           match depth_image_view {
-            Err(_) => {
+            Err(e) => {
               destroy_image(device, depth_image);
               device.destroy_image_view(color_image_view);
               destroy_image(device, color_image);
-              Err("Failed to create depth image view")
+              Err(view_error(e, "Failed to create depth image view"))
             }
             Ok(depth_image_view) => Ok(((color_image, color_image_view), (depth_image, depth_image_view))),
           }
@@ -249,3 +286,19 @@ pub unsafe fn create_images<B: gfx_hal::Backend>(
     }
   }
 }
+
+/// Destroys the images backing the framebuffer, created by `create_images`
+pub unsafe fn destroy_images<B: gfx_hal::Backend>(
+  device: &B::Device,
+  color: (AttachedImage<B>, B::ImageView),
+  depth: (AttachedImage<B>, B::ImageView),
+) -> () {
+  use gfx_hal::device::Device;
+
+  let (depth_image, depth_image_view) = depth;
+  device.destroy_image_view(depth_image_view);
+  destroy_image(device, depth_image);
+  let (color_image, color_image_view) = color;
+  device.destroy_image_view(color_image_view);
+  destroy_image(device, color_image);
+}
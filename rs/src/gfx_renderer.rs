@@ -1,8 +1,10 @@
 #![allow(dead_code)]
 
-use crate::asset::{ClientAssetStore, MorphShapeId, ShapeId};
+use crate::asset::{AssetIds, ClientAssetStore, MorphShapeId, MovieId, ShapeId};
+use crate::error::RendererError;
 use crate::stage::Stage;
 use crate::swf_renderer::SwfRenderer;
+use crate::viewport::Viewport as StageViewport;
 use core::iter;
 use gfx_hal::adapter::{Adapter, Gpu, PhysicalDevice};
 use gfx_hal::command;
@@ -19,7 +21,7 @@ use gfx_hal::pso;
 use gfx_hal::pso::{PipelineStage, Rect, Viewport};
 use gfx_hal::queue::family::QueueFamily;
 use gfx_hal::queue::{CommandQueue, QueueGroup, Submission};
-use gfx_hal::window::PresentationSurface;
+use gfx_hal::window::{AcquireError, CreationError as SwapchainCreationError, PresentError, PresentationSurface};
 use gfx_hal::window::{Extent2D, PresentMode, SwapImageIndex};
 use gfx_hal::window::{Surface, SwapchainConfig};
 use gfx_hal::Backend;
@@ -47,16 +49,24 @@ struct FrameState<B: Backend> {
 
 pub struct GfxRenderer<B: Backend> {
   pub stage: Option<Stage>,
+  pub viewport: StageViewport,
 
+  adapter: Adapter<B>,
   pub device: B::Device,
   pub queue_group: QueueGroup<B>,
   pub surface: B::Surface,
   swapchain: SwapchainState,
+  // Set when the swapchain no longer matches the surface and must be rebuilt before the next frame
+  swapchain_outdated: bool,
+  // Size requested by the last resize, used to rebuild the swapchain (zero while minimized)
+  requested_extent: Extent2D,
   frames: Vec<FrameState<B>>,
 
   pub memories: gfx_hal::adapter::MemoryProperties,
 
   pub render_pass: ManuallyDrop<B::RenderPass>,
+  // Global ids of the assets of each movie
+  asset_ids: AssetIds,
   // Current frame count
   pub frame: u64,
 }
@@ -87,7 +97,8 @@ unsafe fn create_swapchain<B: Backend>(
   device: &B::Device,
   physical_device: &B::PhysicalDevice,
   surface: &mut B::Surface,
-) -> SwapchainState {
+  preferred_extent: Extent2D,
+) -> Result<SwapchainState, RendererError> {
   let caps = surface.capabilities(physical_device);
   let formats = surface.supported_formats(physical_device);
 
@@ -99,7 +110,17 @@ unsafe fn create_swapchain<B: Backend>(
       .unwrap_or(formats[0])
   });
 
-  let extent: Extent2D = caps.current_extent.unwrap_or(DEFAULT_EXTENT);
+  // The surface may impose its extent, otherwise use the preferred extent within the supported bounds
+  let extent: Extent2D = caps.current_extent.unwrap_or(Extent2D {
+    width: preferred_extent
+      .width
+      .max(caps.extents.start().width)
+      .min(caps.extents.end().width),
+    height: preferred_extent
+      .height
+      .max(caps.extents.start().height)
+      .min(caps.extents.end().height),
+  });
 
   let config = SwapchainConfig::from_caps(&caps, format, extent);
   debug!("{:?}", config);
@@ -114,15 +135,87 @@ unsafe fn create_swapchain<B: Backend>(
     SwapImageIndex::max(*caps.image_count.start(), preferred_frames_in_flight),
   );
 
-  surface
-    .configure_swapchain(&device, config)
-    .expect("Failed to configure swapchain");
+  surface.configure_swapchain(&device, config).map_err(|e| match e {
+    SwapchainCreationError::OutOfMemory(_) => RendererError::OutOfMemory,
+    SwapchainCreationError::DeviceLost(_) => RendererError::DeviceLost,
+    _ => RendererError::Gfx("Failed to configure swapchain"),
+  })?;
 
-  SwapchainState {
+  Ok(SwapchainState {
     format,
     frames_in_flight,
     extent,
-  }
+  })
+}
+
+/// Creates the render pass drawing to the swapchain images, of the provided format.
+unsafe fn create_render_pass<B: Backend>(device: &B::Device, format: Format) -> Result<B::RenderPass, RendererError> {
+  let attachment: pass::Attachment = pass::Attachment {
+    format: Some(format),
+    samples: 1,
+    ops: pass::AttachmentOps {
+      load: pass::AttachmentLoadOp::Clear,
+      store: pass::AttachmentStoreOp::Store,
+    },
+    stencil_ops: pass::AttachmentOps::DONT_CARE,
+    layouts: Layout::Undefined..Layout::Present,
+  };
+  let attachments = [attachment];
+
+  let subpass: pass::SubpassDesc = pass::SubpassDesc {
+    colors: &[(0, Layout::ColorAttachmentOptimal)],
+    depth_stencil: None,
+    inputs: &[],
+    resolves: &[],
+    preserves: &[],
+  };
+
+  let dependencies = [pass::SubpassDependency {
+    passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
+    stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+    accesses: ImageAccess::empty()..(ImageAccess::COLOR_ATTACHMENT_READ | ImageAccess::COLOR_ATTACHMENT_WRITE),
+    flags: MemDependencies::empty(),
+  }];
+
+  device
+    .create_render_pass(&attachments, &[subpass], &dependencies)
+    .map_err(RendererError::from)
+}
+
+unsafe fn create_frame<B: Backend>(
+  device: &B::Device,
+  family: gfx_hal::queue::QueueFamilyId,
+) -> Result<FrameState<B>, RendererError> {
+  let submission_complete_semaphore: B::Semaphore = device.create_semaphore()?;
+  let submission_complete_fence: B::Fence = match device.create_fence(true) {
+    Ok(fence) => fence,
+    Err(e) => {
+      device.destroy_semaphore(submission_complete_semaphore);
+      return Err(RendererError::from(e));
+    }
+  };
+  let mut command_pool: B::CommandPool =
+    match device.create_command_pool(family, gfx_hal::pool::CommandPoolCreateFlags::RESET_INDIVIDUAL) {
+      Ok(command_pool) => command_pool,
+      Err(e) => {
+        device.destroy_fence(submission_complete_fence);
+        device.destroy_semaphore(submission_complete_semaphore);
+        return Err(RendererError::from(e));
+      }
+    };
+  let command_buffer: B::CommandBuffer = command_pool.allocate_one(command::Level::Primary);
+  Ok(FrameState {
+    submission_complete_semaphore,
+    submission_complete_fence,
+    command_pool,
+    command_buffer,
+  })
+}
+
+unsafe fn destroy_frame<B: Backend>(device: &B::Device, frame: FrameState<B>) -> () {
+  device.destroy_command_pool(frame.command_pool);
+  device.destroy_fence(frame.submission_complete_fence);
+  device.destroy_semaphore(frame.submission_complete_semaphore);
 }
 
 impl<B: Backend> GfxRenderer<B> {
@@ -133,230 +226,348 @@ impl<B: Backend> GfxRenderer<B> {
       .find(|a| find_graphics_queue_family::<B>(a, surface).is_some())
   }
 
-  pub fn new(adapter: Adapter<B>, mut surface: B::Surface) -> GfxRenderer<B> {
+  pub fn new(adapter: Adapter<B>, mut surface: B::Surface) -> Result<GfxRenderer<B>, RendererError> {
     let memories = adapter.physical_device.memory_properties();
     debug!("{:?}", memories);
     let limits = adapter.physical_device.limits();
     debug!("{:?}", limits);
 
     let family: &B::QueueFamily =
-      find_graphics_queue_family(&adapter, &surface).expect("Failed to find queue family with graphics support");
+      find_graphics_queue_family(&adapter, &surface).ok_or(RendererError::AdapterNotFound)?;
 
     let gpu: Gpu<B> = unsafe {
       adapter
         .physical_device
         .open(&[(family, &[1.0])], gfx_hal::Features::empty())
-        .expect("Failed to open GPU")
+        .map_err(|e| match e {
+          gfx_hal::device::CreationError::OutOfMemory(_) => RendererError::OutOfMemory,
+          gfx_hal::device::CreationError::DeviceLost => RendererError::DeviceLost,
+          _ => RendererError::Gfx("Failed to open GPU"),
+        })?
     };
     let device: B::Device = gpu.device;
     let mut queue_groups: Vec<QueueGroup<B>> = gpu.queue_groups;
-    let queue_group: QueueGroup<B> = queue_groups.pop().unwrap();
+    let queue_group: QueueGroup<B> = queue_groups
+      .pop()
+      .ok_or(RendererError::Gfx("Failed to open queue group"))?;
 
-    let swapchain: SwapchainState = unsafe { create_swapchain::<B>(&device, &adapter.physical_device, &mut surface) };
+    let swapchain: SwapchainState =
+      unsafe { create_swapchain::<B>(&device, &adapter.physical_device, &mut surface, DEFAULT_EXTENT)? };
 
     let mut frames: Vec<FrameState<B>> = Vec::with_capacity(usize::try_from(swapchain.frames_in_flight).unwrap());
     for _ in 0..swapchain.frames_in_flight {
-      let submission_complete_semaphore: B::Semaphore = device.create_semaphore().expect("Failed to create semaphore");
-      let submission_complete_fence: B::Fence = device.create_fence(true).expect("Failed to create fence");
-      let mut command_pool: B::CommandPool = unsafe {
-        device
-          .create_command_pool(
-            queue_group.family,
-            gfx_hal::pool::CommandPoolCreateFlags::RESET_INDIVIDUAL,
-          )
-          .expect("Failed to create command pool")
-      };
-      let command_buffer: B::CommandBuffer = unsafe { command_pool.allocate_one(command::Level::Primary) };
-      frames.push(FrameState {
-        submission_complete_semaphore,
-        submission_complete_fence,
-        command_pool,
-        command_buffer,
-      });
+      match unsafe { create_frame::<B>(&device, queue_group.family) } {
+        Ok(frame) => frames.push(frame),
+        Err(e) => {
+          for frame in frames.drain(..) {
+            unsafe { destroy_frame::<B>(&device, frame) };
+          }
+          return Err(e);
+        }
+      }
     }
 
-    let render_pass: B::RenderPass = unsafe {
-      let attachment: pass::Attachment = pass::Attachment {
-        format: Some(swapchain.format),
-        samples: 1,
-        ops: pass::AttachmentOps {
-          load: pass::AttachmentLoadOp::Clear,
-          store: pass::AttachmentStoreOp::Store,
-        },
-        stencil_ops: pass::AttachmentOps::DONT_CARE,
-        layouts: Layout::Undefined..Layout::Present,
-      };
-      let attachments = [attachment];
-
-      let subpass: pass::SubpassDesc = pass::SubpassDesc {
-        colors: &[(0, Layout::ColorAttachmentOptimal)],
-        depth_stencil: None,
-        inputs: &[],
-        resolves: &[],
-        preserves: &[],
-      };
-
-      let dependencies = [pass::SubpassDependency {
-        passes: pass::SubpassRef::External..pass::SubpassRef::Pass(0),
-        stages: PipelineStage::COLOR_ATTACHMENT_OUTPUT..PipelineStage::COLOR_ATTACHMENT_OUTPUT,
-        accesses: ImageAccess::empty()..(ImageAccess::COLOR_ATTACHMENT_READ | ImageAccess::COLOR_ATTACHMENT_WRITE),
-        flags: MemDependencies::empty(),
-      }];
-
-      let render_pass = device
-        .create_render_pass(&attachments, &[subpass], &dependencies)
-        .expect("Failed to create render pass");
-
-      render_pass
+    let render_pass = unsafe { create_render_pass::<B>(&device, swapchain.format) };
+    let render_pass: B::RenderPass = match render_pass {
+      Ok(render_pass) => render_pass,
+      Err(e) => {
+        for frame in frames.drain(..) {
+          unsafe { destroy_frame::<B>(&device, frame) };
+        }
+        return Err(e);
+      }
     };
 
-    GfxRenderer {
+    let viewport = StageViewport::new(swf_tree::Rect {
+      x_min: 0,
+      x_max: (DEFAULT_EXTENT.width * 20) as i32,
+      y_min: 0,
+      y_max: (DEFAULT_EXTENT.height * 20) as i32,
+    });
+
+    Ok(GfxRenderer {
       stage: None,
+      viewport,
+      adapter,
       device,
       queue_group,
       frames,
       surface,
       swapchain,
+      swapchain_outdated: false,
+      requested_extent: DEFAULT_EXTENT,
       memories,
       render_pass: ManuallyDrop::new(render_pass),
+      asset_ids: AssetIds::new(),
       frame: 0,
+    })
+  }
+
+  /// Sets how the stage is mapped to the surface.
+  pub fn set_viewport(&mut self, viewport: StageViewport) -> () {
+    self.viewport = viewport;
+  }
+
+  /// Returns the size of the output, in pixels.
+  pub fn size(&self) -> (u32, u32) {
+    (self.swapchain.extent.width, self.swapchain.extent.height)
+  }
+
+  /// Resizes the output to `width` by `height` pixels (e.g. when the window is resized).
+  ///
+  /// The swapchain is rebuilt and the viewport is re-derived from the new extent on the next frame.
+  pub fn resize(&mut self, width: u32, height: u32) -> Result<(), RendererError> {
+    self.requested_extent = Extent2D { width, height };
+    self.recreate_swapchain()
+  }
+
+  /// Rebuilds the swapchain with the requested extent, or defers it while this extent is zero.
+  fn recreate_swapchain(&mut self) -> Result<(), RendererError> {
+    let extent: Extent2D = self.requested_extent;
+    if extent.width == 0 || extent.height == 0 {
+      // Minimized window: keep the old swapchain and skip the frames until the surface has a usable size
+      self.swapchain_outdated = true;
+      return Ok(());
     }
+    self.device.wait_idle()?;
+    let swapchain: SwapchainState =
+      unsafe { create_swapchain::<B>(&self.device, &self.adapter.physical_device, &mut self.surface, extent)? };
+    if swapchain.format != self.swapchain.format {
+      // The render pass targets the format of the swapchain images
+      let render_pass = match unsafe { create_render_pass::<B>(&self.device, swapchain.format) } {
+        Ok(render_pass) => render_pass,
+        Err(e) => {
+          // Keep the old format so the next frame retries the whole recreation
+          self.swapchain_outdated = true;
+          return Err(e);
+        }
+      };
+      unsafe {
+        self
+          .device
+          .destroy_render_pass(ManuallyDrop::take(&mut self.render_pass));
+      }
+      self.render_pass = ManuallyDrop::new(render_pass);
+    }
+    self.swapchain = swapchain;
+    self.swapchain_outdated = false;
+    Ok(())
   }
 
-  fn draw(&mut self) -> () {
-    let stage: &Stage = match &self.stage {
-      Some(ref stage) => stage,
-      None => {
-        warn!("Skipping draw: no stage set");
-        return;
+  fn draw(&mut self) -> Result<(), RendererError> {
+    if self.stage.is_none() {
+      warn!("Skipping draw: no stage set");
+      return Ok(());
+    }
+
+    if self.swapchain_outdated {
+      self.recreate_swapchain()?;
+      if self.swapchain_outdated {
+        return Ok(());
       }
-    };
+    }
 
     let surface_image = unsafe {
       match self.surface.acquire_image(core::u64::MAX) {
-        Ok((image, _)) => image,
+        Ok((image, suboptimal)) => {
+          if suboptimal.is_some() {
+            // Still presentable: draw this frame and rebuild before the next one
+            self.swapchain_outdated = true;
+          }
+          image
+        }
+        Err(AcquireError::OutOfDate) => {
+          debug!("Swapchain out of date, recreating it");
+          return self.recreate_swapchain();
+        }
+        Err(AcquireError::OutOfMemory(_)) => return Err(RendererError::OutOfMemory),
+        Err(AcquireError::DeviceLost(_)) => return Err(RendererError::DeviceLost),
         Err(_) => {
           warn!("Failed to acquire image");
-          return;
+          return Ok(());
         }
       }
     };
 
     let framebuffer: B::Framebuffer = unsafe {
-      let framebuffer = self
-        .device
-        .create_framebuffer(
-          &self.render_pass,
-          iter::once(surface_image.borrow()),
-          self.swapchain.extent.to_extent(),
-        )
-        .expect("Failed to create framebuffer");
-
-      framebuffer
+      self.device.create_framebuffer(
+        &self.render_pass,
+        iter::once(surface_image.borrow()),
+        self.swapchain.extent.to_extent(),
+      )?
     };
 
-    // Compute index into frame resource ring buffer.
-    // TODO Refactor conversion
-    let frame_resource_idx: SwapImageIndex =
-      SwapImageIndex::try_from(self.frame).unwrap() % self.swapchain.frames_in_flight;
-    let frame: &mut FrameState<B> = &mut self.frames[usize::try_from(frame_resource_idx).unwrap()];
+    let submitted = unsafe { self.submit_frame(&framebuffer, surface_image) };
 
     unsafe {
-      self
-        .device
-        .wait_for_fence(&frame.submission_complete_fence, core::u64::MAX)
-        .expect("Failed to wait for fence");
-      self
-        .device
-        .reset_fence(&frame.submission_complete_fence)
-        .expect("Failed to reset fence");
-      frame.command_pool.reset(false);
-
-      frame
-        .command_buffer
-        .begin_primary(gfx_hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
-
-      frame.command_buffer.set_viewports(
-        0,
-        &[Viewport {
-          rect: Rect {
-            x: 0,
-            y: 0,
-            w: 640,
-            h: 480,
-          },
-          depth: 0.0..1.0,
-        }],
-      );
-
-      let color_f32: [f32; 4] = [
-        f32::from(stage.background_color.r) / 255.0,
-        f32::from(stage.background_color.g) / 255.0,
-        f32::from(stage.background_color.b) / 255.0,
-        1.0,
-      ];
-
-      let clear_values = [gfx_hal::command::ClearValue {
-        color: gfx_hal::command::ClearColor { float32: color_f32 },
-      }];
-      frame.command_buffer.begin_render_pass(
-        &self.render_pass,
-        &framebuffer,
-        self.swapchain.extent.to_extent().rect(),
-        clear_values.iter(),
-        gfx_hal::command::SubpassContents::Inline,
-      );
-
-      frame.command_buffer.end_render_pass();
-      frame.command_buffer.finish();
-
-      let cmd_queue: &mut B::CommandQueue = &mut self.queue_group.queues[0];
-      let submission = Submission {
-        command_buffers: iter::once(&frame.command_buffer),
-        wait_semaphores: None,
-        signal_semaphores: iter::once(&frame.submission_complete_semaphore),
-      };
-      cmd_queue.submit(submission, Some(&frame.submission_complete_fence));
-      cmd_queue
-        .present_surface(
-          &mut self.surface,
-          surface_image,
-          Some(&frame.submission_complete_semaphore),
-        )
-        .unwrap();
-      self
-        .device
-        .wait_for_fence(&frame.submission_complete_fence, core::u64::MAX)
-        .expect("Failed to wait for fence");
+      self.device.destroy_framebuffer(framebuffer);
     }
 
-    unsafe {
-      self.device.destroy_framebuffer(framebuffer);
+    submitted
+  }
+
+  /// Records the commands of the current frame, then submits and presents it.
+  unsafe fn submit_frame(
+    &mut self,
+    framebuffer: &B::Framebuffer,
+    surface_image: <B::Surface as PresentationSurface<B>>::SwapchainImage,
+  ) -> Result<(), RendererError> {
+    // Compute index into frame resource ring buffer.
+    // TODO Refactor conversion
+    // The ring buffer keeps its initial size when the swapchain is recreated.
+    let frame_resource_idx: usize = usize::try_from(self.frame).unwrap() % self.frames.len();
+    let frame: &mut FrameState<B> = &mut self.frames[frame_resource_idx];
+    let stage: &Stage = match self.stage {
+      Some(ref stage) => stage,
+      None => return Err(RendererError::MissingStage),
+    };
+
+    self
+      .device
+      .wait_for_fence(&frame.submission_complete_fence, core::u64::MAX)?;
+    self.device.reset_fence(&frame.submission_complete_fence)?;
+    frame.command_pool.reset(false);
+
+    frame
+      .command_buffer
+      .begin_primary(gfx_hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
+
+    frame.command_buffer.set_viewports(
+      0,
+      &[Viewport {
+        rect: self.swapchain.extent.to_extent().rect(),
+        depth: 0.0..1.0,
+      }],
+    );
+
+    let letterbox_color = self.viewport.letterbox_color;
+    let letterbox_f32: [f32; 4] = [
+      f32::from(letterbox_color.r) / 255.0,
+      f32::from(letterbox_color.g) / 255.0,
+      f32::from(letterbox_color.b) / 255.0,
+      f32::from(letterbox_color.a) / 255.0,
+    ];
+    let color_f32: [f32; 4] = [
+      f32::from(stage.background_color.r) / 255.0,
+      f32::from(stage.background_color.g) / 255.0,
+      f32::from(stage.background_color.b) / 255.0,
+      1.0,
+    ];
+
+    let clear_values = [gfx_hal::command::ClearValue {
+      color: gfx_hal::command::ClearColor { float32: letterbox_f32 },
+    }];
+    frame.command_buffer.begin_render_pass(
+      &self.render_pass,
+      framebuffer,
+      self.swapchain.extent.to_extent().rect(),
+      clear_values.iter(),
+      gfx_hal::command::SubpassContents::Inline,
+    );
+
+    // Only the area covered by the stage gets the background color, the rest is letterboxed
+    let visible_rect = self
+      .viewport
+      .visible_rect(self.swapchain.extent.width, self.swapchain.extent.height);
+    let visible_rect = Rect {
+      x: visible_rect.x as i16,
+      y: visible_rect.y as i16,
+      w: visible_rect.width as i16,
+      h: visible_rect.height as i16,
+    };
+    frame.command_buffer.clear_attachments(
+      iter::once(command::AttachmentClear::Color {
+        index: 0,
+        value: command::ClearColor { float32: color_f32 },
+      }),
+      iter::once(pso::ClearRect {
+        rect: visible_rect,
+        layers: 0..1,
+      }),
+    );
+    frame.command_buffer.set_scissors(0, iter::once(visible_rect));
+
+    frame.command_buffer.end_render_pass();
+    frame.command_buffer.finish();
+
+    let cmd_queue: &mut B::CommandQueue = &mut self.queue_group.queues[0];
+    let submission = Submission {
+      command_buffers: iter::once(&frame.command_buffer),
+      wait_semaphores: None,
+      signal_semaphores: iter::once(&frame.submission_complete_semaphore),
+    };
+    cmd_queue.submit(submission, Some(&frame.submission_complete_fence));
+    match cmd_queue.present_surface(
+      &mut self.surface,
+      surface_image,
+      Some(&frame.submission_complete_semaphore),
+    ) {
+      Ok(None) => {}
+      Ok(Some(_)) | Err(PresentError::OutOfDate) => self.swapchain_outdated = true,
+      Err(PresentError::OutOfMemory(_)) => return Err(RendererError::OutOfMemory),
+      Err(PresentError::DeviceLost(_)) => return Err(RendererError::DeviceLost),
+      Err(e) => warn!("Failed to present image: {:?}", e),
     }
+    self
+      .device
+      .wait_for_fence(&frame.submission_complete_fence, core::u64::MAX)?;
+    Ok(())
   }
 }
 
 impl<B: Backend> SwfRenderer for GfxRenderer<B> {
-  fn render(&mut self, stage: Stage) -> () {
+  fn render(&mut self, stage: Stage) -> Result<(), RendererError> {
     self.stage = Some(stage);
-    self.draw();
+    self.draw()
+  }
+
+  fn resize(&mut self, width: u32, height: u32) -> Result<(), RendererError> {
+    GfxRenderer::resize(self, width, height)
+  }
+
+  fn set_viewport(&mut self, viewport: StageViewport) -> () {
+    GfxRenderer::set_viewport(self, viewport)
   }
 }
 
 impl<B: Backend> ClientAssetStore for GfxRenderer<B> {
-  fn register_shape(&mut self, _tag: &DefineShape) -> ShapeId {
-    ShapeId(0)
+  fn create_movie(&mut self) -> MovieId {
+    self.asset_ids.create_movie()
+  }
+
+  fn release_movie(&mut self, movie: MovieId) -> () {
+    self.asset_ids.release_movie(movie);
+  }
+
+  // The shapes are not stored yet: only their ids are allocated
+
+  fn register_shape(&mut self, movie: MovieId, tag: &DefineShape) -> Result<ShapeId, RendererError> {
+    Ok(ShapeId(self.asset_ids.asset_id(movie, tag.id)))
+  }
+
+  fn register_morph_shape(&mut self, movie: MovieId, tag: &DefineMorphShape) -> Result<MorphShapeId, RendererError> {
+    Ok(MorphShapeId(self.asset_ids.asset_id(movie, tag.id)))
   }
 
-  fn register_morph_shape(&mut self, _tag: &DefineMorphShape) -> MorphShapeId {
-    MorphShapeId(0)
+  fn redefine_shape(&mut self, _id: ShapeId, _tag: &DefineShape) -> Result<(), RendererError> {
+    Ok(())
   }
+
+  fn redefine_morph_shape(&mut self, _id: MorphShapeId, _tag: &DefineMorphShape) -> Result<(), RendererError> {
+    Ok(())
+  }
+
+  fn release_shape(&mut self, _id: ShapeId) -> () {}
+
+  fn release_morph_shape(&mut self, _id: MorphShapeId) -> () {}
 }
 
 impl<B: Backend> Drop for GfxRenderer<B> {
   fn drop(&mut self) -> () {
     unsafe {
-      self.device.wait_idle().expect("Failed to wait for device to be idle");
+      if let Err(e) = self.device.wait_idle() {
+        warn!("Failed to wait for device to be idle: {:?}", e);
+      }
 
       //      for (_, mesh) in self.shape_meshes.drain() {
       //        destroy_buffer(&self.device, ManuallyDrop::into_inner(mesh.indices));
@@ -376,9 +587,7 @@ impl<B: Backend> Drop for GfxRenderer<B> {
         .destroy_render_pass(ManuallyDrop::take(&mut self.render_pass));
 
       for frame in self.frames.drain(..) {
-        self.device.destroy_command_pool(frame.command_pool);
-        self.device.destroy_fence(frame.submission_complete_fence);
-        self.device.destroy_semaphore(frame.submission_complete_semaphore);
+        destroy_frame::<B>(&self.device, frame);
       }
 
       self.surface.unconfigure_swapchain(&self.device);
@@ -1,5 +1,7 @@
 use std::collections::HashMap;
 use std::mem::ManuallyDrop;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 
 use gfx_hal::adapter::PhysicalDevice;
 use gfx_hal::command::CommandBuffer;
@@ -9,25 +11,51 @@ use gfx_hal::pool::CommandPool;
 use gfx_hal::queue::family::QueueFamily;
 use gfx_hal::queue::CommandQueue;
 use gfx_hal::Backend as GfxBackend;
+use log::warn;
 use nalgebra_glm as glm;
-
+use swf_tree::tags::{DefineMorphShape, DefineShape};
+
+use crate::allocator::RangeAllocator;
+use crate::asset::{AssetIds, ClientAssetStore, MorphShapeId, MovieId, ShapeId};
+use crate::budget::{MemoryBudget, MemoryStats};
+use crate::curve::CurveMode;
+use crate::damage::{stage_damage, Damage};
+use crate::error::RendererError;
 use crate::gfx::{
-  create_buffer, create_image, create_images, destroy_buffer, destroy_image, get_supported_depth_format,
-  AttachedBuffer, AttachedImage,
+  create_buffer, create_image, create_images, destroy_buffer, destroy_image, destroy_images,
+  get_supported_depth_format, AttachedBuffer, AttachedImage,
 };
-use crate::renderer::{DisplayItem, GfxSymbol, Image, ImageMetadata, Renderer, ShapeStore};
-use crate::swf_renderer::Vertex;
+use crate::lod::{LodCache, LodLevel};
+use crate::pipeline_cache::{cache_path, create_pipeline_cache, destroy_pipeline_cache};
+use crate::renderer::{flatten_stage, DisplayItem, Image, ImageMetadata, Renderer, ShapeStore};
+use crate::shaders::{ShaderBackend, ShaderVariant};
+use crate::stage::{Matrix2D, Stage};
+use crate::swf_renderer::{Instance, SwfRenderer, Vertex};
+use crate::viewport::{HorizontalAlign, PixelRect, ScaleMode, VerticalAlign, Viewport};
 use std::borrow::Cow;
 
 const QUEUE_COUNT: usize = 1;
-const VERTEX_SHADER_SOURCE: &'static str = include_str!("shader.vert.glsl");
-const FRAGMENT_SHADER_SOURCE: &'static str = include_str!("shader.frag.glsl");
+
+/// Initial capacity of the shared buffers, they grow when full
+const INITIAL_VERTEX_CAPACITY: u32 = 1 << 16;
+const INITIAL_INDEX_CAPACITY: u32 = 1 << 16;
+const INITIAL_INSTANCE_CAPACITY: u32 = 1 << 10;
 
 pub struct HeadlessGfxRenderer<B: GfxBackend> {
   pub viewport_extent: Extent,
-  pub stage: Option<DisplayItem>,
-  pub shape_store: ShapeStore,
-  pub shape_meshes: HashMap<usize, ShapeMesh<B>>,
+  pub viewport: Viewport,
+  pub stage: Option<Vec<DisplayItem>>,
+  /// Stage of the last frame drawn by `SwfRenderer::render`, `None` if the next frame must be fully drawn
+  previous_stage: Option<Stage>,
+  /// Redraw only the area changed since the previous stage
+  incremental: bool,
+  /// Decoded shapes, by global id
+  shape_store: ShapeStore,
+  pub shape_meshes: HashMap<usize, LodCache<ShapeMesh>>,
+  /// Residency of the meshes of `shape_meshes`, by shape id and level of detail
+  memory: MemoryBudget<(usize, LodLevel)>,
+  /// Global ids of the assets of each movie
+  asset_ids: AssetIds,
 
   pub device: B::Device,
   pub queue_group: gfx_hal::queue::QueueGroup<B>,
@@ -43,13 +71,77 @@ pub struct HeadlessGfxRenderer<B: GfxBackend> {
   pub depth_image_view: ManuallyDrop<B::ImageView>,
 
   pub render_pass: ManuallyDrop<B::RenderPass>,
+  /// Render pass keeping the previous frame, for the incremental updates
+  incremental_render_pass: ManuallyDrop<B::RenderPass>,
   pub framebuffer: ManuallyDrop<B::Framebuffer>,
+  /// Pipelines created for the shader variants used so far
+  pub pipelines: HashMap<ShaderVariant, GfxPipeline<B>>,
+  pub pipeline_cache: ManuallyDrop<B::PipelineCache>,
+  /// File where the pipeline cache is saved on drop
+  pipeline_cache_path: Option<PathBuf>,
+
+  /// Vertices of all the shape meshes
+  vertex_arena: ManuallyDrop<MeshArena<B>>,
+  /// Indices of all the shape meshes, relative to the first vertex of their mesh
+  index_arena: ManuallyDrop<MeshArena<B>>,
+  /// Per-instance data of the last frame (host-visible)
+  instance_buffer: ManuallyDrop<AttachedBuffer<B>>,
+  instance_capacity: u32,
+}
+
+/// Graphics pipeline shared by all the draw calls using the same shader variant.
+pub struct GfxPipeline<B: GfxBackend> {
+  pub descriptor_set_layout: B::DescriptorSetLayout,
+  pub layout: B::PipelineLayout,
+  pub pipeline: B::GraphicsPipeline,
+}
+
+/// Location of a shape mesh in the shared vertex and index buffers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ShapeMesh {
+  vertices: Range<u32>,
+  indices: Range<u32>,
+}
+
+impl ShapeMesh {
+  /// Size of the mesh in the shared buffers, in bytes.
+  fn size(&self) -> u64 {
+    let vertex_size = ::std::mem::size_of::<Vertex>() as u64;
+    let index_size = ::std::mem::size_of::<u32>() as u64;
+    u64::from(self.vertices.end - self.vertices.start) * vertex_size
+      + u64::from(self.indices.end - self.indices.start) * index_size
+  }
+}
+
+/// Device-local buffer shared by the meshes, suballocated by element.
+struct MeshArena<B: GfxBackend> {
+  buffer: AttachedBuffer<B>,
+  allocator: RangeAllocator,
+  usage: gfx_hal::buffer::Usage,
+  element_size: u64,
+}
+
+/// Mesh data waiting to be copied to the shared buffers.
+struct MeshUploads {
+  vertices: Vec<Vertex>,
+  /// Copies from `vertices` to the vertex arena, as `(source offset, destination offset, size)` in elements
+  vertex_copies: Vec<(u32, u32, u32)>,
+  indices: Vec<u32>,
+  index_copies: Vec<(u32, u32, u32)>,
+  /// Uploaded meshes, as `(shape id, level)`
+  meshes: Vec<(usize, LodLevel)>,
 }
 
-pub struct ShapeMesh<B: GfxBackend> {
-  vertices: ManuallyDrop<AttachedBuffer<B>>,
-  indices: ManuallyDrop<AttachedBuffer<B>>,
-  index_count: usize,
+impl MeshUploads {
+  fn new() -> Self {
+    Self {
+      vertices: Vec::new(),
+      vertex_copies: Vec::new(),
+      indices: Vec::new(),
+      index_copies: Vec::new(),
+      meshes: Vec::new(),
+    }
+  }
 }
 
 fn is_compatible_queue_familiy<B: GfxBackend>(qf: &B::QueueFamily) -> bool {
@@ -61,7 +153,19 @@ impl<B: GfxBackend> HeadlessGfxRenderer<B> {
     instance: &I,
     width: usize,
     height: usize,
-  ) -> Result<HeadlessGfxRenderer<B>, &'static str> {
+  ) -> Result<HeadlessGfxRenderer<B>, RendererError> {
+    Self::new_with_pipeline_cache(instance, width, height, None)
+  }
+
+  /// Creates a renderer whose pipeline cache is loaded from `cache_dir` and saved back on drop.
+  ///
+  /// The cache file is specific to the adapter and to the shaders, see `pipeline_cache::cache_path`.
+  pub fn new_with_pipeline_cache<I: gfx_hal::Instance<B>>(
+    instance: &I,
+    width: usize,
+    height: usize,
+    cache_dir: Option<&Path>,
+  ) -> Result<HeadlessGfxRenderer<B>, RendererError> {
     let viewport_extent = Extent {
       width: width as u32,
       height: height as u32,
@@ -72,145 +176,164 @@ impl<B: GfxBackend> HeadlessGfxRenderer<B> {
       .enumerate_adapters()
       .into_iter()
       .find(|a| a.queue_families.iter().any(is_compatible_queue_familiy::<B>))
-      .ok_or("Failed to find a compatible GPU adapter")?;
+      .ok_or(RendererError::AdapterNotFound)?;
+    let pipeline_cache_path = cache_dir.map(|cache_dir| cache_path(cache_dir, &adapter.info));
 
     let (device, queue_group): (B::Device, gfx_hal::queue::QueueGroup<B>) = {
       let family: &B::QueueFamily = adapter
         .queue_families
         .iter()
         .find(|qf| is_compatible_queue_familiy::<B>(qf))
-        .expect("Failed to find queue family with graphics support");
+        .ok_or(RendererError::AdapterNotFound)?;
 
       let mut gpu: gfx_hal::adapter::Gpu<B> = unsafe {
         adapter
           .physical_device
           .open(&[(family, &[1.0])], gfx_hal::Features::empty())
-          .expect("Failed to open GPU")
+          .map_err(|e| match e {
+            gfx_hal::device::CreationError::OutOfMemory(_) => RendererError::OutOfMemory,
+            gfx_hal::device::CreationError::DeviceLost => RendererError::DeviceLost,
+            _ => RendererError::Gfx("Failed to open GPU"),
+          })?
       };
 
-      (gpu.device, gpu.queue_groups.pop().unwrap())
+      let queue_group = gpu
+        .queue_groups
+        .pop()
+        .ok_or(RendererError::Gfx("Failed to open queue group"))?;
+      (gpu.device, queue_group)
     };
 
     let memories = adapter.physical_device.memory_properties();
     let color_format = gfx_hal::format::Format::Rgba8Unorm;
     let depth_format =
-      get_supported_depth_format::<B>(&adapter.physical_device).ok_or("Failed to find supported depth format")?;
+      get_supported_depth_format::<B>(&adapter.physical_device).ok_or(RendererError::AdapterNotFound)?;
 
     let command_pool = unsafe {
-      device
-        .create_command_pool(
-          queue_group.family,
-          gfx_hal::pool::CommandPoolCreateFlags::RESET_INDIVIDUAL,
-        )
-        .map_err(|_| "Failed to create command pool")?
+      device.create_command_pool(
+        queue_group.family,
+        gfx_hal::pool::CommandPoolCreateFlags::RESET_INDIVIDUAL,
+      )?
     };
 
     // Create attachments
     let attachments = unsafe { create_images::<B>(&device, viewport_extent, color_format, depth_format, &memories) };
 
-    let ((color_image, color_image_view), (depth_image, depth_image_view)) = attachments.unwrap();
+    let ((color_image, color_image_view), (depth_image, depth_image_view)) = match attachments {
+      Ok(attachments) => attachments,
+      Err(e) => {
+        unsafe {
+          device.destroy_command_pool(command_pool);
+        }
+        return Err(e);
+      }
+    };
 
-    let render_pass = unsafe {
-      let color_attachment: gfx_hal::pass::Attachment = gfx_hal::pass::Attachment {
-        format: Some(color_format),
-        samples: 1,
-        ops: gfx_hal::pass::AttachmentOps {
-          load: gfx_hal::pass::AttachmentLoadOp::Clear,
-          store: gfx_hal::pass::AttachmentStoreOp::Store,
-        },
-        stencil_ops: gfx_hal::pass::AttachmentOps {
-          load: gfx_hal::pass::AttachmentLoadOp::DontCare,
-          store: gfx_hal::pass::AttachmentStoreOp::DontCare,
-        },
-        layouts: std::ops::Range {
-          start: gfx_hal::image::Layout::Undefined,
-          end: gfx_hal::image::Layout::TransferSrcOptimal,
-        },
-      };
-      let depth_attachment: gfx_hal::pass::Attachment = gfx_hal::pass::Attachment {
-        format: Some(depth_format),
-        samples: 1,
-        ops: gfx_hal::pass::AttachmentOps {
-          load: gfx_hal::pass::AttachmentLoadOp::Clear,
-          store: gfx_hal::pass::AttachmentStoreOp::DontCare,
-        },
-        stencil_ops: gfx_hal::pass::AttachmentOps {
-          load: gfx_hal::pass::AttachmentLoadOp::DontCare,
-          store: gfx_hal::pass::AttachmentStoreOp::DontCare,
-        },
-        layouts: std::ops::Range {
-          start: gfx_hal::image::Layout::Undefined,
-          end: gfx_hal::image::Layout::DepthStencilAttachmentOptimal,
-        },
-      };
-      let attachments = [color_attachment, depth_attachment];
+    let render_passes = unsafe {
+      create_render_pass::<B>(&device, color_format, depth_format, false).and_then(|render_pass| {
+        match create_render_pass::<B>(&device, color_format, depth_format, true) {
+          Ok(incremental_render_pass) => Ok((render_pass, incremental_render_pass)),
+          Err(e) => {
+            device.destroy_render_pass(render_pass);
+            Err(e)
+          }
+        }
+      })
+    };
 
-      let color_ref: gfx_hal::pass::AttachmentRef = (0, gfx_hal::image::Layout::ColorAttachmentOptimal);
-      let depth_ref: gfx_hal::pass::AttachmentRef = (1, gfx_hal::image::Layout::DepthStencilAttachmentOptimal);
+    let framebuffer = match render_passes {
+      Ok((ref render_pass, _)) => unsafe {
+        let image_views = vec![&color_image_view, &depth_image_view];
+        device.create_framebuffer(render_pass, image_views.into_iter(), viewport_extent)
+      },
+      Err(e) => Err(e),
+    };
 
-      let subpass_desc: gfx_hal::pass::SubpassDesc = gfx_hal::pass::SubpassDesc {
-        colors: &[color_ref],
-        depth_stencil: Some(&depth_ref),
-        inputs: &[],
-        resolves: &[],
-        preserves: &[],
-      };
+    let (render_pass, incremental_render_pass, framebuffer) = match (render_passes, framebuffer) {
+      (Ok((render_pass, incremental_render_pass)), Ok(framebuffer)) => {
+        (render_pass, incremental_render_pass, framebuffer)
+      }
+      (render_passes, _) => {
+        unsafe {
+          if let Ok((render_pass, incremental_render_pass)) = render_passes {
+            device.destroy_render_pass(render_pass);
+            device.destroy_render_pass(incremental_render_pass);
+          }
+          destroy_images(
+            &device,
+            (color_image, color_image_view),
+            (depth_image, depth_image_view),
+          );
+          device.destroy_command_pool(command_pool);
+        }
+        return Err(RendererError::OutOfMemory);
+      }
+    };
 
-      let dependencies = [
-        gfx_hal::pass::SubpassDependency {
-          passes: std::ops::Range {
-            start: gfx_hal::pass::SubpassRef::External,
-            end: gfx_hal::pass::SubpassRef::Pass(0),
-          },
-          stages: std::ops::Range {
-            start: gfx_hal::pso::PipelineStage::BOTTOM_OF_PIPE,
-            end: gfx_hal::pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
-          },
-          accesses: std::ops::Range {
-            start: gfx_hal::image::Access::MEMORY_READ,
-            end: gfx_hal::image::Access::COLOR_ATTACHMENT_READ | gfx_hal::image::Access::COLOR_ATTACHMENT_WRITE,
-          },
-          flags: gfx_hal::memory::Dependencies::empty(),
-        },
-        gfx_hal::pass::SubpassDependency {
-          passes: std::ops::Range {
-            start: gfx_hal::pass::SubpassRef::Pass(0),
-            end: gfx_hal::pass::SubpassRef::External,
-          },
-          stages: std::ops::Range {
-            start: gfx_hal::pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
-            end: gfx_hal::pso::PipelineStage::BOTTOM_OF_PIPE,
-          },
-          accesses: std::ops::Range {
-            start: gfx_hal::image::Access::COLOR_ATTACHMENT_READ | gfx_hal::image::Access::COLOR_ATTACHMENT_WRITE,
-            end: gfx_hal::image::Access::MEMORY_READ,
-          },
-          flags: gfx_hal::memory::Dependencies::empty(),
+    let resources = unsafe {
+      let variant = ShaderVariant::for_curve_mode(CurveMode::Tessellated);
+      create_pipeline_cache::<B>(&device, pipeline_cache_path.as_ref().map(PathBuf::as_path)).and_then(
+        |pipeline_cache| {
+          let resources = create_pipeline::<B>(&device, &render_pass, &pipeline_cache, viewport_extent, variant)
+            .and_then(|pipeline| match create_draw_buffers::<B>(&device, &memories) {
+              Ok(buffers) => Ok(((variant, pipeline), buffers)),
+              Err(e) => {
+                destroy_pipeline(&device, pipeline);
+                Err(e)
+              }
+            });
+          match resources {
+            Ok((pipeline, buffers)) => Ok((pipeline_cache, pipeline, buffers)),
+            Err(e) => {
+              device.destroy_pipeline_cache(pipeline_cache);
+              Err(e)
+            }
+          }
         },
-      ];
-
-      let render_pass = device
-        .create_render_pass(&attachments, &[subpass_desc], &dependencies)
-        .expect("Failed to create render pass");
-
-      render_pass
+      )
     };
 
-    let framebuffer = unsafe {
-      let image_views = vec![&color_image_view, &depth_image_view];
-
-      let framebuffer = device
-        .create_framebuffer(&render_pass, image_views.into_iter(), viewport_extent)
-        .expect("Failed to create frame buffer");
+    let (pipeline_cache, pipeline, (vertex_arena, index_arena, instance_buffer)) = match resources {
+      Ok(resources) => resources,
+      Err(e) => {
+        unsafe {
+          device.destroy_framebuffer(framebuffer);
+          device.destroy_render_pass(render_pass);
+          device.destroy_render_pass(incremental_render_pass);
+          destroy_images(
+            &device,
+            (color_image, color_image_view),
+            (depth_image, depth_image_view),
+          );
+          device.destroy_command_pool(command_pool);
+        }
+        return Err(e);
+      }
+    };
 
-      framebuffer
+    // By default, the image shows the area `(0, 0, width, height)` of the stage with a 1:1 scale.
+    let viewport = Viewport {
+      scale_mode: ScaleMode::NoScale,
+      horizontal_align: HorizontalAlign::Left,
+      vertical_align: VerticalAlign::Top,
+      ..Viewport::new(swf_tree::Rect {
+        x_min: 0,
+        x_max: (width * 20) as i32,
+        y_min: 0,
+        y_max: (height * 20) as i32,
+      })
     };
 
     Ok(HeadlessGfxRenderer::<B> {
       viewport_extent,
+      viewport,
       stage: None,
+      previous_stage: None,
+      incremental: true,
       shape_store: ShapeStore::new(),
       shape_meshes: HashMap::new(),
+      memory: MemoryBudget::new(None),
+      asset_ids: AssetIds::new(),
       device,
       queue_group,
       command_pool: ManuallyDrop::new(command_pool),
@@ -222,395 +345,418 @@ impl<B: GfxBackend> HeadlessGfxRenderer<B> {
       depth_image: ManuallyDrop::new(depth_image),
       depth_image_view: ManuallyDrop::new(depth_image_view),
       render_pass: ManuallyDrop::new(render_pass),
+      incremental_render_pass: ManuallyDrop::new(incremental_render_pass),
       framebuffer: ManuallyDrop::new(framebuffer),
+      pipelines: vec![pipeline].into_iter().collect(),
+      pipeline_cache: ManuallyDrop::new(pipeline_cache),
+      pipeline_cache_path,
+      vertex_arena: ManuallyDrop::new(vertex_arena),
+      index_arena: ManuallyDrop::new(index_arena),
+      instance_buffer: ManuallyDrop::new(instance_buffer),
+      instance_capacity: INITIAL_INSTANCE_CAPACITY,
     })
   }
 
-  pub fn define_shape(&mut self, tag: &swf_tree::tags::DefineShape) -> usize {
-    self.shape_store.define_shape(tag)
+  /// Sets how the stage is mapped to the output image.
+  pub fn set_viewport(&mut self, viewport: Viewport) -> () {
+    self.viewport = viewport;
+    self.invalidate();
   }
 
-  pub fn get_image(&mut self) -> Result<Image, &'static str> {
-    match self.stage.take() {
-      None => Err("Failed to render: self.stage is None"),
-      Some(stage) => {
-        let display_list = [stage];
-        self.render_stage(&display_list);
-        let [old_stage] = display_list;
-        self.stage = Some(old_stage);
-        Ok(self.download_image())
-      }
-    }
+  /// Enables or disables the incremental rendering of the stages (enabled by default).
+  ///
+  /// When enabled, `SwfRenderer::render` compares the stage with the previous one and redraws only the changed
+  /// area, keeping the rest of the previous frame. Disable it for content whose changes are not visible in the
+  /// stages, such as animated effects.
+  pub fn set_incremental(&mut self, incremental: bool) -> () {
+    self.incremental = incremental;
+    self.invalidate();
   }
 
-  fn get_shape_mesh(&mut self, shape_id: usize) -> &ShapeMesh<B> {
-    match self.shape_store.get(shape_id) {
-      Some(GfxSymbol::Shape(symbol)) => {
-        let cmd_queue = &mut self.queue_group.queues[0];
-
-        type IndexType = u32;
-
-        let index_count: usize = symbol.mesh.indices.len();
-        let vertex_buffer_size = ::std::mem::size_of::<Vertex>() * symbol.mesh.vertices.len();
-        let index_buffer_size = ::std::mem::size_of::<IndexType>() * index_count;
-
-        let vertices = {
-          let staging_buffer = unsafe {
-            create_buffer::<B>(
-              &self.device,
-              gfx_hal::buffer::Usage::TRANSFER_SRC,
-              gfx_hal::memory::Properties::CPU_VISIBLE | gfx_hal::memory::Properties::COHERENT,
-              vertex_buffer_size as u64,
-              &self.memories,
-            )
-            .unwrap()
-          };
-
-          unsafe {
-            let mapping = self
-              .device
-              .map_memory(&staging_buffer.memory, 0..staging_buffer.capacity)
-              .expect("Failed to map staging memory (for mesh upload)");
+  /// Forces the next stage to be fully redrawn.
+  pub fn invalidate(&mut self) -> () {
+    self.previous_stage = None;
+  }
 
-            std::ptr::copy_nonoverlapping(
-              symbol.mesh.vertices.as_ptr(),
-              mapping as *mut Vertex,
-              symbol.mesh.vertices.len(),
-            );
+  /// Limits the size of the shape meshes kept on the GPU, in bytes (`None` for no limit).
+  ///
+  /// After each frame, the least recently drawn meshes are evicted until the budget is met. They are uploaded
+  /// again from the shape store when they are drawn.
+  pub fn set_memory_budget(&mut self, budget: Option<u64>) -> () {
+    self.memory.set_budget(budget);
+  }
 
-            self.device.unmap_memory(&staging_buffer.memory);
-          }
+  /// Returns the counters of the GPU memory used by the shape meshes.
+  pub fn memory_stats(&self) -> MemoryStats {
+    self.memory.stats()
+  }
 
-          let vertex_buffer = unsafe {
-            create_buffer::<B>(
-              &self.device,
-              gfx_hal::buffer::Usage::VERTEX | gfx_hal::buffer::Usage::TRANSFER_DST,
-              gfx_hal::memory::Properties::DEVICE_LOCAL,
-              vertex_buffer_size as u64,
-              &self.memories,
-            )
-            .unwrap()
-          };
+  /// Returns the size of the shared vertex and index buffers holding the meshes, in bytes.
+  ///
+  /// The buffers grow to fit the meshes of a frame. With a memory budget, they shrink back to fit the
+  /// resident meshes once the frame is complete.
+  pub fn arena_capacity(&self) -> u64 {
+    self.vertex_arena.size() + self.index_arena.size()
+  }
 
-          unsafe {
-            let mut copy_cmd = self.command_pool.allocate_one(gfx_hal::command::Level::Primary);
-            copy_cmd.begin_primary(gfx_hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
-            copy_cmd.copy_buffer(
-              &staging_buffer.buffer,
-              &vertex_buffer.buffer,
-              &[gfx_hal::command::BufferCopy {
-                src: 0,
-                dst: 0,
-                size: vertex_buffer_size as u64,
-              }],
-            );
-            copy_cmd.finish();
-            let copy_fence = self.device.create_fence(false).expect("Failed to create fence");
-            cmd_queue.submit_without_semaphores(Some(&copy_cmd), Some(&copy_fence));
-            self
-              .device
-              .wait_for_fence(&copy_fence, core::u64::MAX)
-              .expect("Failed to wait for fence");
-            self.device.destroy_fence(copy_fence);
-          }
+  /// Changes how the curves are rendered, releasing the meshes built for the previous mode.
+  pub fn set_curve_mode(&mut self, curve_mode: CurveMode) -> () {
+    if curve_mode == self.shape_store.curve_mode() {
+      return;
+    }
+    self.shape_store.set_curve_mode(curve_mode);
+    self.invalidate();
+    self.memory.clear();
+    let meshes: Vec<ShapeMesh> = self
+      .shape_meshes
+      .drain()
+      .flat_map(|(_, mut lods)| lods.drain().collect::<Vec<_>>())
+      .collect();
+    for mesh in meshes {
+      self.release_mesh(mesh);
+    }
+  }
 
-          unsafe {
-            destroy_buffer(&self.device, staging_buffer);
-          }
+  /// Removes a shape and returns its meshes to the shared buffers.
+  fn remove_shape(&mut self, id: usize) -> () {
+    self.shape_store.remove(id);
+    self.release_shape_meshes(id);
+    self.invalidate();
+    self.trim_arenas();
+  }
 
-          vertex_buffer
-        };
+  fn release_shape_meshes(&mut self, id: usize) -> () {
+    if let Some(mut lods) = self.shape_meshes.remove(&id) {
+      for level in lods.levels() {
+        self.memory.remove(&(id, level));
+      }
+      for mesh in lods.drain() {
+        self.release_mesh(mesh);
+      }
+    }
+  }
 
-        let indices = {
-          let staging_buffer = unsafe {
-            create_buffer::<B>(
-              &self.device,
-              gfx_hal::buffer::Usage::TRANSFER_SRC,
-              gfx_hal::memory::Properties::CPU_VISIBLE | gfx_hal::memory::Properties::COHERENT,
-              index_buffer_size as u64,
-              &self.memories,
-            )
-            .unwrap()
-          };
+  /// Returns the memory of the grown shared buffers once all the meshes are released.
+  fn trim_arenas(&mut self) -> () {
+    let trimmed = unsafe {
+      self
+        .vertex_arena
+        .shrink_if_empty(&self.device, &self.memories, INITIAL_VERTEX_CAPACITY)
+        .and_then(|_| {
+          self
+            .index_arena
+            .shrink_if_empty(&self.device, &self.memories, INITIAL_INDEX_CAPACITY)
+        })
+    };
+    if let Err(e) = trimmed {
+      warn!("Failed to shrink the mesh buffers: {:?}", e);
+    }
+  }
 
-          unsafe {
-            let mapping = self
-              .device
-              .map_memory(&staging_buffer.memory, 0..staging_buffer.capacity)
-              .expect("Failed to map staging memory (for indices upload)");
+  /// Moves the resident meshes to smaller shared buffers when the buffers exceed the memory budget.
+  ///
+  /// Evicting a mesh only frees its ranges: without compaction, the buffers would keep the size of the
+  /// largest frame.
+  fn compact_arenas(&mut self) -> () {
+    let budget = match self.memory.budget() {
+      Some(budget) => budget,
+      None => return,
+    };
+    let initial_size = self.vertex_arena.element_size * u64::from(INITIAL_VERTEX_CAPACITY)
+      + self.index_arena.element_size * u64::from(INITIAL_INDEX_CAPACITY);
+    if self.arena_capacity() <= u64::max(budget, initial_size) {
+      return;
+    }
 
-            std::ptr::copy_nonoverlapping(
-              symbol.mesh.indices.as_ptr(),
-              mapping as *mut u32,
-              symbol.mesh.indices.len(),
-            );
+    let cmd_queue = &mut self.queue_group.queues[0];
+    let mut meshes: Vec<&mut ShapeMesh> = self
+      .shape_meshes
+      .values_mut()
+      .flat_map(|lods| lods.values_mut())
+      .collect();
+    let vertices: Vec<Range<u32>> = meshes.iter().map(|mesh| mesh.vertices.clone()).collect();
+    let moved = unsafe {
+      self.vertex_arena.compact(
+        &self.device,
+        &self.memories,
+        &mut self.command_pool,
+        cmd_queue,
+        &vertices,
+        INITIAL_VERTEX_CAPACITY,
+      )
+    };
+    match moved {
+      Ok(moved) => {
+        for (mesh, range) in meshes.iter_mut().zip(moved.into_iter()) {
+          mesh.vertices = range;
+        }
+      }
+      Err(e) => {
+        warn!("Failed to compact the vertex buffer: {}", e);
+        return;
+      }
+    }
 
-            self.device.unmap_memory(&staging_buffer.memory);
-          }
+    let indices: Vec<Range<u32>> = meshes.iter().map(|mesh| mesh.indices.clone()).collect();
+    let moved = unsafe {
+      self.index_arena.compact(
+        &self.device,
+        &self.memories,
+        &mut self.command_pool,
+        cmd_queue,
+        &indices,
+        INITIAL_INDEX_CAPACITY,
+      )
+    };
+    match moved {
+      Ok(moved) => {
+        for (mesh, range) in meshes.iter_mut().zip(moved.into_iter()) {
+          mesh.indices = range;
+        }
+      }
+      Err(e) => warn!("Failed to compact the index buffer: {}", e),
+    }
+  }
 
-          let index_buffer = unsafe {
-            create_buffer::<B>(
-              &self.device,
-              gfx_hal::buffer::Usage::INDEX | gfx_hal::buffer::Usage::TRANSFER_DST,
-              gfx_hal::memory::Properties::DEVICE_LOCAL,
-              index_buffer_size as u64,
-              &self.memories,
-            )
-            .unwrap()
-          };
+  pub fn get_image(&mut self) -> Result<Image, RendererError> {
+    match self.stage.take() {
+      None => Err(RendererError::MissingStage),
+      Some(stage) => {
+        let rendered = self.render_stage(&stage, None);
+        self.stage = Some(stage);
+        rendered?;
+        self.download_image()
+      }
+    }
+  }
 
-          unsafe {
-            let mut copy_cmd = self.command_pool.allocate_one(gfx_hal::command::Level::Primary);
-            copy_cmd.begin_primary(gfx_hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
-            copy_cmd.copy_buffer(
-              &staging_buffer.buffer,
-              &index_buffer.buffer,
-              &[gfx_hal::command::BufferCopy {
-                src: 0,
-                dst: 0,
-                size: index_buffer_size as u64,
-              }],
-            );
-            copy_cmd.finish();
-            let copy_fence = self.device.create_fence(false).expect("Failed to create fence");
-            cmd_queue.submit_without_semaphores(Some(&copy_cmd), Some(&copy_fence));
-            self
-              .device
-              .wait_for_fence(&copy_fence, core::u64::MAX)
-              .expect("Failed to wait for fence");
-            self.device.destroy_fence(copy_fence);
-          }
+  /// Returns the location of the mesh of a shape for a level of detail, allocating it on first use.
+  ///
+  /// New meshes are queued in `uploads`. The meshes evicted from the cache are queued in `released`: they
+  /// may still be drawn in the current frame.
+  fn prepare_shape_mesh(
+    &mut self,
+    shape_id: usize,
+    level: LodLevel,
+    uploads: &mut MeshUploads,
+    released: &mut Vec<ShapeMesh>,
+  ) -> Result<ShapeMesh, RendererError> {
+    if let Some(mesh) = self.shape_meshes.get_mut(&shape_id).and_then(|lods| lods.get(level)) {
+      self.memory.touch(&(shape_id, level));
+      return Ok(mesh.clone());
+    }
 
-          unsafe {
-            destroy_buffer(&self.device, staging_buffer);
-          }
+    let mesh = self.shape_store.get_mesh(shape_id, level)?;
+    let cmd_queue = &mut self.queue_group.queues[0];
+    let vertex_count = mesh.vertices.len() as u32;
+    let index_count = mesh.indices.len() as u32;
 
-          index_buffer
-        };
+    let vertices = unsafe {
+      self.vertex_arena.allocate(
+        &self.device,
+        &self.memories,
+        &mut self.command_pool,
+        cmd_queue,
+        vertex_count,
+      )?
+    };
+    let indices = unsafe {
+      self.index_arena.allocate(
+        &self.device,
+        &self.memories,
+        &mut self.command_pool,
+        cmd_queue,
+        index_count,
+      )
+    };
+    let indices = match indices {
+      Ok(indices) => indices,
+      Err(e) => {
+        self.vertex_arena.allocator.free(vertices);
+        return Err(e);
+      }
+    };
 
-        let shape_mesh = ShapeMesh {
-          vertices: ManuallyDrop::new(vertices),
-          indices: ManuallyDrop::new(indices),
-          index_count,
-        };
-        self.shape_meshes.entry(shape_id).or_insert(shape_mesh)
+    uploads
+      .vertex_copies
+      .push((uploads.vertices.len() as u32, vertices.start, vertex_count));
+    uploads.vertices.extend_from_slice(&mesh.vertices);
+    uploads
+      .index_copies
+      .push((uploads.indices.len() as u32, indices.start, index_count));
+    uploads.indices.extend_from_slice(&mesh.indices);
+    uploads.meshes.push((shape_id, level));
+
+    let shape_mesh = ShapeMesh { vertices, indices };
+    let lods = self.shape_meshes.entry(shape_id).or_insert_with(LodCache::new);
+    let cached_levels: Vec<LodLevel> = lods.levels().collect();
+    let evicted = lods.insert(level, shape_mesh.clone());
+    if !evicted.is_empty() {
+      for cached_level in cached_levels.into_iter() {
+        if lods.levels().all(|remaining| remaining != cached_level) {
+          self.memory.remove(&(shape_id, cached_level));
+        }
       }
-      _ => panic!("ShapeNotFound"),
     }
+    self.memory.insert((shape_id, level), shape_mesh.size());
+    released.extend(evicted);
+    Ok(shape_mesh)
   }
 
-  fn render_stage(&mut self, display_list: &[DisplayItem]) -> () {
-    let (shape_id, matrix) = match display_list[0] {
-      DisplayItem::Shape(ref id, ref matrix) => (*id, matrix),
+  /// Copies the queued meshes to the shared buffers, with a single staging buffer and submission.
+  ///
+  /// On failure, the queued meshes are removed from the cache.
+  fn flush_uploads(&mut self, uploads: MeshUploads) -> Result<(), RendererError> {
+    if uploads.meshes.is_empty() {
+      return Ok(());
+    }
+    let cmd_queue = &mut self.queue_group.queues[0];
+    let uploaded = unsafe {
+      upload_meshes::<B>(
+        &self.device,
+        &self.memories,
+        &mut self.command_pool,
+        cmd_queue,
+        (&self.vertex_arena.buffer, &self.index_arena.buffer),
+        &uploads,
+      )
     };
+    if uploaded.is_err() {
+      for (shape_id, level) in uploads.meshes.iter() {
+        let mesh = self.shape_meshes.get_mut(shape_id).and_then(|lods| lods.remove(*level));
+        self.memory.remove(&(*shape_id, *level));
+        if let Some(mesh) = mesh {
+          self.release_mesh(mesh);
+        }
+      }
+    }
+    uploaded
+  }
 
-    let (
-      vertex_shader_module,
-      fragment_shader_module,
-      descriptor_set_layout,
-      pipeline_layout,
-      pipeline_cache,
-      pipeline,
-    ) = unsafe {
-      let descriptor_set_layout = self
-        .device
-        .create_descriptor_set_layout(&[], &[])
-        .expect("Failed to create descriptor set layout");
-
-      let constant_size: usize = ::std::mem::size_of::<glm::TMat4<f32>>();
-      let push_constants: Vec<(gfx_hal::pso::ShaderStageFlags, core::ops::Range<u32>)> = vec![(
-        gfx_hal::pso::ShaderStageFlags::VERTEX,
-        0..((constant_size / ::std::mem::size_of::<f32>()) as u32),
-      )];
-
-      let pipeline_layout = self
-        .device
-        .create_pipeline_layout(&[], push_constants)
-        .expect("Failed to create pipeline layout");
+  /// Returns the ranges of a mesh to the shared buffers.
+  fn release_mesh(&mut self, mesh: ShapeMesh) -> () {
+    self.vertex_arena.allocator.free(mesh.vertices);
+    self.index_arena.allocator.free(mesh.indices);
+  }
 
-      let pipeline_cache = self
+  /// Writes the per-instance data of the frame, growing the instance buffer if needed.
+  fn write_instances(&mut self, instances: &[Instance]) -> Result<(), RendererError> {
+    unsafe {
+      if instances.len() > self.instance_capacity as usize {
+        let capacity = (instances.len() as u32).next_power_of_two();
+        let buffer = create_instance_buffer::<B>(&self.device, &self.memories, capacity)?;
+        destroy_buffer(
+          &self.device,
+          ManuallyDrop::into_inner(core::ptr::read(&self.instance_buffer)),
+        );
+        self.instance_buffer = ManuallyDrop::new(buffer);
+        self.instance_capacity = capacity;
+      }
+      if instances.is_empty() {
+        return Ok(());
+      }
+      let size = (::std::mem::size_of::<Instance>() * instances.len()) as u64;
+      let mapping = self
         .device
-        .create_pipeline_cache(Option::None)
-        .expect("Failed to create pipeline cache");
-
-      let mut shader_compiler: shaderc::Compiler = shaderc::Compiler::new().expect("Failed to create shader");
-      let vertex_compile_artifact: shaderc::CompilationArtifact = shader_compiler
-        .compile_into_spirv(
-          VERTEX_SHADER_SOURCE,
-          shaderc::ShaderKind::Vertex,
-          "shader.vert",
-          "main",
-          None,
-        )
-        .expect("Failed to compile vertex shader");
-      let fragment_compile_artifact: shaderc::CompilationArtifact = shader_compiler
-        .compile_into_spirv(
-          FRAGMENT_SHADER_SOURCE,
-          shaderc::ShaderKind::Fragment,
-          "shader.frag",
-          "main",
-          None,
-        )
-        .expect("Failed to compile fragment shader");
-      let vertex_shader_module = {
-        self
-          .device
-          .create_shader_module(vertex_compile_artifact.as_binary())
-          .expect("Failed to create shader module")
-      };
-      let fragment_shader_module = {
-        self
-          .device
-          .create_shader_module(fragment_compile_artifact.as_binary())
-          .expect("Failed to create fragment module")
-      };
-
-      let shaders = gfx_hal::pso::GraphicsShaderSet {
-        vertex: gfx_hal::pso::EntryPoint {
-          entry: "main",
-          module: &vertex_shader_module,
-          specialization: gfx_hal::pso::Specialization {
-            constants: Cow::Owned(Vec::new()),
-            data: Cow::Owned(Vec::new()),
-          },
-        },
-        hull: None,
-        domain: None,
-        geometry: None,
-        fragment: Some(gfx_hal::pso::EntryPoint {
-          entry: "main",
-          module: &fragment_shader_module,
-          specialization: gfx_hal::pso::Specialization {
-            constants: Cow::Owned(Vec::new()),
-            data: Cow::Owned(Vec::new()),
-          },
-        }),
-      };
+        .map_memory(&self.instance_buffer.memory, 0..size)
+        .map_err(|e| map_error(e, "Failed to map instance memory"))?;
+      std::ptr::copy_nonoverlapping(instances.as_ptr(), mapping as *mut Instance, instances.len());
+      self.device.unmap_memory(&self.instance_buffer.memory);
+    }
+    Ok(())
+  }
 
-      let rasterizer = gfx_hal::pso::Rasterizer {
-        depth_clamping: false,
-        polygon_mode: gfx_hal::pso::PolygonMode::Fill,
-        cull_face: gfx_hal::pso::Face::NONE,
-        front_face: gfx_hal::pso::FrontFace::Clockwise,
-        depth_bias: None,
-        conservative: false,
+  /// Draws a display list, only inside `region` (in pixels) if provided: the previous frame is kept elsewhere.
+  fn render_stage(&mut self, display_list: &[DisplayItem], region: Option<PixelRect>) -> Result<(), RendererError> {
+    let stage_matrix = self
+      .viewport
+      .stage_matrix(self.viewport_extent.width, self.viewport_extent.height);
+
+    let variant = ShaderVariant::for_curve_mode(self.shape_store.curve_mode());
+    self.memory.next_frame();
+    let mut uploads = MeshUploads::new();
+    let mut released: Vec<ShapeMesh> = Vec::new();
+    let mut draws: Vec<ShapeMesh> = Vec::with_capacity(display_list.len());
+    let mut instances: Vec<Instance> = Vec::with_capacity(display_list.len());
+    let mut prepared: Result<(), RendererError> = Ok(());
+    for item in display_list.iter() {
+      let (shape_id, matrix, color_transform) = match item {
+        DisplayItem::Shape(ref id, ref matrix, ref color_transform) => (*id, matrix, color_transform),
       };
-
-      let vertex_buffers: Vec<gfx_hal::pso::VertexBufferDesc> = vec![gfx_hal::pso::VertexBufferDesc {
-        binding: 0,
-        stride: (::std::mem::size_of::<Vertex>()) as u32,
-        rate: ::gfx_hal::pso::VertexInputRate::Vertex,
-      }];
-      let attributes: Vec<gfx_hal::pso::AttributeDesc> = vec![
-        // position
-        gfx_hal::pso::AttributeDesc {
-          binding: 0,
-          location: 0,
-          element: gfx_hal::pso::Element {
-            format: gfx_hal::format::Format::Rgb32Sfloat,
-            offset: offset_of!(Vertex, position) as u32,
-          },
-        },
-        // color
-        gfx_hal::pso::AttributeDesc {
-          binding: 0,
-          location: 1,
-          element: gfx_hal::pso::Element {
-            format: gfx_hal::format::Format::Rgb32Sfloat,
-            offset: offset_of!(Vertex, color) as u32,
-          },
-        },
-      ];
-
-      let input_assembler: gfx_hal::pso::InputAssemblerDesc =
-        gfx_hal::pso::InputAssemblerDesc::new(gfx_hal::pso::Primitive::TriangleList);
-
-      let blender = {
-        let blend_state: Option<gfx_hal::pso::BlendState> = Some(gfx_hal::pso::BlendState {
-          color: gfx_hal::pso::BlendOp::Add {
-            src: gfx_hal::pso::Factor::One,
-            dst: gfx_hal::pso::Factor::Zero,
-          },
-          alpha: gfx_hal::pso::BlendOp::Add {
-            src: gfx_hal::pso::Factor::One,
-            dst: gfx_hal::pso::Factor::Zero,
-          },
-        });
-        gfx_hal::pso::BlendDesc {
-          logic_op: Some(gfx_hal::pso::LogicOp::Copy),
-          targets: vec![gfx_hal::pso::ColorBlendDesc {
-            mask: gfx_hal::pso::ColorMask::ALL,
-            blend: blend_state,
-          }],
+      let level = self.shape_store.lod_level((&stage_matrix * matrix).max_scale());
+      match self.prepare_shape_mesh(shape_id, level, &mut uploads, &mut released) {
+        Ok(mesh) => {
+          draws.push(mesh);
+          instances.push(Instance::new(matrix, color_transform));
         }
-      };
-
-      let depth_stencil = gfx_hal::pso::DepthStencilDesc {
-        depth: Some(gfx_hal::pso::DepthTest {
-          fun: gfx_hal::pso::Comparison::LessEqual,
-          write: true,
-        }),
-        depth_bounds: false,
-        stencil: None,
-      };
+        Err(e) => {
+          prepared = Err(e);
+          break;
+        }
+      }
+    }
 
-      let multisampling: Option<gfx_hal::pso::Multisampling> = None;
+    // The queued meshes are already cached: upload them even if the frame is not drawn
+    let uploaded = self.flush_uploads(uploads);
+    let rendered = prepared
+      .and(uploaded)
+      .and_then(|_| self.write_instances(&instances))
+      .and_then(|_| self.get_pipeline(variant).map(|_| ()))
+      .and_then(|_| self.draw(&stage_matrix, variant, &group_instances(&draws), region));
 
-      let baked_states = gfx_hal::pso::BakedStates {
-        viewport: Some(gfx_hal::pso::Viewport {
-          rect: self.viewport_extent.rect(),
-          depth: (0.0..1.0),
-        }),
-        scissor: Some(self.viewport_extent.rect()),
-        blend_color: None,
-        depth_bounds: None,
-      };
+    for mesh in released {
+      self.release_mesh(mesh);
+    }
+    // The frame is complete: the meshes above the budget can be freed
+    for (shape_id, level) in self.memory.evict() {
+      if let Some(mesh) = self.shape_meshes.get_mut(&shape_id).and_then(|lods| lods.remove(level)) {
+        self.release_mesh(mesh);
+      }
+    }
+    self.compact_arenas();
+    rendered
+  }
 
-      let pipeline_flags: gfx_hal::pso::PipelineCreationFlags = gfx_hal::pso::PipelineCreationFlags::empty();
-
-      let pipeline_desc = gfx_hal::pso::GraphicsPipelineDesc {
-        shaders,
-        rasterizer,
-        vertex_buffers,
-        attributes,
-        input_assembler,
-        blender,
-        depth_stencil,
-        multisampling,
-        baked_states,
-        layout: &pipeline_layout,
-        subpass: gfx_hal::pass::Subpass {
-          index: 0,
-          main_pass: &*self.render_pass,
-        },
-        flags: pipeline_flags,
-        parent: gfx_hal::pso::BasePipeline::None,
+  /// Returns the pipeline of a shader variant, creating it on first use.
+  fn get_pipeline(&mut self, variant: ShaderVariant) -> Result<&GfxPipeline<B>, RendererError> {
+    if !self.pipelines.contains_key(&variant) {
+      let pipeline = unsafe {
+        create_pipeline::<B>(
+          &self.device,
+          &self.render_pass,
+          &self.pipeline_cache,
+          self.viewport_extent,
+          variant,
+        )?
       };
+      self.pipelines.insert(variant, pipeline);
+    }
+    Ok(&self.pipelines[&variant])
+  }
 
-      let pipeline = self
-        .device
-        .create_graphics_pipeline(&pipeline_desc, Some(&pipeline_cache))
-        .expect("Failed to create pipeline");
-
-      (
-        vertex_shader_module,
-        fragment_shader_module,
-        descriptor_set_layout,
-        pipeline_layout,
-        pipeline_cache,
-        pipeline,
-      )
-    };
-
+  /// Records and submits the draw calls of a frame: one instanced draw per mesh and range of instances.
+  fn draw(
+    &mut self,
+    stage_matrix: &Matrix2D,
+    variant: ShaderVariant,
+    draws: &[(ShapeMesh, Range<u32>)],
+    region: Option<PixelRect>,
+  ) -> Result<(), RendererError> {
     unsafe {
       let mut command_buffer: B::CommandBuffer = self.command_pool.allocate_one(gfx_hal::command::Level::Primary);
       command_buffer.begin_primary(gfx_hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
 
       {
+        let letterbox_color = self.viewport.letterbox_color;
         let clear_values = [
           gfx_hal::command::ClearValue {
             color: gfx_hal::command::ClearColor {
-              float32: [0.0, 0.0, 0.0, 0.0],
+              float32: [
+                f32::from(letterbox_color.r) / 255.0,
+                f32::from(letterbox_color.g) / 255.0,
+                f32::from(letterbox_color.b) / 255.0,
+                f32::from(letterbox_color.a) / 255.0,
+              ],
             },
           },
           gfx_hal::command::ClearValue {
@@ -619,10 +765,17 @@ impl<B: GfxBackend> HeadlessGfxRenderer<B> {
         ];
 
         // Start of render pass
+        let (render_pass, render_area) = match region {
+          Some(region) => (
+            &self.incremental_render_pass,
+            to_gfx_rect(&region, self.viewport_extent),
+          ),
+          None => (&self.render_pass, self.viewport_extent.rect()),
+        };
         command_buffer.begin_render_pass(
-          &self.render_pass,
+          render_pass,
           &self.framebuffer,
-          self.viewport_extent.rect(),
+          render_area,
           clear_values.iter(),
           gfx_hal::command::SubpassContents::Inline,
         );
@@ -633,96 +786,77 @@ impl<B: GfxBackend> HeadlessGfxRenderer<B> {
         }];
         command_buffer.set_viewports(0, viewports);
 
-        let scissors = vec![self.viewport_extent.rect()];
-        command_buffer.set_scissors(0, scissors);
-
-        command_buffer.bind_graphics_pipeline(&pipeline);
-
-        let index_count: usize = {
-          let mesh = self.get_shape_mesh(shape_id);
-
-          command_buffer.bind_vertex_buffers(0, vec![(&mesh.vertices.buffer, 0)]);
-          command_buffer.bind_index_buffer(gfx_hal::buffer::IndexBufferView {
-            buffer: &mesh.indices.buffer,
-            offset: 0,
-            index_type: gfx_hal::IndexType::U32,
-          });
+        // Clear the stage area and restrict drawing to it, the rest is letterboxed
+        let visible_rect = self
+          .viewport
+          .visible_rect(self.viewport_extent.width, self.viewport_extent.height);
+        let visible_rect = to_gfx_rect(
+          &match region {
+            Some(region) => visible_rect.intersection(&region),
+            None => visible_rect,
+          },
+          self.viewport_extent,
+        );
+        command_buffer.clear_attachments(
+          Some(gfx_hal::command::AttachmentClear::Color {
+            index: 0,
+            value: gfx_hal::command::ClearColor {
+              float32: [0.0, 0.0, 0.0, 0.0],
+            },
+          }),
+          Some(gfx_hal::pso::ClearRect {
+            rect: visible_rect,
+            layers: 0..1,
+          }),
+        );
 
-          mesh.index_count
-        };
+        let scissors = vec![visible_rect];
+        command_buffer.set_scissors(0, scissors);
 
-        //        let pos = vec![
-        //          glm::vec3(0.0f32, 0.0f32, 0.0f32),
-        //        ];
+        let pipeline = &self.pipelines[&variant];
+        command_buffer.bind_graphics_pipeline(&pipeline.pipeline);
+        command_buffer.bind_vertex_buffers(
+          0,
+          vec![(&self.vertex_arena.buffer.buffer, 0), (&self.instance_buffer.buffer, 0)],
+        );
+        command_buffer.bind_index_buffer(gfx_hal::buffer::IndexBufferView {
+          buffer: &self.index_arena.buffer.buffer,
+          offset: 0,
+          index_type: gfx_hal::IndexType::U32,
+        });
 
-        //        for v in pos {
-        let eye_matrix = glm::ortho(
+        let view_projection = glm::ortho(
           0f32,
-          (self.viewport_extent.width * 20) as f32,
+          self.viewport_extent.width as f32,
           0f32,
-          (self.viewport_extent.height * 20) as f32,
+          self.viewport_extent.height as f32,
           -10f32,
           10f32,
-        );
-
-        let world_matrix = glm::make_mat4x4(&[
-          f64::from(matrix.scale_x) as f32,
-          f64::from(matrix.rotate_skew0) as f32,
-          0.0,
-          0.0,
-          f64::from(matrix.rotate_skew1) as f32,
-          f64::from(matrix.scale_y) as f32,
-          0.0,
-          0.0,
-          0.0,
-          0.0,
-          1.0,
-          0.0,
-          matrix.translate_x as f32,
-          matrix.translate_y as f32,
-          0.0,
-          1.0,
-        ]);
-
-        let mvp_matrix_bits: Vec<u32> = (eye_matrix * world_matrix).data.iter().map(|x| x.to_bits()).collect();
-
+        ) * glm::make_mat4x4(&stage_matrix.to_mat4());
+        let view_projection_bits: Vec<u32> = view_projection.data.iter().map(|x| x.to_bits()).collect();
         command_buffer.push_graphics_constants(
-          &pipeline_layout,
+          &pipeline.layout,
           gfx_hal::pso::ShaderStageFlags::VERTEX,
           0,
-          &mvp_matrix_bits[..],
+          &view_projection_bits[..],
         );
 
-        command_buffer.draw_indexed(0..(index_count as u32), 0, 0..1);
-        // End of render pass
-        //        }
+        for (mesh, instances) in draws.iter() {
+          command_buffer.draw_indexed(mesh.indices.clone(), mesh.vertices.start as i32, instances.clone());
+        }
       }
 
       command_buffer.finish();
 
       let cmd_queue = &mut self.queue_group.queues[0];
-      let cmd_fence = self.device.create_fence(false).expect("Failed to create fence");
-      cmd_queue.submit_without_semaphores(Some(&command_buffer), Some(&cmd_fence));
-      self
-        .device
-        .wait_for_fence(&cmd_fence, core::u64::MAX)
-        .expect("Failed to wait for fence");
-      self.device.destroy_fence(cmd_fence);
-
-      self.device.wait_idle().expect("Failed to wait for device to be idle");
-    }
-
-    unsafe {
-      self.device.destroy_graphics_pipeline(pipeline);
-      self.device.destroy_pipeline_cache(pipeline_cache);
-      self.device.destroy_pipeline_layout(pipeline_layout);
-      self.device.destroy_descriptor_set_layout(descriptor_set_layout);
-      self.device.destroy_shader_module(fragment_shader_module);
-      self.device.destroy_shader_module(vertex_shader_module);
+      let submitted = submit_and_wait::<B>(&self.device, cmd_queue, &command_buffer);
+      self.command_pool.free(Some(command_buffer));
+      submitted
     }
   }
 
-  fn download_image(&mut self) -> Image {
+  /// Reads back the last rendered frame.
+  pub fn download_image(&mut self) -> Result<Image, RendererError> {
     let cmd_queue = &mut self.queue_group.queues[0];
 
     let gfx_image = unsafe {
@@ -736,8 +870,7 @@ impl<B: GfxBackend> HeadlessGfxRenderer<B> {
         gfx_hal::image::ViewCapabilities::empty(),
         gfx_hal::memory::Properties::CPU_VISIBLE | gfx_hal::memory::Properties::COHERENT,
         &self.memories,
-      )
-      .unwrap()
+      )?
     };
 
     let image = unsafe {
@@ -818,13 +951,12 @@ impl<B: GfxBackend> HeadlessGfxRenderer<B> {
 
         copy_cmd.finish();
 
-        let copy_fence = self.device.create_fence(false).expect("Failed to create fence");
-        cmd_queue.submit_without_semaphores(Some(&copy_cmd), Some(&copy_fence));
-        self
-          .device
-          .wait_for_fence(&copy_fence, core::u64::MAX)
-          .expect("Failed to wait for fence");
-        self.device.destroy_fence(copy_fence);
+        let copied = submit_and_wait::<B>(&self.device, cmd_queue, &copy_cmd);
+        self.command_pool.free(Some(copy_cmd));
+        if let Err(e) = copied {
+          destroy_image(&self.device, gfx_image);
+          return Err(e);
+        }
       }
 
       let image_footprint = self.device.get_image_subresource_footprint(
@@ -842,22 +974,19 @@ impl<B: GfxBackend> HeadlessGfxRenderer<B> {
         stride: image_footprint.row_pitch as usize,
       };
 
-      let data = {
-        let count = ((image_footprint.slice.end - image_footprint.slice.start) as usize) / std::mem::size_of::<u8>();
-        let mapping = self
-          .device
-          .map_memory(&gfx_image.memory, image_footprint.slice)
-          .expect("Failed to map image memory (for read)");
-        let data = std::slice::from_raw_parts::<u8>(mapping as *const u8, count);
+      let count = ((image_footprint.slice.end - image_footprint.slice.start) as usize) / std::mem::size_of::<u8>();
+      match self.device.map_memory(&gfx_image.memory, image_footprint.slice) {
+        Ok(mapping) => {
+          let data = std::slice::from_raw_parts::<u8>(mapping as *const u8, count);
 
-        let data: Vec<u8> = Vec::from(data);
+          let data: Vec<u8> = Vec::from(data);
 
-        self.device.unmap_memory(&gfx_image.memory);
+          self.device.unmap_memory(&gfx_image.memory);
 
-        data
-      };
-
-      Image { meta, data }
+          Ok(Image { meta, data })
+        }
+        Err(e) => Err(map_error(e, "Failed to map image memory (for read)")),
+      }
     };
 
     unsafe {
@@ -868,17 +997,723 @@ impl<B: GfxBackend> HeadlessGfxRenderer<B> {
   }
 }
 
+impl<B: GfxBackend> MeshArena<B> {
+  unsafe fn new(
+    device: &B::Device,
+    memories: &gfx_hal::adapter::MemoryProperties,
+    usage: gfx_hal::buffer::Usage,
+    element_size: u64,
+    capacity: u32,
+  ) -> Result<Self, RendererError> {
+    let buffer = create_arena_buffer::<B>(device, memories, usage, element_size * u64::from(capacity))?;
+    Ok(Self {
+      buffer,
+      allocator: RangeAllocator::new(capacity),
+      usage,
+      element_size,
+    })
+  }
+
+  /// Allocates `size` elements, growing the buffer if there is not enough contiguous space.
+  ///
+  /// Growing copies the current content to a new buffer and waits for the copy to complete.
+  unsafe fn allocate(
+    &mut self,
+    device: &B::Device,
+    memories: &gfx_hal::adapter::MemoryProperties,
+    command_pool: &mut B::CommandPool,
+    cmd_queue: &mut B::CommandQueue,
+    size: u32,
+  ) -> Result<Range<u32>, RendererError> {
+    if let Some(range) = self.allocator.allocate(size) {
+      return Ok(range);
+    }
+
+    let old_capacity = self.allocator.capacity();
+    let capacity = u32::max(old_capacity * 2, old_capacity + size).next_power_of_two();
+    let buffer = create_arena_buffer::<B>(device, memories, self.usage, self.element_size * u64::from(capacity))?;
+
+    let mut copy_cmd = command_pool.allocate_one(gfx_hal::command::Level::Primary);
+    copy_cmd.begin_primary(gfx_hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
+    copy_cmd.copy_buffer(
+      &self.buffer.buffer,
+      &buffer.buffer,
+      &[gfx_hal::command::BufferCopy {
+        src: 0,
+        dst: 0,
+        size: self.element_size * u64::from(old_capacity),
+      }],
+    );
+    copy_cmd.finish();
+    let copied = submit_and_wait::<B>(device, cmd_queue, &copy_cmd);
+    command_pool.free(Some(copy_cmd));
+    if let Err(e) = copied {
+      destroy_buffer(device, buffer);
+      return Err(e);
+    }
+
+    destroy_buffer(device, ::std::mem::replace(&mut self.buffer, buffer));
+    self.allocator.grow(capacity);
+    Ok(self.allocator.allocate(size).unwrap())
+  }
+
+  /// Moves `ranges` to the start of a new buffer, and returns their new locations in the same order.
+  ///
+  /// The new buffer has the smallest power of two elements fitting the ranges, and at least `min_capacity`
+  /// elements. It is only created if it is smaller than the current buffer. The other ranges are freed.
+  /// Waits for the copy to complete.
+  unsafe fn compact(
+    &mut self,
+    device: &B::Device,
+    memories: &gfx_hal::adapter::MemoryProperties,
+    command_pool: &mut B::CommandPool,
+    cmd_queue: &mut B::CommandQueue,
+    ranges: &[Range<u32>],
+    min_capacity: u32,
+  ) -> Result<Vec<Range<u32>>, RendererError> {
+    let used: u32 = ranges.iter().map(|range| range.end - range.start).sum();
+    let capacity = u32::max(used.next_power_of_two(), min_capacity);
+    if capacity >= self.allocator.capacity() {
+      return Ok(ranges.to_vec());
+    }
+
+    let buffer = create_arena_buffer::<B>(device, memories, self.usage, self.element_size * u64::from(capacity))?;
+    let mut allocator = RangeAllocator::new(capacity);
+    let mut moved: Vec<Range<u32>> = Vec::with_capacity(ranges.len());
+    let mut copies: Vec<gfx_hal::command::BufferCopy> = Vec::new();
+    for range in ranges.iter() {
+      let size = range.end - range.start;
+      let target = allocator.allocate(size).unwrap();
+      if size > 0 {
+        copies.push(gfx_hal::command::BufferCopy {
+          src: self.element_size * u64::from(range.start),
+          dst: self.element_size * u64::from(target.start),
+          size: self.element_size * u64::from(size),
+        });
+      }
+      moved.push(target);
+    }
+
+    if !copies.is_empty() {
+      let mut copy_cmd = command_pool.allocate_one(gfx_hal::command::Level::Primary);
+      copy_cmd.begin_primary(gfx_hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
+      copy_cmd.copy_buffer(&self.buffer.buffer, &buffer.buffer, &copies);
+      copy_cmd.finish();
+      let copied = submit_and_wait::<B>(device, cmd_queue, &copy_cmd);
+      command_pool.free(Some(copy_cmd));
+      if let Err(e) = copied {
+        destroy_buffer(device, buffer);
+        return Err(e);
+      }
+    }
+
+    destroy_buffer(device, ::std::mem::replace(&mut self.buffer, buffer));
+    self.allocator = allocator;
+    Ok(moved)
+  }
+
+  /// Size of the buffer, in bytes.
+  fn size(&self) -> u64 {
+    self.element_size * u64::from(self.allocator.capacity())
+  }
+
+  /// Replaces the buffer by a buffer of `capacity` elements if it is larger and no range is allocated.
+  unsafe fn shrink_if_empty(
+    &mut self,
+    device: &B::Device,
+    memories: &gfx_hal::adapter::MemoryProperties,
+    capacity: u32,
+  ) -> Result<(), RendererError> {
+    if self.allocator.used() != 0 || self.allocator.capacity() <= capacity {
+      return Ok(());
+    }
+    let buffer = create_arena_buffer::<B>(device, memories, self.usage, self.element_size * u64::from(capacity))?;
+    destroy_buffer(device, ::std::mem::replace(&mut self.buffer, buffer));
+    self.allocator = RangeAllocator::new(capacity);
+    Ok(())
+  }
+
+  unsafe fn destroy(self, device: &B::Device) -> () {
+    destroy_buffer(device, self.buffer);
+  }
+}
+
+unsafe fn create_arena_buffer<B: GfxBackend>(
+  device: &B::Device,
+  memories: &gfx_hal::adapter::MemoryProperties,
+  usage: gfx_hal::buffer::Usage,
+  size: u64,
+) -> Result<AttachedBuffer<B>, RendererError> {
+  create_buffer::<B>(
+    device,
+    usage | gfx_hal::buffer::Usage::TRANSFER_SRC | gfx_hal::buffer::Usage::TRANSFER_DST,
+    gfx_hal::memory::Properties::DEVICE_LOCAL,
+    size,
+    memories,
+  )
+}
+
+unsafe fn create_instance_buffer<B: GfxBackend>(
+  device: &B::Device,
+  memories: &gfx_hal::adapter::MemoryProperties,
+  capacity: u32,
+) -> Result<AttachedBuffer<B>, RendererError> {
+  create_buffer::<B>(
+    device,
+    gfx_hal::buffer::Usage::VERTEX,
+    gfx_hal::memory::Properties::CPU_VISIBLE | gfx_hal::memory::Properties::COHERENT,
+    (::std::mem::size_of::<Instance>() as u64) * u64::from(capacity),
+    memories,
+  )
+}
+
+/// Creates the shared vertex and index buffers, and the instance buffer.
+unsafe fn create_draw_buffers<B: GfxBackend>(
+  device: &B::Device,
+  memories: &gfx_hal::adapter::MemoryProperties,
+) -> Result<(MeshArena<B>, MeshArena<B>, AttachedBuffer<B>), RendererError> {
+  let vertex_arena = MeshArena::<B>::new(
+    device,
+    memories,
+    gfx_hal::buffer::Usage::VERTEX,
+    ::std::mem::size_of::<Vertex>() as u64,
+    INITIAL_VERTEX_CAPACITY,
+  )?;
+  let index_arena = match MeshArena::<B>::new(
+    device,
+    memories,
+    gfx_hal::buffer::Usage::INDEX,
+    ::std::mem::size_of::<u32>() as u64,
+    INITIAL_INDEX_CAPACITY,
+  ) {
+    Ok(index_arena) => index_arena,
+    Err(e) => {
+      vertex_arena.destroy(device);
+      return Err(e);
+    }
+  };
+  match create_instance_buffer::<B>(device, memories, INITIAL_INSTANCE_CAPACITY) {
+    Ok(instance_buffer) => Ok((vertex_arena, index_arena, instance_buffer)),
+    Err(e) => {
+      index_arena.destroy(device);
+      vertex_arena.destroy(device);
+      Err(e)
+    }
+  }
+}
+
+/// Copies the queued meshes to the shared buffers, using a single staging buffer and submission.
+unsafe fn upload_meshes<B: GfxBackend>(
+  device: &B::Device,
+  memories: &gfx_hal::adapter::MemoryProperties,
+  command_pool: &mut B::CommandPool,
+  cmd_queue: &mut B::CommandQueue,
+  (vertex_buffer, index_buffer): (&AttachedBuffer<B>, &AttachedBuffer<B>),
+  uploads: &MeshUploads,
+) -> Result<(), RendererError> {
+  let vertex_size = ::std::mem::size_of::<Vertex>() as u64;
+  let index_size = ::std::mem::size_of::<u32>() as u64;
+  let vertices_size = vertex_size * (uploads.vertices.len() as u64);
+  let indices_size = index_size * (uploads.indices.len() as u64);
+  if vertices_size + indices_size == 0 {
+    return Ok(());
+  }
+
+  let staging_buffer = create_buffer::<B>(
+    device,
+    gfx_hal::buffer::Usage::TRANSFER_SRC,
+    gfx_hal::memory::Properties::CPU_VISIBLE | gfx_hal::memory::Properties::COHERENT,
+    vertices_size + indices_size,
+    memories,
+  )?;
+
+  let result = match device.map_memory(&staging_buffer.memory, 0..staging_buffer.capacity) {
+    Err(e) => Err(map_error(e, "Failed to map staging memory")),
+    Ok(mapping) => {
+      std::ptr::copy_nonoverlapping(
+        uploads.vertices.as_ptr(),
+        mapping as *mut Vertex,
+        uploads.vertices.len(),
+      );
+      std::ptr::copy_nonoverlapping(
+        uploads.indices.as_ptr(),
+        mapping.offset(vertices_size as isize) as *mut u32,
+        uploads.indices.len(),
+      );
+      device.unmap_memory(&staging_buffer.memory);
+
+      let to_copy =
+        |copies: &[(u32, u32, u32)], src_offset: u64, element_size: u64| -> Vec<gfx_hal::command::BufferCopy> {
+          copies
+            .iter()
+            .filter(|(_, _, size)| *size > 0)
+            .map(|(src, dst, size)| gfx_hal::command::BufferCopy {
+              src: src_offset + element_size * u64::from(*src),
+              dst: element_size * u64::from(*dst),
+              size: element_size * u64::from(*size),
+            })
+            .collect()
+        };
+
+      let mut copy_cmd = command_pool.allocate_one(gfx_hal::command::Level::Primary);
+      copy_cmd.begin_primary(gfx_hal::command::CommandBufferFlags::ONE_TIME_SUBMIT);
+      let vertex_copies = to_copy(&uploads.vertex_copies, 0, vertex_size);
+      if !vertex_copies.is_empty() {
+        copy_cmd.copy_buffer(&staging_buffer.buffer, &vertex_buffer.buffer, &vertex_copies);
+      }
+      let index_copies = to_copy(&uploads.index_copies, vertices_size, index_size);
+      if !index_copies.is_empty() {
+        copy_cmd.copy_buffer(&staging_buffer.buffer, &index_buffer.buffer, &index_copies);
+      }
+      copy_cmd.finish();
+      let copied = submit_and_wait::<B>(device, cmd_queue, &copy_cmd);
+      command_pool.free(Some(copy_cmd));
+      copied
+    }
+  };
+
+  destroy_buffer(device, staging_buffer);
+
+  result
+}
+
+/// Creates the graphics pipeline drawing the shapes with a shader variant.
+/// Creates the render pass drawing to the color and depth attachments.
+///
+/// With `keep_color`, the color attachment is loaded instead of cleared: the previous frame is kept outside of
+/// the render area.
+unsafe fn create_render_pass<B: GfxBackend>(
+  device: &B::Device,
+  color_format: gfx_hal::format::Format,
+  depth_format: gfx_hal::format::Format,
+  keep_color: bool,
+) -> Result<B::RenderPass, gfx_hal::device::OutOfMemory> {
+  let (color_load, color_layout) = if keep_color {
+    // The previous frame left the image ready to be downloaded
+    (
+      gfx_hal::pass::AttachmentLoadOp::Load,
+      gfx_hal::image::Layout::TransferSrcOptimal,
+    )
+  } else {
+    (
+      gfx_hal::pass::AttachmentLoadOp::Clear,
+      gfx_hal::image::Layout::Undefined,
+    )
+  };
+  let color_attachment: gfx_hal::pass::Attachment = gfx_hal::pass::Attachment {
+    format: Some(color_format),
+    samples: 1,
+    ops: gfx_hal::pass::AttachmentOps {
+      load: color_load,
+      store: gfx_hal::pass::AttachmentStoreOp::Store,
+    },
+    stencil_ops: gfx_hal::pass::AttachmentOps {
+      load: gfx_hal::pass::AttachmentLoadOp::DontCare,
+      store: gfx_hal::pass::AttachmentStoreOp::DontCare,
+    },
+    layouts: std::ops::Range {
+      start: color_layout,
+      end: gfx_hal::image::Layout::TransferSrcOptimal,
+    },
+  };
+  let depth_attachment: gfx_hal::pass::Attachment = gfx_hal::pass::Attachment {
+    format: Some(depth_format),
+    samples: 1,
+    ops: gfx_hal::pass::AttachmentOps {
+      load: gfx_hal::pass::AttachmentLoadOp::Clear,
+      store: gfx_hal::pass::AttachmentStoreOp::DontCare,
+    },
+    stencil_ops: gfx_hal::pass::AttachmentOps {
+      load: gfx_hal::pass::AttachmentLoadOp::DontCare,
+      store: gfx_hal::pass::AttachmentStoreOp::DontCare,
+    },
+    layouts: std::ops::Range {
+      start: gfx_hal::image::Layout::Undefined,
+      end: gfx_hal::image::Layout::DepthStencilAttachmentOptimal,
+    },
+  };
+  let attachments = [color_attachment, depth_attachment];
+
+  let color_ref: gfx_hal::pass::AttachmentRef = (0, gfx_hal::image::Layout::ColorAttachmentOptimal);
+  let depth_ref: gfx_hal::pass::AttachmentRef = (1, gfx_hal::image::Layout::DepthStencilAttachmentOptimal);
+
+  let subpass_desc: gfx_hal::pass::SubpassDesc = gfx_hal::pass::SubpassDesc {
+    colors: &[color_ref],
+    depth_stencil: Some(&depth_ref),
+    inputs: &[],
+    resolves: &[],
+    preserves: &[],
+  };
+
+  let dependencies = [
+    gfx_hal::pass::SubpassDependency {
+      passes: std::ops::Range {
+        start: gfx_hal::pass::SubpassRef::External,
+        end: gfx_hal::pass::SubpassRef::Pass(0),
+      },
+      stages: std::ops::Range {
+        start: gfx_hal::pso::PipelineStage::BOTTOM_OF_PIPE,
+        end: gfx_hal::pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+      },
+      accesses: std::ops::Range {
+        start: gfx_hal::image::Access::MEMORY_READ,
+        end: gfx_hal::image::Access::COLOR_ATTACHMENT_READ | gfx_hal::image::Access::COLOR_ATTACHMENT_WRITE,
+      },
+      flags: gfx_hal::memory::Dependencies::empty(),
+    },
+    gfx_hal::pass::SubpassDependency {
+      passes: std::ops::Range {
+        start: gfx_hal::pass::SubpassRef::Pass(0),
+        end: gfx_hal::pass::SubpassRef::External,
+      },
+      stages: std::ops::Range {
+        start: gfx_hal::pso::PipelineStage::COLOR_ATTACHMENT_OUTPUT,
+        end: gfx_hal::pso::PipelineStage::BOTTOM_OF_PIPE,
+      },
+      accesses: std::ops::Range {
+        start: gfx_hal::image::Access::COLOR_ATTACHMENT_READ | gfx_hal::image::Access::COLOR_ATTACHMENT_WRITE,
+        end: gfx_hal::image::Access::MEMORY_READ,
+      },
+      flags: gfx_hal::memory::Dependencies::empty(),
+    },
+  ];
+
+  device.create_render_pass(&attachments, &[subpass_desc], &dependencies)
+}
+
+unsafe fn create_pipeline<B: GfxBackend>(
+  device: &B::Device,
+  render_pass: &B::RenderPass,
+  pipeline_cache: &B::PipelineCache,
+  viewport_extent: Extent,
+  variant: ShaderVariant,
+) -> Result<GfxPipeline<B>, RendererError> {
+  let vertex_shader_module = <B as ShaderBackend>::create_shader_module(device, variant, gfx_hal::pso::Stage::Vertex)?;
+  let fragment_shader_module =
+    match <B as ShaderBackend>::create_shader_module(device, variant, gfx_hal::pso::Stage::Fragment) {
+      Ok(module) => module,
+      Err(e) => {
+        device.destroy_shader_module(vertex_shader_module);
+        return Err(e);
+      }
+    };
+
+  let pipeline = create_pipeline_with_shaders::<B>(
+    device,
+    render_pass,
+    pipeline_cache,
+    viewport_extent,
+    &vertex_shader_module,
+    &fragment_shader_module,
+  );
+
+  device.destroy_shader_module(fragment_shader_module);
+  device.destroy_shader_module(vertex_shader_module);
+
+  pipeline
+}
+
+unsafe fn create_pipeline_with_shaders<B: GfxBackend>(
+  device: &B::Device,
+  render_pass: &B::RenderPass,
+  pipeline_cache: &B::PipelineCache,
+  viewport_extent: Extent,
+  vertex_shader_module: &B::ShaderModule,
+  fragment_shader_module: &B::ShaderModule,
+) -> Result<GfxPipeline<B>, RendererError> {
+  let descriptor_set_layout = device.create_descriptor_set_layout(&[], &[])?;
+
+  let constant_size: usize = ::std::mem::size_of::<glm::TMat4<f32>>();
+  let push_constants: Vec<(gfx_hal::pso::ShaderStageFlags, core::ops::Range<u32>)> = vec![(
+    gfx_hal::pso::ShaderStageFlags::VERTEX,
+    0..((constant_size / ::std::mem::size_of::<f32>()) as u32),
+  )];
+
+  let layout = match device.create_pipeline_layout(&[], push_constants) {
+    Ok(layout) => layout,
+    Err(e) => {
+      device.destroy_descriptor_set_layout(descriptor_set_layout);
+      return Err(e.into());
+    }
+  };
+
+  let shaders = gfx_hal::pso::GraphicsShaderSet {
+    vertex: gfx_hal::pso::EntryPoint {
+      entry: "main",
+      module: vertex_shader_module,
+      specialization: gfx_hal::pso::Specialization {
+        constants: Cow::Owned(Vec::new()),
+        data: Cow::Owned(Vec::new()),
+      },
+    },
+    hull: None,
+    domain: None,
+    geometry: None,
+    fragment: Some(gfx_hal::pso::EntryPoint {
+      entry: "main",
+      module: fragment_shader_module,
+      specialization: gfx_hal::pso::Specialization {
+        constants: Cow::Owned(Vec::new()),
+        data: Cow::Owned(Vec::new()),
+      },
+    }),
+  };
+
+  let rasterizer = gfx_hal::pso::Rasterizer {
+    depth_clamping: false,
+    polygon_mode: gfx_hal::pso::PolygonMode::Fill,
+    cull_face: gfx_hal::pso::Face::NONE,
+    front_face: gfx_hal::pso::FrontFace::Clockwise,
+    depth_bias: None,
+    conservative: false,
+  };
+
+  let vertex_buffers: Vec<gfx_hal::pso::VertexBufferDesc> = vec![
+    gfx_hal::pso::VertexBufferDesc {
+      binding: 0,
+      stride: (::std::mem::size_of::<Vertex>()) as u32,
+      rate: ::gfx_hal::pso::VertexInputRate::Vertex,
+    },
+    gfx_hal::pso::VertexBufferDesc {
+      binding: 1,
+      stride: (::std::mem::size_of::<Instance>()) as u32,
+      rate: ::gfx_hal::pso::VertexInputRate::Instance(1),
+    },
+  ];
+  let attributes: Vec<gfx_hal::pso::AttributeDesc> = vec![
+    // position
+    gfx_hal::pso::AttributeDesc {
+      binding: 0,
+      location: 0,
+      element: gfx_hal::pso::Element {
+        format: gfx_hal::format::Format::Rgb32Sfloat,
+        offset: offset_of!(Vertex, position) as u32,
+      },
+    },
+    // color
+    gfx_hal::pso::AttributeDesc {
+      binding: 0,
+      location: 1,
+      element: gfx_hal::pso::Element {
+        format: gfx_hal::format::Format::Rgb32Sfloat,
+        offset: offset_of!(Vertex, color) as u32,
+      },
+    },
+    // curve
+    gfx_hal::pso::AttributeDesc {
+      binding: 0,
+      location: 2,
+      element: gfx_hal::pso::Element {
+        format: gfx_hal::format::Format::Rgb32Sfloat,
+        offset: offset_of!(Vertex, curve) as u32,
+      },
+    },
+    // matrix (first row)
+    gfx_hal::pso::AttributeDesc {
+      binding: 1,
+      location: 3,
+      element: gfx_hal::pso::Element {
+        format: gfx_hal::format::Format::Rgb32Sfloat,
+        offset: offset_of!(Instance, matrix_x) as u32,
+      },
+    },
+    // matrix (second row)
+    gfx_hal::pso::AttributeDesc {
+      binding: 1,
+      location: 4,
+      element: gfx_hal::pso::Element {
+        format: gfx_hal::format::Format::Rgb32Sfloat,
+        offset: offset_of!(Instance, matrix_y) as u32,
+      },
+    },
+    // color transform (multiplication)
+    gfx_hal::pso::AttributeDesc {
+      binding: 1,
+      location: 5,
+      element: gfx_hal::pso::Element {
+        format: gfx_hal::format::Format::Rgba32Sfloat,
+        offset: offset_of!(Instance, color_mult) as u32,
+      },
+    },
+    // color transform (addition)
+    gfx_hal::pso::AttributeDesc {
+      binding: 1,
+      location: 6,
+      element: gfx_hal::pso::Element {
+        format: gfx_hal::format::Format::Rgba32Sfloat,
+        offset: offset_of!(Instance, color_add) as u32,
+      },
+    },
+  ];
+
+  let input_assembler: gfx_hal::pso::InputAssemblerDesc =
+    gfx_hal::pso::InputAssemblerDesc::new(gfx_hal::pso::Primitive::TriangleList);
+
+  let blender = {
+    // The fragment shader outputs the coverage of analytic curves as alpha
+    let blend_state: Option<gfx_hal::pso::BlendState> = Some(gfx_hal::pso::BlendState {
+      color: gfx_hal::pso::BlendOp::Add {
+        src: gfx_hal::pso::Factor::SrcAlpha,
+        dst: gfx_hal::pso::Factor::OneMinusSrcAlpha,
+      },
+      alpha: gfx_hal::pso::BlendOp::Add {
+        src: gfx_hal::pso::Factor::One,
+        dst: gfx_hal::pso::Factor::OneMinusSrcAlpha,
+      },
+    });
+    gfx_hal::pso::BlendDesc {
+      logic_op: Some(gfx_hal::pso::LogicOp::Copy),
+      targets: vec![gfx_hal::pso::ColorBlendDesc {
+        mask: gfx_hal::pso::ColorMask::ALL,
+        blend: blend_state,
+      }],
+    }
+  };
+
+  let depth_stencil = gfx_hal::pso::DepthStencilDesc {
+    depth: Some(gfx_hal::pso::DepthTest {
+      fun: gfx_hal::pso::Comparison::LessEqual,
+      write: true,
+    }),
+    depth_bounds: false,
+    stencil: None,
+  };
+
+  let multisampling: Option<gfx_hal::pso::Multisampling> = None;
+
+  let baked_states = gfx_hal::pso::BakedStates {
+    viewport: Some(gfx_hal::pso::Viewport {
+      rect: viewport_extent.rect(),
+      depth: (0.0..1.0),
+    }),
+    scissor: None,
+    blend_color: None,
+    depth_bounds: None,
+  };
+
+  let pipeline_flags: gfx_hal::pso::PipelineCreationFlags = gfx_hal::pso::PipelineCreationFlags::empty();
+
+  let pipeline_desc = gfx_hal::pso::GraphicsPipelineDesc {
+    shaders,
+    rasterizer,
+    vertex_buffers,
+    attributes,
+    input_assembler,
+    blender,
+    depth_stencil,
+    multisampling,
+    baked_states,
+    layout: &layout,
+    subpass: gfx_hal::pass::Subpass {
+      index: 0,
+      main_pass: render_pass,
+    },
+    flags: pipeline_flags,
+    parent: gfx_hal::pso::BasePipeline::None,
+  };
+
+  let pipeline = device
+    .create_graphics_pipeline(&pipeline_desc, Some(pipeline_cache))
+    .map_err(|e| match e {
+      gfx_hal::pso::CreationError::OutOfMemory(_) => RendererError::OutOfMemory,
+      _ => RendererError::Gfx("Failed to create pipeline"),
+    });
+
+  match pipeline {
+    Ok(pipeline) => Ok(GfxPipeline {
+      descriptor_set_layout,
+      layout,
+      pipeline,
+    }),
+    Err(e) => {
+      device.destroy_pipeline_layout(layout);
+      device.destroy_descriptor_set_layout(descriptor_set_layout);
+      Err(e)
+    }
+  }
+}
+
+unsafe fn destroy_pipeline<B: GfxBackend>(device: &B::Device, pipeline: GfxPipeline<B>) -> () {
+  device.destroy_graphics_pipeline(pipeline.pipeline);
+  device.destroy_pipeline_layout(pipeline.layout);
+  device.destroy_descriptor_set_layout(pipeline.descriptor_set_layout);
+}
+
+/// Groups consecutive equal items, returning each group with the range of its instances.
+///
+/// Only consecutive items are merged, so drawing the groups in order preserves the painter's order.
+pub(crate) fn group_instances<T: PartialEq + Clone>(items: &[T]) -> Vec<(T, Range<u32>)> {
+  let mut groups: Vec<(T, Range<u32>)> = Vec::new();
+  for (index, item) in items.iter().enumerate() {
+    let index = index as u32;
+    match groups.last_mut() {
+      Some((last, instances)) if last == item => instances.end = index + 1,
+      _ => groups.push((item.clone(), index..(index + 1))),
+    }
+  }
+  groups
+}
+
+/// Submits a command buffer and blocks until its execution is complete.
+unsafe fn submit_and_wait<B: GfxBackend>(
+  device: &B::Device,
+  cmd_queue: &mut B::CommandQueue,
+  command_buffer: &B::CommandBuffer,
+) -> Result<(), RendererError> {
+  let fence = device.create_fence(false)?;
+  cmd_queue.submit_without_semaphores(Some(command_buffer), Some(&fence));
+  let result = device.wait_for_fence(&fence, core::u64::MAX);
+  device.destroy_fence(fence);
+  result?;
+  Ok(())
+}
+
+/// Converts a rectangle of pixels to a gfx rectangle, clamped to the output `extent`.
+fn to_gfx_rect(rect: &PixelRect, extent: Extent) -> gfx_hal::pso::Rect {
+  let output = PixelRect {
+    x: 0,
+    y: 0,
+    width: extent.width as i32,
+    height: extent.height as i32,
+  };
+  let rect = rect.intersection(&output);
+  gfx_hal::pso::Rect {
+    // An empty intersection may start outside of the output
+    x: i32::min(rect.x, output.width) as i16,
+    y: i32::min(rect.y, output.height) as i16,
+    w: rect.width as i16,
+    h: rect.height as i16,
+  }
+}
+
+fn map_error(error: gfx_hal::device::MapError, message: &'static str) -> RendererError {
+  match error {
+    gfx_hal::device::MapError::OutOfMemory(_) => RendererError::OutOfMemory,
+    _ => RendererError::Gfx(message),
+  }
+}
+
 impl<B: GfxBackend> Drop for HeadlessGfxRenderer<B> {
   fn drop(&mut self) -> () {
     unsafe {
       use core::ptr::read;
 
-      self.device.wait_idle().expect("Failed to wait for device to be idle");
+      if let Err(e) = self.device.wait_idle() {
+        warn!("Failed to wait for device to be idle: {:?}", e);
+      }
 
-      for (_, mesh) in self.shape_meshes.drain() {
-        destroy_buffer(&self.device, ManuallyDrop::into_inner(mesh.indices));
-        destroy_buffer(&self.device, ManuallyDrop::into_inner(mesh.vertices));
+      self.shape_meshes.clear();
+      destroy_buffer(&self.device, ManuallyDrop::into_inner(read(&self.instance_buffer)));
+      ManuallyDrop::into_inner(read(&self.index_arena)).destroy(&self.device);
+      ManuallyDrop::into_inner(read(&self.vertex_arena)).destroy(&self.device);
+      for (_, pipeline) in self.pipelines.drain() {
+        destroy_pipeline(&self.device, pipeline);
       }
+      destroy_pipeline_cache::<B>(
+        &self.device,
+        ManuallyDrop::into_inner(read(&self.pipeline_cache)),
+        self.pipeline_cache_path.as_ref().map(PathBuf::as_path),
+      );
 
       self
         .device
@@ -886,6 +1721,9 @@ impl<B: GfxBackend> Drop for HeadlessGfxRenderer<B> {
       self
         .device
         .destroy_render_pass(ManuallyDrop::into_inner(read(&self.render_pass)));
+      self
+        .device
+        .destroy_render_pass(ManuallyDrop::into_inner(read(&self.incremental_render_pass)));
 
       self
         .device
@@ -903,10 +1741,182 @@ impl<B: GfxBackend> Drop for HeadlessGfxRenderer<B> {
   }
 }
 
+/// Renders the stages offscreen, use `download_image` to read the frames back.
+///
+/// The stage background is not drawn: the output is cleared with the letterbox color of the viewport. Unless
+/// disabled with `set_incremental`, only the area changed since the previous stage is redrawn.
+impl<B: GfxBackend> SwfRenderer for HeadlessGfxRenderer<B> {
+  fn render(&mut self, stage: Stage) -> Result<(), RendererError> {
+    let damage = match self.previous_stage {
+      Some(ref previous_stage) if self.incremental => stage_damage(previous_stage, &stage, &self.shape_store),
+      _ => Damage::Full,
+    };
+    let region = match damage {
+      // Nothing to redraw: an empty region
+      Damage::Unchanged => Some(PixelRect {
+        x: 0,
+        y: 0,
+        width: 0,
+        height: 0,
+      }),
+      Damage::Region(bounds) => {
+        let stage_matrix = self
+          .viewport
+          .stage_matrix(self.viewport_extent.width, self.viewport_extent.height);
+        // Include the pixels partially covered by the antialiased edges
+        let bounds = stage_matrix.transform_bounds(&bounds).inflate(1.0);
+        let visible_rect = self
+          .viewport
+          .visible_rect(self.viewport_extent.width, self.viewport_extent.height);
+        Some(PixelRect::from_bounds(&bounds).intersection(&visible_rect))
+      }
+      Damage::Full => None,
+    };
+
+    let display_list = flatten_stage(&stage);
+    let rendered = match region {
+      Some(ref region) if region.is_empty() => Ok(()),
+      region => self.render_stage(&display_list, region),
+    };
+    self.stage = Some(display_list);
+    // A failed frame may be partially drawn
+    self.previous_stage = match rendered {
+      Ok(()) => Some(stage),
+      Err(_) => None,
+    };
+    rendered
+  }
+
+  /// Recreates the offscreen images and the framebuffer, the next stage is fully drawn.
+  fn resize(&mut self, width: u32, height: u32) -> Result<(), RendererError> {
+    if width == self.viewport_extent.width && height == self.viewport_extent.height {
+      return Ok(());
+    }
+    if width == 0 || height == 0 {
+      return Err(RendererError::Gfx("Invalid size of headless renderer"));
+    }
+    let extent = Extent {
+      width,
+      height,
+      depth: 1,
+    };
+    self.device.wait_idle()?;
+
+    let ((color_image, color_image_view), (depth_image, depth_image_view)) = unsafe {
+      create_images::<B>(
+        &self.device,
+        extent,
+        self.color_format,
+        self.depth_format,
+        &self.memories,
+      )?
+    };
+    let framebuffer = unsafe {
+      let image_views = vec![&color_image_view, &depth_image_view];
+      self
+        .device
+        .create_framebuffer(&self.render_pass, image_views.into_iter(), extent)
+    };
+    let framebuffer = match framebuffer {
+      Ok(framebuffer) => framebuffer,
+      Err(_) => {
+        unsafe {
+          destroy_images(
+            &self.device,
+            (color_image, color_image_view),
+            (depth_image, depth_image_view),
+          );
+        }
+        return Err(RendererError::OutOfMemory);
+      }
+    };
+
+    unsafe {
+      use core::ptr::read;
+
+      self
+        .device
+        .destroy_framebuffer(ManuallyDrop::into_inner(read(&self.framebuffer)));
+      destroy_images(
+        &self.device,
+        (
+          ManuallyDrop::into_inner(read(&self.color_image)),
+          ManuallyDrop::into_inner(read(&self.color_image_view)),
+        ),
+        (
+          ManuallyDrop::into_inner(read(&self.depth_image)),
+          ManuallyDrop::into_inner(read(&self.depth_image_view)),
+        ),
+      );
+      // The pipelines use the size of the output, they are created again by `get_pipeline`
+      for (_, pipeline) in self.pipelines.drain() {
+        destroy_pipeline(&self.device, pipeline);
+      }
+    }
+    self.color_image = ManuallyDrop::new(color_image);
+    self.color_image_view = ManuallyDrop::new(color_image_view);
+    self.depth_image = ManuallyDrop::new(depth_image);
+    self.depth_image_view = ManuallyDrop::new(depth_image_view);
+    self.framebuffer = ManuallyDrop::new(framebuffer);
+    self.viewport_extent = extent;
+    self.invalidate();
+    Ok(())
+  }
+
+  fn set_viewport(&mut self, viewport: Viewport) -> () {
+    HeadlessGfxRenderer::set_viewport(self, viewport)
+  }
+}
+
+impl<B: GfxBackend> ClientAssetStore for HeadlessGfxRenderer<B> {
+  fn create_movie(&mut self) -> MovieId {
+    self.asset_ids.create_movie()
+  }
+
+  fn release_movie(&mut self, movie: MovieId) -> () {
+    for id in self.asset_ids.release_movie(movie) {
+      self.shape_store.remove(id);
+      self.release_shape_meshes(id);
+    }
+    self.trim_arenas();
+    // The released shapes may be displayed by the previous frame
+    self.invalidate();
+  }
+
+  fn register_shape(&mut self, movie: MovieId, tag: &DefineShape) -> Result<ShapeId, RendererError> {
+    let id = ShapeId(self.asset_ids.asset_id(movie, tag.id));
+    self.redefine_shape(id, tag)?;
+    Ok(id)
+  }
+
+  fn register_morph_shape(&mut self, movie: MovieId, tag: &DefineMorphShape) -> Result<MorphShapeId, RendererError> {
+    // Morph shapes are not rendered yet
+    Ok(MorphShapeId(self.asset_ids.asset_id(movie, tag.id)))
+  }
+
+  fn redefine_shape(&mut self, id: ShapeId, tag: &DefineShape) -> Result<(), RendererError> {
+    self.shape_store.define_shape(id.0, tag)?;
+    self.release_shape_meshes(id.0);
+    // The displayed shapes with this id changed
+    self.invalidate();
+    Ok(())
+  }
+
+  fn redefine_morph_shape(&mut self, _id: MorphShapeId, _tag: &DefineMorphShape) -> Result<(), RendererError> {
+    Ok(())
+  }
+
+  fn release_shape(&mut self, id: ShapeId) -> () {
+    self.remove_shape(id.0);
+  }
+
+  fn release_morph_shape(&mut self, _id: MorphShapeId) -> () {}
+}
+
 impl<B: GfxBackend> Renderer for HeadlessGfxRenderer<B> {
-  // TODO: Pass a list instead of a single item
-  fn set_stage(&mut self, display_list: DisplayItem) -> () {
+  fn set_stage(&mut self, display_list: Vec<DisplayItem>) -> () {
     self.stage = Some(display_list);
+    self.invalidate();
   }
 
   //  let mut tessellator = FillTessellator::new();
@@ -0,0 +1,252 @@
+use lyon::math::Point;
+use lyon::path::{Path, PathEvent};
+use lyon::tessellation::FillRule;
+
+use crate::bounds::HAIRLINE_WIDTH;
+use crate::renderer::{GfxSymbol, ShapeStore};
+use crate::stage::{DisplayPrimitive, Stage};
+use crate::Shape;
+
+/// Tolerance used to flatten curves, in twips.
+const FLATTENING_TOLERANCE: f32 = 1.0;
+
+/// Geometry used to test if a point is inside a shape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HitTestMode {
+  /// Test against the bounds of the shapes.
+  BoundingBox,
+  /// Test against the exact fills and strokes of the shapes (the "shape flag" of ActionScript).
+  Exact,
+}
+
+/// Result of a successful hit-test.
+#[derive(Debug)]
+pub struct Hit<'a> {
+  /// Top-most primitive containing the point.
+  ///
+  /// Buttons are hit as a whole, using their hit area.
+  pub primitive: &'a DisplayPrimitive,
+  /// Indexes leading to `primitive`, starting in `Stage::display_root`.
+  ///
+  /// For containers and buttons, the next index refers to their `children`; for clip layers it refers to
+  /// the `children` of the layer (the mask itself is never hit).
+  pub path: Vec<usize>,
+}
+
+/// Returns the top-most primitive of `stage` containing `point`.
+///
+/// `point` is in stage coordinates (twips).
+/// Morph shapes are not supported yet and are never hit.
+pub fn hit_test<'a>(stage: &'a Stage, shapes: &ShapeStore, point: Point, mode: HitTestMode) -> Option<Hit<'a>> {
+  let mut path: Vec<usize> = Vec::new();
+  let primitive = hit_test_list(&stage.display_root, shapes, point, mode, &mut path)?;
+  Some(Hit { primitive, path })
+}
+
+fn hit_test_list<'a>(
+  primitives: &'a [DisplayPrimitive],
+  shapes: &ShapeStore,
+  point: Point,
+  mode: HitTestMode,
+  path: &mut Vec<usize>,
+) -> Option<&'a DisplayPrimitive> {
+  for (index, primitive) in primitives.iter().enumerate().rev() {
+    path.push(index);
+    if let Some(hit) = hit_test_primitive(primitive, shapes, point, mode, path) {
+      return Some(hit);
+    }
+    path.pop();
+  }
+  None
+}
+
+fn hit_test_primitive<'a>(
+  primitive: &'a DisplayPrimitive,
+  shapes: &ShapeStore,
+  point: Point,
+  mode: HitTestMode,
+  path: &mut Vec<usize>,
+) -> Option<&'a DisplayPrimitive> {
+  match primitive {
+    DisplayPrimitive::Shape(ref shape) => {
+      let local = shape.matrix.invert()?.transform_point(point);
+      match shapes.get(shape.id.0) {
+        Some(GfxSymbol::Shape(ref symbol)) => {
+          let is_hit = match mode {
+            HitTestMode::BoundingBox => {
+              let bounds = &symbol.bounds;
+              bounds.x_min as f32 <= local.x
+                && local.x <= bounds.x_max as f32
+                && bounds.y_min as f32 <= local.y
+                && local.y <= bounds.y_max as f32
+            }
+            HitTestMode::Exact => shape_contains(&symbol.shape, local),
+          };
+          if is_hit {
+            Some(primitive)
+          } else {
+            None
+          }
+        }
+        _ => None,
+      }
+    }
+    DisplayPrimitive::MorphShape(_) => None,
+    DisplayPrimitive::Container(ref container) => {
+      let local = container.matrix.invert()?.transform_point(point);
+      hit_test_list(&container.children, shapes, local, mode, path)
+    }
+    DisplayPrimitive::Button(ref button) => {
+      let local = button.matrix.invert()?.transform_point(point);
+      let mut hit_area_path: Vec<usize> = Vec::new();
+      match hit_test_list(&button.hit_area, shapes, local, mode, &mut hit_area_path) {
+        Some(_) => Some(primitive),
+        None => None,
+      }
+    }
+    DisplayPrimitive::Clip(ref clip) => {
+      let mut mask_path: Vec<usize> = Vec::new();
+      hit_test_primitive(&clip.mask, shapes, point, mode, &mut mask_path)?;
+      hit_test_list(&clip.children, shapes, point, mode, path)
+    }
+  }
+}
+
+/// Tests if a point (in the local coordinates of the shape) is inside one of its fills or strokes.
+fn shape_contains(shape: &Shape, point: Point) -> bool {
+  shape.paths.iter().any(|styled_path| {
+    let in_fill =
+      styled_path.fill.is_some() && contains_fill(&flatten_path(&styled_path.path, true), point, shape.fill_rule);
+    let in_line = match styled_path.line {
+      Some(ref line) => {
+        let half_width = f32::max(f32::from(line.width), HAIRLINE_WIDTH) / 2.0;
+        flatten_path(&styled_path.path, false)
+          .iter()
+          .any(|(start, end)| segment_distance(*start, *end, point) <= half_width)
+      }
+      None => false,
+    };
+    in_fill || in_line
+  })
+}
+
+/// Tests if a point is inside the closed contours formed by `segments`, using `fill_rule`.
+pub(crate) fn contains_fill(segments: &[(Point, Point)], point: Point, fill_rule: FillRule) -> bool {
+  match fill_rule {
+    FillRule::EvenOdd => contains_even_odd(segments, point),
+    FillRule::NonZero => contains_non_zero(segments, point),
+  }
+}
+
+/// Even-odd rule: a point is inside if a ray starting from it crosses an odd number of edges.
+fn contains_even_odd(segments: &[(Point, Point)], point: Point) -> bool {
+  let mut inside = false;
+  for (start, end) in segments.iter() {
+    if (start.y > point.y) != (end.y > point.y) {
+      let x = start.x + (point.y - start.y) * (end.x - start.x) / (end.y - start.y);
+      if point.x < x {
+        inside = !inside;
+      }
+    }
+  }
+  inside
+}
+
+/// Non-zero rule: a point is inside if the edges crossed by a ray starting from it do not cancel out
+/// (upward edges count `+1`, downward edges count `-1`).
+fn contains_non_zero(segments: &[(Point, Point)], point: Point) -> bool {
+  let mut winding: i32 = 0;
+  for (start, end) in segments.iter() {
+    if (start.y > point.y) != (end.y > point.y) {
+      let x = start.x + (point.y - start.y) * (end.x - start.x) / (end.y - start.y);
+      if point.x < x {
+        winding += if end.y > start.y { 1 } else { -1 };
+      }
+    }
+  }
+  winding != 0
+}
+
+fn segment_distance(start: Point, end: Point, point: Point) -> f32 {
+  let segment = end - start;
+  let length_squared = segment.square_length();
+  let t = if length_squared == 0.0 {
+    0.0
+  } else {
+    f32::min(f32::max((point - start).dot(segment) / length_squared, 0.0), 1.0)
+  };
+  (start + segment * t - point).length()
+}
+
+/// Approximates a path with line segments.
+///
+/// Fills close every sub-path (`close_all`), strokes only close the sub-paths ending with a `close` command.
+fn flatten_path(path: &Path, close_all: bool) -> Vec<(Point, Point)> {
+  let mut segments: Vec<(Point, Point)> = Vec::new();
+  for event in path.iter() {
+    match event {
+      PathEvent::Begin { .. } => {}
+      PathEvent::Line { from, to } => segments.push((from, to)),
+      PathEvent::Quadratic { from, ctrl, to } => {
+        let steps = curve_steps(from, ctrl, ctrl, to);
+        let mut previous = from;
+        for step in 1..=steps {
+          let t = (step as f32) / (steps as f32);
+          let u = 1.0 - t;
+          let next = Point::new(
+            u * u * from.x + 2.0 * u * t * ctrl.x + t * t * to.x,
+            u * u * from.y + 2.0 * u * t * ctrl.y + t * t * to.y,
+          );
+          segments.push((previous, next));
+          previous = next;
+        }
+      }
+      PathEvent::Cubic { from, ctrl1, ctrl2, to } => {
+        let steps = curve_steps(from, ctrl1, ctrl2, to);
+        let mut previous = from;
+        for step in 1..=steps {
+          let t = (step as f32) / (steps as f32);
+          let u = 1.0 - t;
+          let next = Point::new(
+            u * u * u * from.x + 3.0 * u * u * t * ctrl1.x + 3.0 * u * t * t * ctrl2.x + t * t * t * to.x,
+            u * u * u * from.y + 3.0 * u * u * t * ctrl1.y + 3.0 * u * t * t * ctrl2.y + t * t * t * to.y,
+          );
+          segments.push((previous, next));
+          previous = next;
+        }
+      }
+      PathEvent::End { last, first, close } => {
+        if (close || close_all) && last != first {
+          segments.push((last, first));
+        }
+      }
+    }
+  }
+  segments
+}
+
+/// Approximates a path with line segments, replacing each curve by its chord and closing every sub-path.
+pub(crate) fn flatten_chords(path: &Path) -> Vec<(Point, Point)> {
+  let mut segments: Vec<(Point, Point)> = Vec::new();
+  for event in path.iter() {
+    match event {
+      PathEvent::Begin { .. } => {}
+      PathEvent::Line { from, to } => segments.push((from, to)),
+      PathEvent::Quadratic { from, to, .. } => segments.push((from, to)),
+      PathEvent::Cubic { from, to, .. } => segments.push((from, to)),
+      PathEvent::End { last, first, .. } => {
+        if last != first {
+          segments.push((last, first));
+        }
+      }
+    }
+  }
+  segments
+}
+
+/// Number of line segments to approximate a curve within `FLATTENING_TOLERANCE`, based on the
+/// length of its control polygon.
+fn curve_steps(from: Point, ctrl1: Point, ctrl2: Point, to: Point) -> usize {
+  let length = (ctrl1 - from).length() + (ctrl2 - ctrl1).length() + (to - ctrl2).length();
+  usize::max(1, f32::ceil((length / FLATTENING_TOLERANCE).sqrt()) as usize)
+}
@@ -2,18 +2,34 @@
 #![allow(dead_code)]
 
 pub use crate::gfx_renderer::GfxRenderer;
-pub use decoder::shape_decoder::{decode_shape, Shape, StyledPath};
+pub use decoder::shape_decoder::{
+  decode_define_shape, decode_shape, decode_shape_with_mode, DecodeMode, Shape, ShapeDecodeError, StyledPath,
+};
 
+pub mod allocator;
 pub mod asset;
+pub mod bounds;
+pub mod budget;
+pub mod curve;
+pub mod damage;
+pub mod error;
 pub mod stage;
 
 mod gfx;
 mod gfx_renderer;
 #[cfg(not(target_arch = "wasm32"))]
 pub mod headless_renderer;
+pub mod hit_test;
+pub mod lod;
 pub mod pam;
+pub mod pipeline_cache;
+pub mod protocol;
+pub mod recording;
 pub mod renderer;
+pub mod shaders;
 pub mod swf_renderer;
+pub mod timeline;
+pub mod viewport;
 pub(crate) mod decoder {
   pub(crate) mod shape_decoder;
 }
@@ -25,10 +41,13 @@ mod wasm;
 
 #[cfg(test)]
 mod renderer_tests {
+  use crate::asset::ClientAssetStore;
+  use crate::curve::CurveMode;
   use crate::decode_shape;
   use crate::headless_renderer::HeadlessGfxRenderer;
   use crate::pam::write_pam;
-  use crate::renderer::DisplayItem;
+  use crate::renderer::{DisplayItem, Image};
+  use crate::stage::{ColorTransform, Matrix2D};
   use ::swf_tree::tags::DefineShape;
   use ::test_generator::test_resources;
   use gfx_hal::Instance;
@@ -50,7 +69,7 @@ mod renderer_tests {
     let ast_reader = ::std::io::BufReader::new(ast_file);
     let ast: DefineShape = serde_json::from_reader(ast_reader).unwrap();
 
-    let shape = decode_shape(&ast.shape);
+    let shape = decode_shape(&ast.shape).expect("Failed to decode shape");
     let shape_info: String = format!("{:#?}\n", &shape);
 
     let actual_shape_path = path.join("tmp-shape.rs.log");
@@ -76,33 +95,18 @@ mod renderer_tests {
     }
   }
 
-  #[test_resources("../tests/flat-shapes/*/")]
-  fn test_render_flat_shape(path: &str) {
+  /// Renders a shape at its original size, with its top-left corner at the origin.
+  fn render_flat_shape(
+    ast: &swf_tree::tags::DefineShape,
+    curve_mode: CurveMode,
+    color_transform: ColorTransform,
+  ) -> Image {
     use crate::renderer::Renderer;
     use gfx_backend_vulkan as gfx_backend;
 
     const GFX_APP_NAME: &'static str = "ofl-renderer";
     const GFX_BACKEND_VERSION: u32 = 1;
 
-    let path: &Path = Path::new(path);
-    let name = path
-      .components()
-      .last()
-      .unwrap()
-      .as_os_str()
-      .to_str()
-      .expect("Failed to retrieve sample name");
-
-    if !is_whitelisted(&name) {
-      eprintln!("Skipping: {}", &name);
-      return;
-    }
-
-    let ast_path = path.join("ast.json");
-    let ast_file = ::std::fs::File::open(ast_path).expect("Failed to open AST");
-    let ast_reader = ::std::io::BufReader::new(ast_file);
-    let ast: swf_tree::tags::DefineShape = serde_json::from_reader(ast_reader).unwrap();
-
     let instance: gfx_backend::Instance =
       gfx_backend::Instance::create(GFX_APP_NAME, GFX_BACKEND_VERSION).expect("Failed to create Instance");
 
@@ -115,19 +119,59 @@ mod renderer_tests {
 
     let mut renderer =
       HeadlessGfxRenderer::<gfx_backend::Backend>::new(&instance, width_px as usize, height_px as usize).unwrap();
+    renderer.set_curve_mode(curve_mode);
 
-    let shape_id = renderer.define_shape(&ast);
+    let movie = renderer.create_movie();
+    let shape_id = renderer.register_shape(movie, ast).unwrap().0;
 
-    let matrix = {
-      let mut matrix = swf_tree::Matrix::default();
-      matrix.translate_x = -ast.bounds.x_min;
-      matrix.translate_y = -ast.bounds.y_min;
-      matrix
-    };
+    let matrix = origin_matrix(&ast.bounds);
 
-    renderer.set_stage(DisplayItem::Shape(shape_id, matrix));
+    renderer.set_stage(vec![DisplayItem::Shape(shape_id, matrix, color_transform)]);
 
-    let image = renderer.get_image().unwrap();
+    renderer.get_image().unwrap()
+  }
+
+  /// Returns the matrix moving the top-left corner of `bounds` to the origin.
+  fn origin_matrix(bounds: &swf_tree::Rect) -> Matrix2D {
+    let mut matrix = swf_tree::Matrix::default();
+    matrix.translate_x = -bounds.x_min;
+    matrix.translate_y = -bounds.y_min;
+    Matrix2D::from(&matrix)
+  }
+
+  fn load_ast(path: &Path) -> swf_tree::tags::DefineShape {
+    let ast_path = path.join("ast.json");
+    let ast_file = ::std::fs::File::open(ast_path).expect("Failed to open AST");
+    let ast_reader = ::std::io::BufReader::new(ast_file);
+    serde_json::from_reader(ast_reader).unwrap()
+  }
+
+  /// Loads the tag of a sample of `tests/flat-shapes`.
+  pub(crate) fn load_flat_shape(name: &str) -> swf_tree::tags::DefineShape {
+    load_ast(&Path::new("../tests/flat-shapes").join(name))
+  }
+
+  fn sample_name(path: &Path) -> &str {
+    path
+      .components()
+      .last()
+      .unwrap()
+      .as_os_str()
+      .to_str()
+      .expect("Failed to retrieve sample name")
+  }
+
+  #[test_resources("../tests/flat-shapes/*/")]
+  fn test_render_flat_shape(path: &str) {
+    let path: &Path = Path::new(path);
+    let name = sample_name(path);
+
+    if !is_whitelisted(&name) {
+      eprintln!("Skipping: {}", &name);
+      return;
+    }
+
+    let image = render_flat_shape(&load_ast(path), CurveMode::Tessellated, ColorTransform::default());
 
     {
       let actual_shape_path = path.join("tmp-shape.rs.pam");
@@ -142,4 +186,2155 @@ mod renderer_tests {
     //
     //    assert_eq!(shape_info, expected_shape_info);
   }
+
+  #[test]
+  fn test_render_display_list() {
+    use crate::renderer::Renderer;
+    use gfx_backend_vulkan as gfx_backend;
+
+    let ast = load_ast(Path::new("../tests/flat-shapes/squares"));
+    let single = render_flat_shape(&ast, CurveMode::Tessellated, ColorTransform::default());
+    let (width, height) = (single.meta.width, single.meta.height);
+
+    let instance: gfx_backend::Instance =
+      gfx_backend::Instance::create("ofl-renderer", 1).expect("Failed to create Instance");
+    let mut renderer = HeadlessGfxRenderer::<gfx_backend::Backend>::new(&instance, width * 3, height).unwrap();
+    let movie = renderer.create_movie();
+    let shape_id = renderer.register_shape(movie, &ast).unwrap().0;
+
+    // Three copies side by side, with enough draws to exercise the growth of the instance buffer
+    let display_list: Vec<DisplayItem> = (0..3000)
+      .map(|index| {
+        let mut matrix = swf_tree::Matrix::default();
+        matrix.translate_x = -ast.bounds.x_min + ((index % 3) * width * 20) as i32;
+        matrix.translate_y = -ast.bounds.y_min;
+        DisplayItem::Shape(shape_id, Matrix2D::from(&matrix), ColorTransform::default())
+      })
+      .collect();
+    renderer.set_stage(display_list);
+    let image = renderer.get_image().unwrap();
+
+    for copy in 0..3 {
+      for y in 0..height {
+        let expected = &single.data[(y * single.meta.stride)..(y * single.meta.stride + width * 4)];
+        let start = y * image.meta.stride + copy * width * 4;
+        assert_eq!(&image.data[start..(start + width * 4)], expected);
+      }
+    }
+  }
+
+  #[test]
+  fn test_render_color_transform() {
+    let ast = load_ast(Path::new("../tests/flat-shapes/squares"));
+    let plain = render_flat_shape(&ast, CurveMode::Tessellated, ColorTransform::default());
+    let red = ColorTransform {
+      mult: [0.0, 0.0, 0.0, 1.0],
+      add: [1.0, 0.0, 0.0, 0.0],
+    };
+    let image = render_flat_shape(&ast, CurveMode::Tessellated, red);
+
+    let mut colored_pixels: usize = 0;
+    for (plain_pixel, pixel) in plain.data.chunks(4).zip(image.data.chunks(4)) {
+      // Only the pixels covered by the shape are opaque
+      if plain_pixel[3] == 255 {
+        assert_eq!(pixel, &[255, 0, 0, 255]);
+        colored_pixels += 1;
+      } else {
+        assert_eq!(pixel, plain_pixel);
+      }
+    }
+    assert!(colored_pixels > 0);
+  }
+
+  #[test_resources("../tests/flat-shapes/*/")]
+  fn test_render_flat_shape_analytic(path: &str) {
+    let path: &Path = Path::new(path);
+    let name = sample_name(path);
+
+    if !is_whitelisted(&name) {
+      eprintln!("Skipping: {}", &name);
+      return;
+    }
+
+    let ast = load_ast(path);
+    let tessellated = render_flat_shape(&ast, CurveMode::Tessellated, ColorTransform::default());
+    let analytic = render_flat_shape(&ast, CurveMode::Analytic, ColorTransform::default());
+
+    {
+      let actual_shape_path = path.join("tmp-shape-analytic.rs.pam");
+      let actual_shape_file = ::std::fs::File::create(actual_shape_path).expect("Failed to create actual shape file");
+      let mut pam_writer = ::std::io::BufWriter::new(actual_shape_file);
+      write_pam(&mut pam_writer, &analytic).expect("Failed to write PAM");
+    }
+
+    // The flat shapes only contain straight edges: both modes must produce the same pixels
+    assert_eq!(analytic.data, tessellated.data);
+  }
+
+  #[test]
+  fn test_release_and_redefine_shape() {
+    use crate::error::RendererError;
+    use crate::renderer::Renderer;
+    use gfx_backend_vulkan as gfx_backend;
+
+    let squares = load_ast(Path::new("../tests/flat-shapes/squares"));
+    let triangle = load_ast(Path::new("../tests/flat-shapes/triangle"));
+    let expected = render_flat_shape(&squares, CurveMode::Tessellated, ColorTransform::default());
+
+    let instance: gfx_backend::Instance =
+      gfx_backend::Instance::create("ofl-renderer", 1).expect("Failed to create Instance");
+    let mut renderer =
+      HeadlessGfxRenderer::<gfx_backend::Backend>::new(&instance, expected.meta.width, expected.meta.height).unwrap();
+    let movie = renderer.create_movie();
+    let id = renderer.register_shape(movie, &triangle).unwrap();
+    let matrix = origin_matrix(&squares.bounds);
+    renderer.set_stage(vec![DisplayItem::Shape(id.0, matrix, ColorTransform::default())]);
+    renderer.get_image().unwrap();
+
+    // The meshes of the triangle are replaced by the meshes of the squares
+    renderer.redefine_shape(id, &squares).unwrap();
+    assert_eq!(renderer.get_image().unwrap().data, expected.data);
+
+    renderer.release_shape(id);
+    match renderer.get_image() {
+      Err(RendererError::UnknownAsset(asset)) => assert_eq!(asset, id.0),
+      _ => panic!("Expected an unknown asset error"),
+    }
+  }
+
+  #[test]
+  fn test_render_with_memory_budget() {
+    use crate::renderer::Renderer;
+    use gfx_backend_vulkan as gfx_backend;
+
+    let squares = load_ast(Path::new("../tests/flat-shapes/squares"));
+    let triangle = load_ast(Path::new("../tests/flat-shapes/triangle"));
+    let expected = render_flat_shape(&squares, CurveMode::Tessellated, ColorTransform::default());
+
+    let instance: gfx_backend::Instance =
+      gfx_backend::Instance::create("ofl-renderer", 1).expect("Failed to create Instance");
+    let mut renderer =
+      HeadlessGfxRenderer::<gfx_backend::Backend>::new(&instance, expected.meta.width, expected.meta.height).unwrap();
+    // Only the meshes of the current frame fit in the budget
+    renderer.set_memory_budget(Some(1));
+    let movie = renderer.create_movie();
+    let squares_id = renderer.register_shape(movie, &squares).unwrap().0;
+    let triangle_id = renderer.register_shape(movie, &triangle).unwrap().0;
+    let matrix = origin_matrix(&squares.bounds);
+
+    for shape_id in [squares_id, triangle_id, squares_id].iter() {
+      renderer.set_stage(vec![DisplayItem::Shape(
+        *shape_id,
+        matrix.clone(),
+        ColorTransform::default(),
+      )]);
+      let image = renderer.get_image().unwrap();
+      if *shape_id == squares_id {
+        assert_eq!(image.data, expected.data);
+      }
+    }
+
+    let stats = renderer.memory_stats();
+    assert_eq!((stats.uploads, stats.evictions, stats.resident_count), (3, 2, 1));
+  }
+
+  #[test]
+  fn test_memory_budget_shrinks_buffers() {
+    use crate::renderer::Renderer;
+    use gfx_backend_vulkan as gfx_backend;
+
+    let squares = load_ast(Path::new("../tests/flat-shapes/squares"));
+    let expected = render_flat_shape(&squares, CurveMode::Tessellated, ColorTransform::default());
+
+    let instance: gfx_backend::Instance =
+      gfx_backend::Instance::create("ofl-renderer", 1).expect("Failed to create Instance");
+    let mut renderer =
+      HeadlessGfxRenderer::<gfx_backend::Backend>::new(&instance, expected.meta.width, expected.meta.height).unwrap();
+    let initial_capacity = renderer.arena_capacity();
+    let matrix = origin_matrix(&squares.bounds);
+    let frame = |ids: &[usize]| -> Vec<DisplayItem> {
+      ids
+        .iter()
+        .map(|id| DisplayItem::Shape(*id, matrix.clone(), ColorTransform::default()))
+        .collect()
+    };
+
+    let movie = renderer.create_movie();
+    let first_id = renderer.register_shape(movie, &squares).unwrap().0;
+    renderer.set_stage(frame(&[first_id]));
+    renderer.get_image().unwrap();
+    let mesh_size = renderer.memory_stats().resident_bytes;
+
+    // Draw enough copies of the squares in a single frame to grow the shared buffers
+    let copy_count = (initial_capacity / mesh_size) as u16 + 1;
+    let mut ids: Vec<usize> = vec![first_id];
+    for id in 1..=copy_count {
+      let mut copy = squares.clone();
+      copy.id = squares.id + id;
+      ids.push(renderer.register_shape(movie, &copy).unwrap().0);
+    }
+    renderer.set_stage(frame(&ids));
+    renderer.get_image().unwrap();
+    assert!(renderer.arena_capacity() > initial_capacity);
+
+    // Once the other copies are evicted, the remaining mesh is moved to buffers of the initial size
+    renderer.set_memory_budget(Some(mesh_size));
+    renderer.set_stage(frame(&[first_id]));
+    renderer.get_image().unwrap();
+    assert_eq!(renderer.arena_capacity(), initial_capacity);
+    assert_eq!(renderer.memory_stats().resident_count, 1);
+
+    // The moved mesh is drawn without being uploaded again
+    let uploads = renderer.memory_stats().uploads;
+    renderer.set_stage(frame(&[first_id]));
+    assert_eq!(renderer.get_image().unwrap().data, expected.data);
+    assert_eq!(renderer.memory_stats().uploads, uploads);
+  }
+}
+
+/// Builders of the display lists shared by the tests.
+#[cfg(test)]
+mod stage_helpers {
+  use crate::asset::ShapeId;
+  use crate::stage::{ClipLayer, ColorTransform, DisplayContainer, DisplayPrimitive, Matrix2D, Stage, StoredShape};
+
+  pub(crate) fn translate(x: f32, y: f32) -> Matrix2D {
+    Matrix2D([1.0, 1.0, 0.0, 0.0, x, y])
+  }
+
+  pub(crate) fn shape(id: ShapeId, matrix: Matrix2D) -> DisplayPrimitive {
+    DisplayPrimitive::Shape(StoredShape {
+      id,
+      matrix,
+      color_transform: ColorTransform::default(),
+      name: None,
+    })
+  }
+
+  pub(crate) fn container(matrix: Matrix2D, children: Vec<DisplayPrimitive>) -> DisplayPrimitive {
+    DisplayPrimitive::Container(DisplayContainer {
+      matrix,
+      color_transform: ColorTransform::default(),
+      blend_mode: swf_tree::BlendMode::Normal,
+      filters: Vec::new(),
+      name: None,
+      children,
+    })
+  }
+
+  /// Clip layer masking `children` (at depth 2) with `mask`.
+  pub(crate) fn clip(mask: DisplayPrimitive, children: Vec<DisplayPrimitive>) -> DisplayPrimitive {
+    DisplayPrimitive::Clip(ClipLayer {
+      clip_depth: 2,
+      mask: Box::new(mask),
+      children,
+    })
+  }
+
+  /// Stage with a white background.
+  pub(crate) fn stage(display_root: Vec<DisplayPrimitive>) -> Stage {
+    Stage {
+      background_color: swf_tree::StraightSRgba8 {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+      },
+      display_root,
+    }
+  }
+}
+
+#[cfg(test)]
+mod hit_test_tests {
+  use crate::asset::ShapeId;
+  use crate::hit_test::{hit_test, HitTestMode};
+  use crate::renderer::ShapeStore;
+  use crate::renderer_tests::load_flat_shape;
+  use crate::stage::{ButtonState, ColorTransform, DisplayButton, DisplayPrimitive, Matrix2D, Stage};
+  use crate::stage_helpers::{clip, container, shape, stage, translate};
+  use lyon::math::Point;
+  use serde_json::{json, Value};
+
+  const MODES: [HitTestMode; 2] = [HitTestMode::BoundingBox, HitTestMode::Exact];
+
+  fn hit_path(stage: &Stage, store: &ShapeStore, x: f32, y: f32, mode: HitTestMode) -> Option<Vec<usize>> {
+    hit_test(stage, store, Point::new(x, y), mode).map(|hit| hit.path)
+  }
+
+  /// Defines a `1000 * 1000` twips shape from its style change and edge records, in the JSON format of
+  /// `swf_tree`. It has a single red fill and a single black line, `40` twips wide.
+  fn define_shape(store: &mut ShapeStore, id: u16, records: Vec<Value>) -> ShapeId {
+    let tag: swf_tree::tags::DefineShape = serde_json::from_value(json!({
+      "type": "define-shape",
+      "id": id,
+      "bounds": {"x_min": 0, "x_max": 1000, "y_min": 0, "y_max": 1000},
+      "has_fill_winding": false,
+      "has_non_scaling_strokes": false,
+      "has_scaling_strokes": true,
+      "shape": {
+        "initial_styles": {
+          "fill": [{"type": "solid", "color": {"r": 255, "g": 0, "b": 0, "a": 255}}],
+          "line": [{
+            "width": 40,
+            "start_cap": "round",
+            "end_cap": "round",
+            "join": {"type": "round"},
+            "no_h_scale": false,
+            "no_v_scale": false,
+            "no_close": false,
+            "pixel_hinting": false,
+            "fill": {"type": "solid", "color": {"r": 0, "g": 0, "b": 0, "a": 255}},
+          }],
+        },
+        "records": records,
+      },
+    }))
+    .expect("Invalid shape");
+    store.define_shape(usize::from(id), &tag).unwrap();
+    ShapeId(usize::from(id))
+  }
+
+  fn edge(x: i32, y: i32) -> Value {
+    json!({"type": "edge", "delta": {"x": x, "y": y}})
+  }
+
+  #[test]
+  fn test_hit_test_squares() {
+    let ast = load_flat_shape("squares");
+
+    let mut store = ShapeStore::new();
+    let id = ShapeId(1);
+    store.define_shape(id.0, &ast).unwrap();
+
+    let stage = stage(vec![shape(id, Matrix2D::default())]);
+
+    for mode in [HitTestMode::BoundingBox, HitTestMode::Exact].iter() {
+      let hit = hit_test(&stage, &store, Point::new(3500.0, 2000.0), *mode).expect("Expected a hit");
+      assert_eq!(hit.path, vec![0]);
+      assert!(hit_test(&stage, &store, Point::new(0.0, 0.0), *mode).is_none());
+    }
+  }
+
+  #[test]
+  fn test_hit_test_transformed_container() {
+    let mut store = ShapeStore::new();
+    let id = ShapeId(1);
+    store.define_shape(id.0, &load_flat_shape("squares")).unwrap();
+    let scaled = Matrix2D([2.0, 2.0, 0.0, 0.0, 0.0, 0.0]);
+    let stage = stage(vec![container(
+      translate(10000.0, 0.0),
+      vec![shape(id, Matrix2D::default()), shape(id, scaled)],
+    )]);
+
+    for mode in MODES.iter() {
+      // Only the scaled squares cover this point
+      assert_eq!(hit_path(&stage, &store, 17000.0, 4000.0, *mode), Some(vec![0, 1]));
+      // Only the squares without scale cover this point
+      assert_eq!(hit_path(&stage, &store, 13500.0, 2000.0, *mode), Some(vec![0, 0]));
+      // The point is inside the squares before the translation of the container
+      assert_eq!(hit_path(&stage, &store, 3500.0, 2000.0, *mode), None);
+    }
+  }
+
+  #[test]
+  fn test_hit_test_clip_mask() {
+    let mut store = ShapeStore::new();
+    let id = ShapeId(1);
+    store.define_shape(id.0, &load_flat_shape("squares")).unwrap();
+    let masked_squares = |mask_matrix: Matrix2D| clip(shape(id, mask_matrix), vec![shape(id, Matrix2D::default())]);
+
+    for mode in MODES.iter() {
+      let visible = stage(vec![masked_squares(Matrix2D::default())]);
+      assert_eq!(hit_path(&visible, &store, 3500.0, 2000.0, *mode), Some(vec![0, 0]));
+      // The mask is never hit
+      let masked = stage(vec![masked_squares(translate(10000.0, 0.0))]);
+      assert_eq!(hit_path(&masked, &store, 3500.0, 2000.0, *mode), None);
+      assert_eq!(hit_path(&masked, &store, 13500.0, 2000.0, *mode), None);
+    }
+  }
+
+  #[test]
+  fn test_hit_test_button_hit_area() {
+    let mut store = ShapeStore::new();
+    let id = ShapeId(1);
+    store.define_shape(id.0, &load_flat_shape("squares")).unwrap();
+    let stage = stage(vec![DisplayPrimitive::Button(DisplayButton {
+      matrix: Matrix2D::default(),
+      color_transform: ColorTransform::default(),
+      name: None,
+      state: ButtonState::Up,
+      children: vec![shape(id, Matrix2D::default())],
+      hit_area: vec![shape(id, translate(10000.0, 0.0))],
+    })]);
+
+    for mode in MODES.iter() {
+      let hit = hit_test(&stage, &store, Point::new(13500.0, 2000.0), *mode).expect("Expected a hit");
+      assert_eq!(hit.path, vec![0]);
+      match hit.primitive {
+        DisplayPrimitive::Button(_) => {}
+        _ => panic!("Expected the button to be hit"),
+      }
+      // The visible children are not part of the hit area
+      assert_eq!(hit_path(&stage, &store, 3500.0, 2000.0, *mode), None);
+    }
+  }
+
+  #[test]
+  fn test_hit_test_stroke_only() {
+    let mut store = ShapeStore::new();
+    // Open stroke along the top and right sides, without fill
+    let id = define_shape(
+      &mut store,
+      1,
+      vec![
+        json!({"type": "style-change", "move_to": {"x": 0, "y": 0}, "line_style": 1}),
+        edge(1000, 0),
+        edge(0, 1000),
+      ],
+    );
+    let stage = stage(vec![shape(id, Matrix2D::default())]);
+
+    assert_eq!(hit_path(&stage, &store, 500.0, 15.0, HitTestMode::Exact), Some(vec![0]));
+    assert_eq!(
+      hit_path(&stage, &store, 985.0, 500.0, HitTestMode::Exact),
+      Some(vec![0])
+    );
+    assert_eq!(hit_path(&stage, &store, 500.0, 100.0, HitTestMode::Exact), None);
+    // The stroke is not closed: the diagonal from its end to its start is empty
+    assert_eq!(hit_path(&stage, &store, 500.0, 500.0, HitTestMode::Exact), None);
+    assert_eq!(
+      hit_path(&stage, &store, 500.0, 500.0, HitTestMode::BoundingBox),
+      Some(vec![0])
+    );
+  }
+
+  #[test]
+  fn test_hit_test_even_odd_hole() {
+    let mut store = ShapeStore::new();
+    // Square with a square hole, filled on the right of the edges
+    let id = define_shape(
+      &mut store,
+      1,
+      vec![
+        json!({"type": "style-change", "move_to": {"x": 0, "y": 0}, "right_fill": 1}),
+        edge(1000, 0),
+        edge(0, 1000),
+        edge(-1000, 0),
+        edge(0, -1000),
+        json!({"type": "style-change", "move_to": {"x": 250, "y": 250}}),
+        edge(0, 500),
+        edge(500, 0),
+        edge(0, -500),
+        edge(-500, 0),
+      ],
+    );
+    let stage = stage(vec![shape(id, Matrix2D::default())]);
+
+    assert_eq!(
+      hit_path(&stage, &store, 100.0, 500.0, HitTestMode::Exact),
+      Some(vec![0])
+    );
+    assert_eq!(hit_path(&stage, &store, 500.0, 500.0, HitTestMode::Exact), None);
+    assert_eq!(
+      hit_path(&stage, &store, 500.0, 500.0, HitTestMode::BoundingBox),
+      Some(vec![0])
+    );
+  }
+}
+
+#[cfg(test)]
+mod bounds_tests {
+  use crate::asset::{MorphShapeId, ShapeId};
+  use crate::bounds::{primitive_bounds, shape_bounds, stage_bounds, Bounds, HAIRLINE_WIDTH};
+  use crate::renderer::{GfxSymbol, ShapeStore};
+  use crate::renderer_tests::load_flat_shape;
+  use crate::stage::{
+    ClipLayer, ColorTransform, DisplayPrimitive, Matrix2D, MorphRatio, Stage, StoredMorphShape, StoredShape,
+  };
+  use crate::{Shape, StyledPath};
+  use lyon::math::Point;
+  use lyon::tessellation::FillRule;
+  use serde_json::json;
+
+  #[test]
+  fn test_stage_bounds_squares() {
+    let ast = load_flat_shape("squares");
+
+    let mut store = ShapeStore::new();
+    let id = 1;
+    store.define_shape(id, &ast).unwrap();
+
+    let local_bounds: Bounds = match store.get(id) {
+      Some(GfxSymbol::Shape(ref symbol)) => symbol.tight_bounds.expect("Expected non-empty shape"),
+      _ => panic!("ShapeNotFound"),
+    };
+    let tag_bounds = Bounds::from(ast.bounds);
+    assert!(tag_bounds.x_min <= local_bounds.x_min && local_bounds.x_max <= tag_bounds.x_max);
+    assert!(tag_bounds.y_min <= local_bounds.y_min && local_bounds.y_max <= tag_bounds.y_max);
+
+    let stage = Stage {
+      background_color: swf_tree::StraightSRgba8 {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+      },
+      display_root: vec![DisplayPrimitive::Shape(StoredShape {
+        id: ShapeId(id),
+        matrix: Matrix2D([1.0, 1.0, 0.0, 0.0, 100.0, -200.0]),
+        color_transform: ColorTransform::default(),
+        name: None,
+      })],
+    };
+
+    let expected = Bounds {
+      x_min: local_bounds.x_min + 100.0,
+      x_max: local_bounds.x_max + 100.0,
+      y_min: local_bounds.y_min - 200.0,
+      y_max: local_bounds.y_max - 200.0,
+    };
+    assert_eq!(stage_bounds(&stage, &store), Some(expected));
+  }
+
+  #[test]
+  fn test_hairline_bounds() {
+    let mut builder = lyon::path::Path::builder();
+    builder.move_to(Point::new(0.0, 0.0));
+    builder.line_to(Point::new(1000.0, 500.0));
+    let line: swf_tree::LineStyle = serde_json::from_value(json!({
+      "width": 0,
+      "start_cap": "round",
+      "end_cap": "round",
+      "join": {"type": "round"},
+      "no_h_scale": false,
+      "no_v_scale": false,
+      "no_close": false,
+      "pixel_hinting": false,
+      "fill": {"type": "solid", "color": {"r": 0, "g": 0, "b": 0, "a": 255}},
+    }))
+    .expect("Invalid line style");
+    let shape = Shape {
+      paths: vec![StyledPath {
+        path: builder.build(),
+        fill: None,
+        line: Some(line),
+      }],
+      fill_rule: FillRule::EvenOdd,
+    };
+
+    let half_width = HAIRLINE_WIDTH / 2.0;
+    assert_eq!(
+      shape_bounds(&shape),
+      Some(Bounds {
+        x_min: -half_width,
+        x_max: 1000.0 + half_width,
+        y_min: -half_width,
+        y_max: 500.0 + half_width,
+      })
+    );
+  }
+
+  #[test]
+  fn test_clip_bounds() {
+    let mut store = ShapeStore::new();
+    let id = 1;
+    store.define_shape(id, &load_flat_shape("squares")).unwrap();
+    let squares_bounds = match store.get(id) {
+      Some(GfxSymbol::Shape(ref symbol)) => symbol.tight_bounds.expect("Expected non-empty shape"),
+      _ => panic!("ShapeNotFound"),
+    };
+    let shape = |matrix: Matrix2D| {
+      DisplayPrimitive::Shape(StoredShape {
+        id: ShapeId(id),
+        matrix,
+        color_transform: ColorTransform::default(),
+        name: None,
+      })
+    };
+    let clip = |mask: DisplayPrimitive| {
+      DisplayPrimitive::Clip(ClipLayer {
+        clip_depth: 2,
+        mask: Box::new(mask),
+        children: vec![shape(Matrix2D::default())],
+      })
+    };
+
+    // The mask overlaps the right half of the children
+    let offset = squares_bounds.width() / 2.0;
+    let masked = clip(shape(Matrix2D([1.0, 1.0, 0.0, 0.0, offset, 0.0])));
+    assert_eq!(
+      primitive_bounds(&masked, &store),
+      Some(Bounds {
+        x_min: squares_bounds.x_min + offset,
+        ..squares_bounds
+      })
+    );
+
+    // Morph shapes have no bounds yet: the children may be visible anywhere
+    let morph_mask = clip(DisplayPrimitive::MorphShape(StoredMorphShape {
+      id: MorphShapeId(id),
+      matrix: Matrix2D::default(),
+      color_transform: ColorTransform::default(),
+      ratio: MorphRatio(0),
+      name: None,
+    }));
+    assert_eq!(primitive_bounds(&morph_mask, &store), Some(squares_bounds));
+  }
+}
+
+#[cfg(test)]
+mod matrix_tests {
+  use crate::stage::Matrix2D;
+  use lyon::math::Point;
+
+  fn assert_matrix_eq(actual: &Matrix2D, expected: &Matrix2D) {
+    for (actual, expected) in actual.0.iter().zip(expected.0.iter()) {
+      assert!((actual - expected).abs() < 1e-4, "{:?} != {:?}", actual, expected);
+    }
+  }
+
+  #[test]
+  fn test_from_default_swf_matrix() {
+    assert_eq!(Matrix2D::from(&swf_tree::Matrix::default()), Matrix2D::default());
+  }
+
+  #[test]
+  fn test_invert() {
+    let matrix = Matrix2D([2.0, 0.5, 0.25, -1.5, 100.0, -40.0]);
+    let inverse = matrix.invert().expect("Matrix should be invertible");
+    assert_matrix_eq(&(&matrix * &inverse), &Matrix2D::default());
+    assert_matrix_eq(&(&inverse * &matrix), &Matrix2D::default());
+    assert!(Matrix2D([1.0, 0.0, 0.0, 0.0, 0.0, 0.0]).invert().is_none());
+  }
+
+  #[test]
+  fn test_multiply_order() {
+    let scale = Matrix2D([2.0, 2.0, 0.0, 0.0, 0.0, 0.0]);
+    let translate = Matrix2D([1.0, 1.0, 0.0, 0.0, 10.0, 20.0]);
+    let point = (&translate * &scale).transform_point(Point::new(1.0, 1.0));
+    assert_eq!(point, Point::new(12.0, 22.0));
+  }
+
+  #[test]
+  fn test_decompose() {
+    let matrix = Matrix2D([1.5, -0.75, 0.5, 0.25, 30.0, 40.0]);
+    assert_matrix_eq(&Matrix2D::compose(&matrix.decompose()), &matrix);
+  }
+}
+
+#[cfg(test)]
+mod viewport_tests {
+  use crate::viewport::{PixelRect, ScaleMode, Viewport};
+  use lyon::math::Point;
+
+  fn stage_rect() -> swf_tree::Rect {
+    swf_tree::Rect {
+      x_min: 0,
+      x_max: 8000,
+      y_min: 0,
+      y_max: 6000,
+    }
+  }
+
+  #[test]
+  fn test_show_all_letterbox() {
+    let viewport = Viewport::new(stage_rect());
+    assert_eq!(
+      viewport.visible_rect(800, 400),
+      PixelRect {
+        x: 133,
+        y: 0,
+        width: 534,
+        height: 400,
+      }
+    );
+    let matrix = viewport.stage_matrix(800, 400);
+    let center = matrix.transform_point(Point::new(4000.0, 3000.0));
+    assert!((center.x - 400.0).abs() < 1e-3 && (center.y - 200.0).abs() < 1e-3);
+  }
+
+  #[test]
+  fn test_exact_fit() {
+    let viewport = Viewport {
+      scale_mode: ScaleMode::ExactFit,
+      ..Viewport::new(stage_rect())
+    };
+    let corner = viewport
+      .stage_matrix(800, 400)
+      .transform_point(Point::new(8000.0, 6000.0));
+    assert!((corner.x - 800.0).abs() < 1e-3 && (corner.y - 400.0).abs() < 1e-3);
+  }
+}
+
+#[cfg(test)]
+mod shape_decoder_tests {
+  use crate::renderer_tests::load_flat_shape;
+  use crate::{decode_shape, decode_shape_with_mode, DecodeMode, ShapeDecodeError};
+  use ::swf_tree::ShapeRecord;
+
+  #[test]
+  fn test_fill_style_out_of_range() {
+    let mut ast = load_flat_shape("squares");
+    let fill_count = ast.shape.initial_styles.fill.len();
+    match ast.shape.records[0] {
+      ShapeRecord::StyleChange(ref mut record) => record.right_fill = Some(fill_count + 1),
+      _ => panic!("Expected style change"),
+    }
+
+    assert_eq!(
+      decode_shape(&ast.shape).unwrap_err(),
+      ShapeDecodeError::FillStyleOutOfRange {
+        index: fill_count + 1,
+        count: fill_count,
+      }
+    );
+    assert!(decode_shape_with_mode(&ast.shape, DecodeMode::Lenient).is_ok());
+  }
+
+  #[test]
+  fn test_unclosed_fill() {
+    let mut ast = load_flat_shape("squares");
+    let first_edge = ast
+      .shape
+      .records
+      .iter()
+      .position(|record| match record {
+        ShapeRecord::Edge(_) => true,
+        _ => false,
+      })
+      .unwrap();
+    ast.shape.records.remove(first_edge);
+
+    match decode_shape(&ast.shape) {
+      Err(ShapeDecodeError::UnclosedFill(_)) => {}
+      result => panic!("Expected unclosed fill error, got: {:?}", result),
+    }
+    assert!(decode_shape_with_mode(&ast.shape, DecodeMode::Lenient).is_ok());
+  }
+}
+
+#[cfg(test)]
+mod fill_rule_tests {
+  use crate::lod::LodLevel;
+  use crate::renderer::tessellate_shape;
+  use crate::{Shape, StyledPath};
+  use lyon::math::Point;
+  use lyon::tessellation::FillRule;
+
+  /// Five-pointed star drawn as a single self-intersecting contour: its center has a winding number of 2.
+  fn star(fill_rule: FillRule) -> Shape {
+    let mut builder = lyon::path::Path::builder();
+    builder.move_to(Point::new(0.0, -1000.0));
+    builder.line_to(Point::new(588.0, 809.0));
+    builder.line_to(Point::new(-951.0, -309.0));
+    builder.line_to(Point::new(951.0, -309.0));
+    builder.line_to(Point::new(-588.0, 809.0));
+    builder.close();
+
+    Shape {
+      paths: vec![StyledPath {
+        path: builder.build(),
+        fill: Some(swf_tree::FillStyle::Solid(swf_tree::fill_styles::Solid {
+          color: swf_tree::StraightSRgba8 {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 255,
+          },
+        })),
+        line: None,
+      }],
+      fill_rule,
+    }
+  }
+
+  fn is_covered(shape: &Shape, point: Point) -> bool {
+    let mesh = tessellate_shape(shape, LodLevel(0).tolerance()).expect("Failed to tessellate shape");
+    let position = |index: u32| {
+      let [x, y, _] = mesh.vertices[index as usize].position;
+      Point::new(x, y)
+    };
+    mesh.indices.chunks(3).any(|triangle| {
+      let (a, b, c) = (position(triangle[0]), position(triangle[1]), position(triangle[2]));
+      let sides = [
+        (b - a).cross(point - a),
+        (c - b).cross(point - b),
+        (a - c).cross(point - c),
+      ];
+      sides.iter().all(|side| *side >= 0.0) || sides.iter().all(|side| *side <= 0.0)
+    })
+  }
+
+  #[test]
+  fn test_even_odd_star() {
+    let shape = star(FillRule::EvenOdd);
+    assert!(is_covered(&shape, Point::new(0.0, -700.0)));
+    assert!(!is_covered(&shape, Point::new(0.0, 0.0)));
+  }
+
+  #[test]
+  fn test_non_zero_star() {
+    let shape = star(FillRule::NonZero);
+    assert!(is_covered(&shape, Point::new(0.0, -700.0)));
+    assert!(is_covered(&shape, Point::new(0.0, 0.0)));
+  }
+}
+
+#[cfg(test)]
+mod lod_tests {
+  use crate::lod::{LodCache, LodLevel};
+
+  #[test]
+  fn test_level_from_scale() {
+    assert_eq!(LodLevel::from_scale(1.0), LodLevel(0));
+    assert_eq!(LodLevel::from_scale(1.5), LodLevel(1));
+    assert_eq!(LodLevel::from_scale(0.05), LodLevel(-4));
+    assert_eq!(LodLevel::from_scale(0.0), LodLevel::from_scale(-1.0));
+    let level = LodLevel::from_scale(0.3);
+    assert!(level.max_scale() >= 0.3 && level.max_scale() / 2.0 < 0.3);
+  }
+
+  #[test]
+  fn test_cache_evicts_least_recently_used() {
+    let mut cache: LodCache<i8> = LodCache::new();
+    for level in 0..4 {
+      assert!(cache.insert(LodLevel(level), level).is_empty());
+    }
+    assert_eq!(cache.get(LodLevel(0)), Some(&0));
+    assert_eq!(cache.insert(LodLevel(4), 4), vec![1]);
+    assert_eq!(cache.get(LodLevel(1)), None);
+    assert_eq!(cache.insert(LodLevel(4), 5), vec![4]);
+    assert_eq!(cache.levels().collect::<Vec<_>>().len(), 4);
+    cache.clear();
+    assert_eq!(cache.levels().count(), 0);
+  }
+}
+
+#[cfg(test)]
+mod curve_tests {
+  use crate::curve::build_curve_mesh;
+  use crate::renderer::tessellate_shape;
+  use crate::swf_renderer::Vertex;
+  use crate::{Shape, StyledPath};
+  use lyon::math::Point;
+  use lyon::tessellation::{FillRule, VertexBuffers};
+
+  /// Square of side 2000 twips with a convex curve on its left side and a concave curve on its right side.
+  fn curved_square() -> Shape {
+    let mut builder = lyon::path::Path::builder();
+    builder.move_to(Point::new(0.0, 0.0));
+    builder.line_to(Point::new(2000.0, 0.0));
+    builder.quadratic_bezier_to(Point::new(1000.0, 1000.0), Point::new(2000.0, 2000.0));
+    builder.line_to(Point::new(0.0, 2000.0));
+    builder.quadratic_bezier_to(Point::new(-1000.0, 1000.0), Point::new(0.0, 0.0));
+
+    Shape {
+      paths: vec![StyledPath {
+        path: builder.build(),
+        fill: Some(swf_tree::FillStyle::Solid(swf_tree::fill_styles::Solid {
+          color: swf_tree::StraightSRgba8 {
+            r: 255,
+            g: 0,
+            b: 0,
+            a: 255,
+          },
+        })),
+        line: None,
+      }],
+      fill_rule: FillRule::EvenOdd,
+    }
+  }
+
+  /// Tests if a point is covered by a mesh, evaluating the curve coordinates like the fragment shader.
+  fn is_covered(mesh: &VertexBuffers<Vertex, u32>, point: Point) -> bool {
+    mesh.indices.chunks(3).any(|triangle| {
+      let vertices: Vec<&Vertex> = triangle.iter().map(|index| &mesh.vertices[*index as usize]).collect();
+      let positions: Vec<Point> = vertices
+        .iter()
+        .map(|vertex| Point::new(vertex.position[0], vertex.position[1]))
+        .collect();
+      let (a, b, c) = (positions[0], positions[1], positions[2]);
+      let area = (b - a).cross(c - a);
+      if area == 0.0 {
+        return false;
+      }
+      let weights = [
+        (c - b).cross(point - b) / area,
+        (a - c).cross(point - c) / area,
+        (b - a).cross(point - a) / area,
+      ];
+      if weights.iter().any(|weight| *weight < 0.0) {
+        return false;
+      }
+      let curve = |component: usize| -> f32 {
+        weights
+          .iter()
+          .zip(vertices.iter())
+          .map(|(weight, vertex)| weight * vertex.curve[component])
+          .sum()
+      };
+      let (u, v, sign) = (curve(0), curve(1), curve(2));
+      sign * (u * u - v) <= 0.0
+    })
+  }
+
+  #[test]
+  fn test_analytic_matches_tessellation() {
+    let shape = curved_square();
+    let analytic = build_curve_mesh(&shape).expect("Failed to build curve mesh");
+    let tessellated = tessellate_shape(&shape, 0.01).expect("Failed to tessellate shape");
+
+    // The convex curve reaches x = -500 and the concave curve reaches x = 1500
+    assert!(is_covered(&analytic, Point::new(-450.0, 1000.0)));
+    assert!(!is_covered(&analytic, Point::new(-550.0, 1000.0)));
+    assert!(is_covered(&analytic, Point::new(1450.0, 1000.0)));
+    assert!(!is_covered(&analytic, Point::new(1550.0, 1000.0)));
+
+    let mut mismatches: usize = 0;
+    for y in 0..50 {
+      for x in 0..70 {
+        let point = Point::new(-700.0 + 40.0 * (x as f32) + 7.0, 40.0 * (y as f32) + 3.0);
+        if is_covered(&analytic, point) != is_covered(&tessellated, point) {
+          mismatches += 1;
+        }
+      }
+    }
+    assert!(mismatches <= 2, "{} samples differ", mismatches);
+  }
+
+  #[test]
+  fn test_analytic_mesh_is_scale_independent() {
+    use crate::curve::CurveMode;
+    use crate::renderer::ShapeStore;
+
+    let mut store = ShapeStore::new();
+    store.set_curve_mode(CurveMode::Analytic);
+    assert_eq!(store.lod_level(0.05), store.lod_level(64.0));
+    store.set_curve_mode(CurveMode::Tessellated);
+    assert_ne!(store.lod_level(0.05), store.lod_level(64.0));
+  }
+}
+
+#[cfg(test)]
+mod allocator_tests {
+  use crate::allocator::RangeAllocator;
+
+  #[test]
+  fn test_allocate_and_free() {
+    let mut allocator = RangeAllocator::new(100);
+    let a = allocator.allocate(40).unwrap();
+    let b = allocator.allocate(40).unwrap();
+    assert_eq!((a.clone(), b.clone()), (0..40, 40..80));
+    assert_eq!(allocator.allocate(30), None);
+
+    allocator.free(a);
+    assert_eq!(allocator.allocate(30), Some(0..30));
+    allocator.free(b);
+    // The freed ranges are merged with the remaining space
+    assert_eq!(allocator.allocate(70), Some(30..100));
+    assert_eq!(allocator.used(), 100);
+  }
+
+  #[test]
+  fn test_grow() {
+    let mut allocator = RangeAllocator::new(10);
+    let a = allocator.allocate(8).unwrap();
+    assert_eq!(allocator.allocate(8), None);
+    allocator.grow(32);
+    assert_eq!(allocator.allocate(8), Some(8..16));
+    allocator.free(a);
+    assert_eq!(allocator.allocate(24), None);
+    assert_eq!(allocator.allocate(16), Some(16..32));
+    assert_eq!(allocator.used(), 24);
+  }
+}
+
+#[cfg(test)]
+mod instancing_tests {
+  use crate::headless_renderer::group_instances;
+
+  #[test]
+  fn test_group_consecutive_instances() {
+    let groups = group_instances(&[1, 1, 1, 2, 1, 1, 3]);
+    assert_eq!(groups, vec![(1, 0..3), (2, 3..4), (1, 4..6), (3, 6..7)]);
+  }
+
+  #[test]
+  fn test_group_empty() {
+    assert_eq!(group_instances::<u32>(&[]), vec![]);
+  }
+}
+
+#[cfg(test)]
+mod shaders_tests {
+  use crate::curve::CurveMode;
+  use crate::shaders::ShaderVariant;
+
+  const SPIRV_MAGIC_NUMBER: u32 = 0x0723_0203;
+
+  #[test]
+  fn test_embedded_variants() {
+    for curve_mode in [CurveMode::Tessellated, CurveMode::Analytic].iter() {
+      let variant = ShaderVariant::for_curve_mode(*curve_mode);
+      let spirv = variant.spirv();
+      for binary in [spirv.vertex, spirv.fragment].iter() {
+        let words = gfx_hal::pso::read_spirv(::std::io::Cursor::new(*binary)).expect("Invalid SPIR-V");
+        assert_eq!(words[0], SPIRV_MAGIC_NUMBER);
+      }
+      let glsl_es = variant.glsl_es();
+      assert!(glsl_es.vertex.starts_with("#version 300 es"));
+      assert!(glsl_es.fragment.starts_with("#version 300 es"));
+    }
+  }
+}
+
+#[cfg(test)]
+mod pipeline_cache_tests {
+  use crate::pipeline_cache::{cache_path, load, save};
+  use gfx_hal::adapter::{AdapterInfo, DeviceType};
+  use std::path::Path;
+
+  fn adapter_info(device: usize) -> AdapterInfo {
+    AdapterInfo {
+      name: String::from("Test adapter"),
+      vendor: 0x10de,
+      device,
+      device_type: DeviceType::DiscreteGpu,
+    }
+  }
+
+  #[test]
+  fn test_cache_path_per_adapter() {
+    let directory = Path::new("cache");
+    let path = cache_path(directory, &adapter_info(1));
+    assert_eq!(path, cache_path(directory, &adapter_info(1)));
+    assert_ne!(path, cache_path(directory, &adapter_info(2)));
+    assert_eq!(path.parent(), Some(directory));
+  }
+
+  #[test]
+  fn test_save_and_load() {
+    let directory = ::std::env::temp_dir().join(format!("swf-renderer-pipeline-cache-{}", ::std::process::id()));
+    let path = cache_path(&directory, &adapter_info(1));
+    assert_eq!(load(&path), None);
+    save(&path, &[1, 2, 3]);
+    assert_eq!(load(&path), Some(vec![1, 2, 3]));
+    ::std::fs::remove_dir_all(&directory).unwrap();
+  }
+}
+
+#[cfg(test)]
+mod budget_tests {
+  use crate::budget::MemoryBudget;
+
+  #[test]
+  fn test_evict_least_recently_used() {
+    let mut budget: MemoryBudget<&str> = MemoryBudget::new(Some(250));
+    budget.next_frame();
+    budget.insert("a", 100);
+    budget.next_frame();
+    budget.insert("b", 100);
+    budget.next_frame();
+    budget.insert("c", 100);
+    budget.touch(&"a");
+    assert_eq!(budget.evict(), vec!["b"]);
+
+    let stats = budget.stats();
+    assert_eq!((stats.resident_count, stats.resident_bytes), (2, 200));
+    assert_eq!((stats.uploads, stats.evictions, stats.evicted_bytes), (3, 1, 100));
+  }
+
+  #[test]
+  fn test_keep_current_frame() {
+    let mut budget: MemoryBudget<u32> = MemoryBudget::new(Some(10));
+    budget.next_frame();
+    budget.insert(1, 100);
+    budget.insert(2, 100);
+    assert!(budget.evict().is_empty());
+    budget.next_frame();
+    budget.touch(&2);
+    assert_eq!(budget.evict(), vec![1]);
+    budget.remove(&2);
+    assert_eq!(budget.stats().resident_bytes, 0);
+    assert_eq!(budget.stats().evictions, 1);
+  }
+}
+
+#[cfg(test)]
+mod asset_tests {
+  use crate::asset::AssetIds;
+
+  #[test]
+  fn test_movie_namespaces() {
+    let mut ids = AssetIds::new();
+    let loader = ids.create_movie();
+    let child = ids.create_movie();
+    assert_ne!(loader, child);
+
+    let loader_shape = ids.asset_id(loader, 1);
+    let child_shape = ids.asset_id(child, 1);
+    assert_ne!(loader_shape, child_shape);
+    assert_eq!(ids.asset_id(loader, 1), loader_shape);
+
+    let child_sprite = ids.asset_id(child, 2);
+    assert_eq!(ids.release_movie(child), vec![child_shape, child_sprite]);
+    assert_eq!(ids.release_movie(child), vec![]);
+    assert_eq!(ids.asset_id(loader, 1), loader_shape);
+  }
+}
+
+#[cfg(test)]
+mod protocol_tests {
+  use crate::asset::{AssetIds, ClientAssetStore, MorphShapeId, MovieId, ShapeId};
+  use crate::error::RendererError;
+  use crate::protocol::{channel, Command, RenderClient, RenderServer, Response, StreamTransport, Transport};
+  use crate::renderer_tests::load_flat_shape;
+  use crate::stage::{ColorTransform, DisplayPrimitive, Matrix2D, Stage, StoredShape};
+  use crate::swf_renderer::SwfRenderer;
+  use crate::viewport::Viewport;
+  use std::collections::HashMap;
+  use swf_tree::tags::{DefineMorphShape, DefineShape};
+
+  /// Renderer recording the shapes and stages it receives, it rejects the shapes without records.
+  struct RecordingRenderer {
+    ids: AssetIds,
+    /// Character id of each registered shape
+    shapes: HashMap<usize, u16>,
+    stages: Vec<Stage>,
+  }
+
+  impl ClientAssetStore for RecordingRenderer {
+    fn create_movie(&mut self) -> MovieId {
+      self.ids.create_movie()
+    }
+
+    fn release_movie(&mut self, movie: MovieId) -> () {
+      for id in self.ids.release_movie(movie) {
+        self.shapes.remove(&id);
+      }
+    }
+
+    fn register_shape(&mut self, movie: MovieId, tag: &DefineShape) -> Result<ShapeId, RendererError> {
+      let id = ShapeId(self.ids.asset_id(movie, tag.id));
+      self.redefine_shape(id, tag)?;
+      Ok(id)
+    }
+
+    fn register_morph_shape(&mut self, movie: MovieId, tag: &DefineMorphShape) -> Result<MorphShapeId, RendererError> {
+      Ok(MorphShapeId(self.ids.asset_id(movie, tag.id)))
+    }
+
+    fn redefine_shape(&mut self, id: ShapeId, tag: &DefineShape) -> Result<(), RendererError> {
+      if tag.shape.records.is_empty() {
+        return Err(RendererError::Gfx("Empty shape"));
+      }
+      self.shapes.insert(id.0, tag.id);
+      Ok(())
+    }
+
+    fn redefine_morph_shape(&mut self, _id: MorphShapeId, _tag: &DefineMorphShape) -> Result<(), RendererError> {
+      Ok(())
+    }
+
+    fn release_shape(&mut self, id: ShapeId) -> () {
+      self.shapes.remove(&id.0);
+    }
+
+    fn release_morph_shape(&mut self, _id: MorphShapeId) -> () {}
+  }
+
+  impl SwfRenderer for RecordingRenderer {
+    fn render(&mut self, stage: Stage) -> Result<(), RendererError> {
+      self.stages.push(stage);
+      Ok(())
+    }
+
+    fn resize(&mut self, _width: u32, _height: u32) -> Result<(), RendererError> {
+      Err(RendererError::Gfx("Resize not supported"))
+    }
+    fn set_viewport(&mut self, _viewport: Viewport) -> () {}
+  }
+
+  fn shape_stage(ids: &[ShapeId]) -> Stage {
+    Stage {
+      background_color: swf_tree::StraightSRgba8 {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+      },
+      display_root: ids
+        .iter()
+        .map(|id| {
+          DisplayPrimitive::Shape(StoredShape {
+            id: *id,
+            matrix: Matrix2D::default(),
+            color_transform: ColorTransform::default(),
+            name: None,
+          })
+        })
+        .collect(),
+    }
+  }
+
+  fn stage_shape_ids(stage: &Stage) -> Vec<usize> {
+    stage
+      .display_root
+      .iter()
+      .map(|primitive| match primitive {
+        DisplayPrimitive::Shape(shape) => shape.id.0,
+        _ => panic!("Expected a shape"),
+      })
+      .collect()
+  }
+
+  #[test]
+  fn test_client_server_channel() {
+    let (client_transport, mut server_transport) = channel();
+    let server = ::std::thread::spawn(move || {
+      let mut server = RenderServer::new(RecordingRenderer {
+        ids: AssetIds::new(),
+        shapes: HashMap::new(),
+        stages: Vec::new(),
+      });
+      server.run(&mut server_transport).unwrap();
+      server.into_renderer()
+    });
+
+    let squares = load_flat_shape("squares");
+    let mut client = RenderClient::new(client_transport);
+    let loader = client.create_movie();
+    let child = client.create_movie();
+    let loader_shape = client.register_shape(loader, &squares).unwrap();
+    let child_shape = client.register_shape(child, &squares).unwrap();
+    client.render(shape_stage(&[loader_shape, child_shape])).unwrap();
+    match client.resize(100, 100) {
+      Err(RendererError::Remote(message)) => assert_eq!(message, "Resize not supported"),
+      _ => panic!("Expected a remote error"),
+    }
+    client.release_movie(child);
+    // The released shape is removed from the stage
+    client.render(shape_stage(&[loader_shape, child_shape])).unwrap();
+    drop(client);
+
+    let renderer = server.join().unwrap();
+    assert_eq!(renderer.stages.len(), 2);
+    let ids = stage_shape_ids(&renderer.stages[0]);
+    assert_eq!(ids.len(), 2);
+    assert_ne!(ids[0], ids[1]);
+    assert_eq!(stage_shape_ids(&renderer.stages[1]), vec![ids[0]]);
+    assert_eq!(renderer.shapes.len(), 1);
+  }
+
+  #[test]
+  fn test_asset_errors() {
+    let (client_transport, mut server_transport) = channel();
+    let server = ::std::thread::spawn(move || {
+      let mut server = RenderServer::new(RecordingRenderer {
+        ids: AssetIds::new(),
+        shapes: HashMap::new(),
+        stages: Vec::new(),
+      });
+      server.run(&mut server_transport).unwrap();
+      server.into_renderer()
+    });
+
+    let squares = load_flat_shape("squares");
+    let mut empty = load_flat_shape("triangle");
+    empty.id = squares.id + 1;
+    empty.shape.records.clear();
+    let mut client = RenderClient::new(client_transport);
+    let movie = client.create_movie();
+    let rejected = client.register_shape(movie, &empty).unwrap();
+    let shape = client.register_shape(movie, &squares).unwrap();
+    // The registration error is returned by the next request, the frame is still rendered
+    match client.render(shape_stage(&[rejected, shape])) {
+      Err(RendererError::Remote(message)) => assert_eq!(message, "Empty shape"),
+      _ => panic!("Expected a remote error"),
+    }
+    client.render(shape_stage(&[rejected, shape])).unwrap();
+    // A failed redefinition keeps the previous definition
+    let mut empty_squares = squares.clone();
+    empty_squares.shape.records.clear();
+    client.redefine_shape(shape, &empty_squares).unwrap();
+    assert!(client.render(shape_stage(&[shape])).is_err());
+    drop(client);
+
+    let renderer = server.join().unwrap();
+    assert_eq!(renderer.stages.len(), 3);
+    // The rejected shape has no renderer id: it is removed from the stages
+    assert_eq!(renderer.stages[0], renderer.stages[1]);
+    assert_eq!(stage_shape_ids(&renderer.stages[1]).len(), 1);
+    assert_eq!(renderer.shapes.values().collect::<Vec<_>>(), vec![&squares.id]);
+  }
+
+  #[test]
+  fn test_stream_transport() {
+    let squares = load_flat_shape("squares");
+    let mut ids = AssetIds::new();
+    let movie = ids.create_movie();
+    let shape = ShapeId(ids.asset_id(movie, squares.id));
+
+    let mut writer = StreamTransport::new(::std::io::empty(), Vec::new());
+    for command in vec![
+      Command::CreateMovie(movie),
+      Command::RegisterShape(movie, shape, squares.clone()),
+      Command::Render(shape_stage(&[shape])),
+    ] {
+      Transport::<Command, Response>::send(&mut writer, command).unwrap();
+    }
+    let (_, bytes) = writer.into_inner();
+
+    let mut reader = StreamTransport::new(&bytes[..], ::std::io::sink());
+    let mut commands: Vec<Command> = Vec::new();
+    while let Some(command) = Transport::<Response, Command>::receive(&mut reader).unwrap() {
+      commands.push(command);
+    }
+    assert_eq!(commands.len(), 3);
+    match &commands[1] {
+      Command::RegisterShape(received_movie, received_shape, tag) => {
+        assert_eq!((*received_movie, *received_shape), (movie, shape));
+        assert_eq!(tag, &squares);
+      }
+      _ => panic!("Expected a shape registration"),
+    }
+  }
+}
+
+#[cfg(test)]
+mod stage_tests {
+  use crate::asset::{MorphShapeId, ShapeId};
+  use crate::stage::{
+    ButtonState, ClipLayer, ColorTransform, DisplayButton, DisplayContainer, DisplayPrimitive, Matrix2D, MorphRatio,
+    Stage, StoredMorphShape, StoredShape,
+  };
+  use ::test_generator::test_resources;
+  use std::path::Path;
+
+  fn shape(id: usize) -> DisplayPrimitive {
+    DisplayPrimitive::Shape(StoredShape {
+      id: ShapeId(id),
+      matrix: Matrix2D::default(),
+      color_transform: ColorTransform::default(),
+      name: None,
+    })
+  }
+
+  /// Stage fixtures: the shape ids of `stage.json` are indices in `shapes.json`, listing samples of
+  /// `tests/flat-shapes`.
+  #[test_resources("../tests/stages/*/")]
+  fn test_stage_fixture(path: &str) {
+    let path: &Path = Path::new(path);
+    let stage_json = ::std::fs::read_to_string(path.join("stage.json")).expect("Failed to read stage");
+    let json: serde_json::Value = serde_json::from_str(&stage_json).unwrap();
+    let stage: Stage = serde_json::from_value(json.clone()).expect("Failed to parse stage");
+    assert_eq!(serde_json::to_value(&stage).unwrap(), json);
+    assert_eq!(Stage::from_bytes(&stage.to_bytes().unwrap()).unwrap(), stage);
+
+    let shapes_json = ::std::fs::read_to_string(path.join("shapes.json")).expect("Failed to read shapes");
+    let shapes: Vec<String> = serde_json::from_str(&shapes_json).unwrap();
+    for primitive in stage.display_root.iter() {
+      if let DisplayPrimitive::Shape(ref shape) = primitive {
+        let ast_path = Path::new("../tests/flat-shapes")
+          .join(&shapes[shape.id.0])
+          .join("ast.json");
+        assert!(ast_path.is_file());
+      }
+    }
+  }
+
+  #[test]
+  fn test_nested_primitives_round_trip() {
+    let stage = Stage {
+      background_color: swf_tree::StraightSRgba8 {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 255,
+      },
+      display_root: vec![
+        DisplayPrimitive::Container(DisplayContainer {
+          matrix: Matrix2D([2.0, 2.0, 0.0, 0.0, 100.0, 200.0]),
+          color_transform: ColorTransform::default(),
+          blend_mode: swf_tree::BlendMode::Normal,
+          filters: Vec::new(),
+          name: Some(String::from("sprite")),
+          children: vec![
+            DisplayPrimitive::MorphShape(StoredMorphShape {
+              id: MorphShapeId(1),
+              matrix: Matrix2D::default(),
+              color_transform: ColorTransform::default(),
+              ratio: MorphRatio(0x8000),
+              name: None,
+            }),
+            DisplayPrimitive::Clip(ClipLayer {
+              clip_depth: 4,
+              mask: Box::new(shape(2)),
+              children: vec![shape(3)],
+            }),
+          ],
+        }),
+        DisplayPrimitive::Button(DisplayButton {
+          matrix: Matrix2D::default(),
+          color_transform: ColorTransform::default(),
+          name: None,
+          state: ButtonState::Over,
+          children: vec![shape(4)],
+          hit_area: vec![shape(5)],
+        }),
+      ],
+    };
+
+    let json = serde_json::to_string(&stage).unwrap();
+    assert_eq!(serde_json::from_str::<Stage>(&json).unwrap(), stage);
+    let bytes = stage.to_bytes().unwrap();
+    assert_eq!(Stage::from_bytes(&bytes).unwrap(), stage);
+  }
+}
+
+#[cfg(test)]
+mod recording_tests {
+  use crate::asset::{ClientAssetStore, MorphShapeId, ShapeId};
+  use crate::headless_renderer::HeadlessGfxRenderer;
+  use crate::recording::{replay, Recorder};
+  use crate::renderer::{flatten_stage, DisplayItem, Image};
+  use crate::renderer_tests::load_flat_shape;
+  use crate::stage::{
+    ClipLayer, ColorTransform, DisplayContainer, DisplayPrimitive, Matrix2D, MorphRatio, StoredMorphShape,
+  };
+  use crate::stage_helpers::{shape, stage, translate};
+  use crate::swf_renderer::SwfRenderer;
+  use crate::viewport::ScaleMode;
+  use gfx_backend_vulkan as gfx_backend;
+  use gfx_hal::Instance;
+
+  fn half_red() -> ColorTransform {
+    ColorTransform {
+      mult: [0.5, 1.0, 1.0, 1.0],
+      add: [0.5, 0.0, 0.0, 0.0],
+    }
+  }
+
+  #[test]
+  fn test_flatten_stage() {
+    let container = DisplayPrimitive::Container(DisplayContainer {
+      matrix: translate(100.0, 0.0),
+      color_transform: half_red(),
+      blend_mode: swf_tree::BlendMode::Normal,
+      filters: Vec::new(),
+      name: None,
+      children: vec![
+        shape(ShapeId(1), translate(0.0, 50.0)),
+        DisplayPrimitive::MorphShape(StoredMorphShape {
+          id: MorphShapeId(2),
+          matrix: Matrix2D::default(),
+          color_transform: ColorTransform::default(),
+          ratio: MorphRatio(0),
+          name: None,
+        }),
+        DisplayPrimitive::Clip(ClipLayer {
+          clip_depth: 3,
+          mask: Box::new(shape(ShapeId(3), Matrix2D::default())),
+          children: vec![shape(ShapeId(4), Matrix2D::default())],
+        }),
+      ],
+    });
+    let items = flatten_stage(&stage(vec![shape(ShapeId(0), Matrix2D::default()), container]));
+
+    let flattened: Vec<(usize, Matrix2D, ColorTransform)> = items
+      .into_iter()
+      .map(|item| match item {
+        DisplayItem::Shape(id, matrix, color_transform) => (id, matrix, color_transform),
+      })
+      .collect();
+    assert_eq!(
+      flattened,
+      vec![
+        (0, Matrix2D::default(), ColorTransform::default()),
+        (1, translate(100.0, 50.0), half_red()),
+        (4, translate(100.0, 0.0), half_red()),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_record_and_replay() {
+    let instance = gfx_backend::Instance::create("ofl-renderer", 1).expect("Failed to create Instance");
+    let mut renderer = HeadlessGfxRenderer::<gfx_backend::Backend>::new(&instance, 300, 200).unwrap();
+    renderer.viewport.scale_mode = ScaleMode::NoScale;
+    renderer.viewport.letterbox_color = swf_tree::StraightSRgba8 {
+      r: 0,
+      g: 0,
+      b: 255,
+      a: 255,
+    };
+    let viewport = renderer.viewport.clone();
+    let mut recorder = Recorder::new(renderer, Vec::new(), 300, 200, viewport);
+
+    let movie = recorder.create_movie();
+    let squares = recorder.register_shape(movie, &load_flat_shape("squares")).unwrap();
+    let triangle = recorder.register_shape(movie, &load_flat_shape("triangle")).unwrap();
+    let frames = vec![
+      stage(vec![shape(squares, Matrix2D::default())]),
+      stage(vec![
+        shape(squares, translate(400.0, 200.0)),
+        DisplayPrimitive::Container(DisplayContainer {
+          matrix: translate(1000.0, 600.0),
+          color_transform: half_red(),
+          blend_mode: swf_tree::BlendMode::Normal,
+          filters: Vec::new(),
+          name: None,
+          children: vec![shape(triangle, Matrix2D::default())],
+        }),
+      ]),
+    ];
+    let mut recorded: Vec<Image> = Vec::new();
+    for (index, frame) in frames.into_iter().enumerate() {
+      if index == 1 {
+        recorder.resize(200, 150).unwrap();
+      }
+      recorder.render(frame).unwrap();
+      recorded.push(recorder.renderer().download_image().unwrap());
+    }
+    let (renderer, recording) = recorder.finish().unwrap();
+    drop(renderer);
+
+    // The recording sets the size and the viewport of the replay renderer
+    let renderer = HeadlessGfxRenderer::<gfx_backend::Backend>::new(&instance, 100, 100).unwrap();
+    let mut replayed: Vec<Image> = Vec::new();
+    replay(&mut &recording[..], renderer, |renderer, frame| {
+      assert_eq!(frame, replayed.len());
+      replayed.push(renderer.download_image()?);
+      Ok(())
+    })
+    .unwrap();
+
+    assert_eq!(replayed.len(), recorded.len());
+    for (replayed, recorded) in replayed.iter().zip(recorded.iter()) {
+      assert_eq!(
+        (replayed.meta.width, replayed.meta.height),
+        (recorded.meta.width, recorded.meta.height)
+      );
+      assert_eq!(replayed.data, recorded.data);
+    }
+    assert_eq!((recorded[0].meta.width, recorded[0].meta.height), (300, 200));
+    assert_eq!((recorded[1].meta.width, recorded[1].meta.height), (200, 150));
+  }
+}
+
+#[cfg(test)]
+mod damage_tests {
+  use crate::asset::{ClientAssetStore, MorphShapeId, ShapeId};
+  use crate::damage::{stage_damage, Damage};
+  use crate::headless_renderer::HeadlessGfxRenderer;
+  use crate::renderer::{GfxSymbol, ShapeStore};
+  use crate::renderer_tests::load_flat_shape;
+  use crate::stage::{ColorTransform, DisplayPrimitive, Matrix2D, MorphRatio, Stage, StoredMorphShape};
+  use crate::stage_helpers::{clip, container, shape, stage, translate};
+  use crate::swf_renderer::SwfRenderer;
+  use gfx_backend_vulkan as gfx_backend;
+  use gfx_hal::Instance;
+
+  #[test]
+  fn test_stage_damage() {
+    let mut store = ShapeStore::new();
+    let id = ShapeId(1);
+    store.define_shape(id.0, &load_flat_shape("squares")).unwrap();
+    let bounds = match store.get(id.0) {
+      Some(GfxSymbol::Shape(ref symbol)) => symbol.tight_bounds.expect("Expected non-empty shape"),
+      _ => panic!("Expected shape symbol"),
+    };
+
+    let previous = stage(vec![
+      shape(id, Matrix2D::default()),
+      container(translate(1000.0, 0.0), vec![shape(id, Matrix2D::default())]),
+    ]);
+    assert_eq!(stage_damage(&previous, &previous.clone(), &store), Damage::Unchanged);
+
+    // Moving a nested shape damages its previous and next areas, in stage coordinates
+    let moved = stage(vec![
+      shape(id, Matrix2D::default()),
+      container(translate(1000.0, 0.0), vec![shape(id, translate(0.0, 500.0))]),
+    ]);
+    assert_eq!(
+      stage_damage(&previous, &moved, &store),
+      Damage::Region(
+        translate(1000.0, 0.0)
+          .transform_bounds(&bounds)
+          .union(&translate(1000.0, 500.0).transform_bounds(&bounds))
+      )
+    );
+
+    // Removing a primitive damages its area
+    let removed = stage(vec![shape(id, Matrix2D::default())]);
+    assert_eq!(
+      stage_damage(&previous, &removed, &store),
+      Damage::Region(translate(1000.0, 0.0).transform_bounds(&bounds))
+    );
+
+    let recolored = Stage {
+      background_color: swf_tree::StraightSRgba8 {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 255,
+      },
+      ..previous.clone()
+    };
+    assert_eq!(stage_damage(&previous, &recolored, &store), Damage::Full);
+
+    // Morph shapes have no bounds yet
+    let morph = |ratio: u16| {
+      stage(vec![DisplayPrimitive::MorphShape(StoredMorphShape {
+        id: MorphShapeId(1),
+        matrix: Matrix2D::default(),
+        color_transform: ColorTransform::default(),
+        ratio: MorphRatio(ratio),
+        name: None,
+      })])
+    };
+    assert_eq!(stage_damage(&morph(0), &morph(0), &store), Damage::Unchanged);
+    assert_eq!(stage_damage(&morph(0), &morph(100), &store), Damage::Full);
+  }
+
+  #[test]
+  fn test_incremental_rendering() {
+    let instance = gfx_backend::Instance::create("ofl-renderer", 1).expect("Failed to create Instance");
+    let mut incremental = HeadlessGfxRenderer::<gfx_backend::Backend>::new(&instance, 300, 200).unwrap();
+    let mut full = HeadlessGfxRenderer::<gfx_backend::Backend>::new(&instance, 300, 200).unwrap();
+    full.set_incremental(false);
+
+    let squares = load_flat_shape("squares");
+    let incremental_id = {
+      let movie = incremental.create_movie();
+      incremental.register_shape(movie, &squares).unwrap()
+    };
+    let full_id = {
+      let movie = full.create_movie();
+      full.register_shape(movie, &squares).unwrap()
+    };
+
+    let frames = |id: ShapeId| {
+      vec![
+        stage(vec![shape(id, Matrix2D::default()), shape(id, translate(3000.0, 0.0))]),
+        stage(vec![
+          shape(id, translate(200.0, 100.0)),
+          shape(id, translate(3000.0, 0.0)),
+        ]),
+        stage(vec![
+          shape(id, translate(200.0, 100.0)),
+          shape(id, translate(3000.0, 0.0)),
+        ]),
+        stage(vec![shape(id, translate(200.0, 100.0))]),
+      ]
+    };
+    for (incremental_frame, full_frame) in frames(incremental_id).into_iter().zip(frames(full_id).into_iter()) {
+      incremental.render(incremental_frame).unwrap();
+      full.render(full_frame).unwrap();
+      assert_eq!(
+        incremental.download_image().unwrap().data,
+        full.download_image().unwrap().data
+      );
+    }
+  }
+
+  #[test]
+  fn test_clip_damage() {
+    let mut store = ShapeStore::new();
+    let id = ShapeId(1);
+    store.define_shape(id.0, &load_flat_shape("squares")).unwrap();
+    let bounds = match store.get(id.0) {
+      Some(GfxSymbol::Shape(ref symbol)) => symbol.tight_bounds.expect("Expected non-empty shape"),
+      _ => panic!("Expected shape symbol"),
+    };
+    // The mask does not overlap the child
+    let mask = |x: f32| shape(id, Matrix2D([0.1, 0.1, 0.0, 0.0, x, 0.0]));
+
+    // The masks are not applied when drawing: the damage covers the whole child
+    let previous = stage(vec![clip(mask(0.0), vec![shape(id, Matrix2D::default())])]);
+    let moved_mask = stage(vec![clip(mask(100.0), vec![shape(id, Matrix2D::default())])]);
+    assert_eq!(stage_damage(&previous, &moved_mask, &store), Damage::Region(bounds));
+    assert_eq!(
+      stage_damage(&previous, &stage(Vec::new()), &store),
+      Damage::Region(bounds)
+    );
+
+    let instance = gfx_backend::Instance::create("ofl-renderer", 1).expect("Failed to create Instance");
+    let mut incremental = HeadlessGfxRenderer::<gfx_backend::Backend>::new(&instance, 300, 200).unwrap();
+    let mut full = HeadlessGfxRenderer::<gfx_backend::Backend>::new(&instance, 300, 200).unwrap();
+    full.set_incremental(false);
+    let squares = load_flat_shape("squares");
+    let incremental_id = {
+      let movie = incremental.create_movie();
+      incremental.register_shape(movie, &squares).unwrap()
+    };
+    let full_id = {
+      let movie = full.create_movie();
+      full.register_shape(movie, &squares).unwrap()
+    };
+
+    let frames = |id: ShapeId| {
+      let mask = |x: f32| shape(id, Matrix2D([0.1, 0.1, 0.0, 0.0, x, 0.0]));
+      vec![
+        stage(vec![clip(mask(0.0), vec![shape(id, Matrix2D::default())])]),
+        stage(vec![clip(mask(100.0), vec![shape(id, translate(200.0, 100.0))])]),
+        stage(Vec::new()),
+      ]
+    };
+    for (incremental_frame, full_frame) in frames(incremental_id).into_iter().zip(frames(full_id).into_iter()) {
+      incremental.render(incremental_frame).unwrap();
+      full.render(full_frame).unwrap();
+      assert_eq!(
+        incremental.download_image().unwrap().data,
+        full.download_image().unwrap().data
+      );
+    }
+  }
+
+  #[test]
+  fn test_release_movie_damage() {
+    let instance = gfx_backend::Instance::create("ofl-renderer", 1).expect("Failed to create Instance");
+    let mut renderer = HeadlessGfxRenderer::<gfx_backend::Backend>::new(&instance, 300, 200).unwrap();
+    let movie = renderer.create_movie();
+    let id = renderer.register_shape(movie, &load_flat_shape("squares")).unwrap();
+
+    renderer.render(stage(Vec::new())).unwrap();
+    let cleared = renderer.download_image().unwrap();
+    renderer.render(stage(vec![shape(id, Matrix2D::default())])).unwrap();
+    assert_ne!(renderer.download_image().unwrap().data, cleared.data);
+
+    // The bounds of the released shape are unknown: the next frame is fully drawn
+    renderer.release_movie(movie);
+    renderer.render(stage(Vec::new())).unwrap();
+    assert_eq!(renderer.download_image().unwrap().data, cleared.data);
+  }
+}
+
+#[cfg(test)]
+mod timeline_tests {
+  use crate::asset::{AssetIds, ClientAssetStore, MorphShapeId, MovieId, ShapeId};
+  use crate::error::RendererError;
+  use crate::renderer_tests::load_flat_shape;
+  use crate::stage::{
+    ButtonState, ClipLayer, ColorTransform, DisplayButton, DisplayPrimitive, Matrix2D, Stage, StoredShape,
+  };
+  use crate::stage_helpers::{container, shape, translate};
+  use crate::timeline::DisplayList;
+  use serde_json::{json, Value};
+  use swf_tree::tags::{DefineMorphShape, DefineShape};
+  use swf_tree::Tag;
+
+  /// Asset store allocating the ids only.
+  struct IdStore {
+    ids: AssetIds,
+  }
+
+  impl ClientAssetStore for IdStore {
+    fn create_movie(&mut self) -> MovieId {
+      self.ids.create_movie()
+    }
+
+    fn release_movie(&mut self, movie: MovieId) -> () {
+      self.ids.release_movie(movie);
+    }
+
+    fn register_shape(&mut self, movie: MovieId, tag: &DefineShape) -> Result<ShapeId, RendererError> {
+      Ok(ShapeId(self.ids.asset_id(movie, tag.id)))
+    }
+
+    fn register_morph_shape(&mut self, movie: MovieId, tag: &DefineMorphShape) -> Result<MorphShapeId, RendererError> {
+      Ok(MorphShapeId(self.ids.asset_id(movie, tag.id)))
+    }
+
+    fn redefine_shape(&mut self, _id: ShapeId, _tag: &DefineShape) -> Result<(), RendererError> {
+      Ok(())
+    }
+
+    fn redefine_morph_shape(&mut self, _id: MorphShapeId, _tag: &DefineMorphShape) -> Result<(), RendererError> {
+      Ok(())
+    }
+
+    fn release_shape(&mut self, _id: ShapeId) -> () {}
+
+    fn release_morph_shape(&mut self, _id: MorphShapeId) -> () {}
+  }
+
+  /// Display list of a movie defining the squares sample as character `1` and the triangle sample as
+  /// character `2`.
+  struct Movie {
+    store: IdStore,
+    display_list: DisplayList,
+    squares: ShapeId,
+    triangle: ShapeId,
+  }
+
+  impl Movie {
+    fn new() -> Self {
+      let mut store = IdStore { ids: AssetIds::new() };
+      let movie = store.create_movie();
+      let mut display_list = DisplayList::new(movie);
+      let mut squares = load_flat_shape("squares");
+      squares.id = 1;
+      let mut triangle = load_flat_shape("triangle");
+      triangle.id = 2;
+      assert!(display_list.apply_tag(&mut store, &Tag::DefineShape(squares)).is_none());
+      assert!(display_list
+        .apply_tag(&mut store, &Tag::DefineShape(triangle))
+        .is_none());
+      let squares = ShapeId(store.ids.asset_id(movie, 1));
+      let triangle = ShapeId(store.ids.asset_id(movie, 2));
+      Self {
+        store,
+        display_list,
+        squares,
+        triangle,
+      }
+    }
+
+    /// Applies tags that are not `ShowFrame`.
+    fn apply(&mut self, tags: Vec<Value>) -> () {
+      for tag in tags.into_iter() {
+        assert!(self.display_list.apply_tag(&mut self.store, &parse_tag(tag)).is_none());
+      }
+    }
+
+    fn show_frame(&mut self) -> Stage {
+      self
+        .display_list
+        .apply_tag(&mut self.store, &parse_tag(json!({"type": "show-frame"})))
+        .expect("Expected a stage")
+    }
+  }
+
+  fn parse_tag(tag: Value) -> Tag {
+    serde_json::from_value(tag).expect("Failed to parse tag")
+  }
+
+  /// Translation matrix in the JSON format of `swf_tree` (fixed-point scales, in 1/65536).
+  fn matrix_json(translate_x: i32, translate_y: i32) -> Value {
+    json!({
+      "scale_x": 65536,
+      "scale_y": 65536,
+      "rotate_skew0": 0,
+      "rotate_skew1": 0,
+      "translate_x": translate_x,
+      "translate_y": translate_y,
+    })
+  }
+
+  fn place(depth: u16, character_id: u16) -> Value {
+    json!({"type": "place-object", "is_update": false, "depth": depth, "character_id": character_id})
+  }
+
+  #[test]
+  fn test_place_and_remove_objects() {
+    let mut movie = Movie::new();
+    let mut placed = place(1, 1);
+    placed["matrix"] = matrix_json(100, 200);
+    movie.apply(vec![placed, place(2, 2)]);
+    let stage = movie.show_frame();
+    assert_eq!(
+      stage.display_root,
+      vec![
+        shape(movie.squares, translate(100.0, 200.0)),
+        shape(movie.triangle, Matrix2D::default())
+      ]
+    );
+
+    // A character id mismatch is ignored
+    movie.apply(vec![
+      json!({"type": "remove-object", "depth": 2, "character_id": 1}),
+      json!({"type": "remove-object", "depth": 1}),
+    ]);
+    assert_eq!(
+      movie.show_frame().display_root,
+      vec![shape(movie.triangle, Matrix2D::default())]
+    );
+  }
+
+  #[test]
+  fn test_move_and_modify_objects() {
+    let mut movie = Movie::new();
+    let mut placed = place(1, 1);
+    placed["name"] = json!("square");
+    movie.apply(vec![placed]);
+
+    // Moving keeps the other properties
+    movie.apply(vec![
+      json!({"type": "place-object", "is_update": true, "depth": 1, "matrix": matrix_json(40, 60)}),
+    ]);
+    let moved = DisplayPrimitive::Shape(StoredShape {
+      id: movie.squares,
+      matrix: translate(40.0, 60.0),
+      color_transform: ColorTransform::default(),
+      name: Some(String::from("square")),
+    });
+    assert_eq!(movie.show_frame().display_root, vec![moved]);
+
+    // Replacing the character keeps the transform and the name
+    movie.apply(vec![
+      json!({"type": "place-object", "is_update": true, "depth": 1, "character_id": 2}),
+      // No object to update: ignored
+      json!({"type": "place-object", "is_update": true, "depth": 3, "matrix": matrix_json(0, 0)}),
+    ]);
+    let replaced = DisplayPrimitive::Shape(StoredShape {
+      id: movie.triangle,
+      matrix: translate(40.0, 60.0),
+      color_transform: ColorTransform::default(),
+      name: Some(String::from("square")),
+    });
+    assert_eq!(movie.show_frame().display_root, vec![replaced]);
+  }
+
+  #[test]
+  fn test_clip_depth() {
+    let mut movie = Movie::new();
+    let mut mask = place(1, 1);
+    mask["clip_depth"] = json!(3);
+    let mut nested_mask = place(2, 2);
+    nested_mask["clip_depth"] = json!(5);
+    movie.apply(vec![mask, nested_mask, place(3, 1), place(4, 2)]);
+
+    // The nested clip layer ends with its parent, at depth 3
+    assert_eq!(
+      movie.show_frame().display_root,
+      vec![
+        DisplayPrimitive::Clip(ClipLayer {
+          clip_depth: 3,
+          mask: Box::new(shape(movie.squares, Matrix2D::default())),
+          children: vec![DisplayPrimitive::Clip(ClipLayer {
+            clip_depth: 3,
+            mask: Box::new(shape(movie.triangle, Matrix2D::default())),
+            children: vec![shape(movie.squares, Matrix2D::default())],
+          })],
+        }),
+        shape(movie.triangle, Matrix2D::default()),
+      ]
+    );
+  }
+
+  /// Sprite instance without transform.
+  fn sprite(children: Vec<DisplayPrimitive>) -> DisplayPrimitive {
+    container(Matrix2D::default(), children)
+  }
+
+  /// Sprite `10` with 3 frames: the squares, the squares moved, then the triangle.
+  fn define_animated_sprite() -> Value {
+    let mut moved = json!({"type": "place-object", "is_update": true, "depth": 1});
+    moved["matrix"] = matrix_json(100, 0);
+    json!({
+      "type": "define-sprite",
+      "id": 10,
+      "frame_count": 3,
+      "tags": [
+        place(1, 1),
+        {"type": "show-frame"},
+        moved,
+        {"type": "show-frame"},
+        {"type": "remove-object", "depth": 1},
+        place(2, 2),
+        {"type": "show-frame"},
+      ],
+    })
+  }
+
+  #[test]
+  fn test_sprite_frames() {
+    let mut movie = Movie::new();
+    movie.apply(vec![define_animated_sprite(), place(1, 10)]);
+    let squares = shape(movie.squares, Matrix2D::default());
+    let moved = shape(movie.squares, translate(100.0, 0.0));
+    let triangle = shape(movie.triangle, Matrix2D::default());
+
+    assert_eq!(movie.show_frame().display_root, vec![sprite(vec![squares.clone()])]);
+
+    // Each instance has its own frame cursor
+    movie.apply(vec![place(2, 10)]);
+    assert_eq!(
+      movie.show_frame().display_root,
+      vec![sprite(vec![moved.clone()]), sprite(vec![squares.clone()])]
+    );
+    assert_eq!(
+      movie.show_frame().display_root,
+      vec![sprite(vec![triangle.clone()]), sprite(vec![moved.clone()])]
+    );
+
+    // After its last frame, a sprite loops to its first frame, removing the objects it does not place
+    assert_eq!(
+      movie.show_frame().display_root,
+      vec![sprite(vec![squares.clone()]), sprite(vec![triangle.clone()])]
+    );
+    assert_eq!(
+      movie.show_frame().display_root,
+      vec![sprite(vec![moved]), sprite(vec![squares])]
+    );
+  }
+
+  #[test]
+  fn test_nested_sprites() {
+    let mut movie = Movie::new();
+    // Sprite `11` with 2 frames: an instance of sprite `10`, then the triangle added above it
+    let outer = json!({
+      "type": "define-sprite",
+      "id": 11,
+      "frame_count": 2,
+      "tags": [place(1, 10), {"type": "show-frame"}, place(2, 2), {"type": "show-frame"}],
+    });
+    movie.apply(vec![define_animated_sprite(), outer, place(1, 11)]);
+    let squares = shape(movie.squares, Matrix2D::default());
+    let moved = shape(movie.squares, translate(100.0, 0.0));
+    let triangle = shape(movie.triangle, Matrix2D::default());
+
+    assert_eq!(
+      movie.show_frame().display_root,
+      vec![sprite(vec![sprite(vec![squares.clone()])])]
+    );
+    // The nested instance advances with its parent
+    assert_eq!(
+      movie.show_frame().display_root,
+      vec![sprite(vec![sprite(vec![moved]), triangle.clone()])]
+    );
+    // Looping the parent keeps the nested instance placed by its first frame, with its frame cursor
+    assert_eq!(
+      movie.show_frame().display_root,
+      vec![sprite(vec![sprite(vec![triangle.clone()])])]
+    );
+    assert_eq!(
+      movie.show_frame().display_root,
+      vec![sprite(vec![sprite(vec![squares]), triangle])]
+    );
+  }
+
+  #[test]
+  fn test_background_color() {
+    let mut movie = Movie::new();
+    assert_eq!(
+      movie.show_frame().background_color,
+      swf_tree::StraightSRgba8 {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+      }
+    );
+    movie.apply(vec![
+      json!({"type": "set-background-color", "color": {"r": 10, "g": 20, "b": 30}}),
+    ]);
+    let stage = movie.show_frame();
+    assert_eq!(
+      stage.background_color,
+      swf_tree::StraightSRgba8 {
+        r: 10,
+        g: 20,
+        b: 30,
+        a: 255,
+      }
+    );
+    assert!(stage.display_root.is_empty());
+  }
+
+  /// Button record in the JSON format of `swf_tree`, with the states `[up, over, down, hit_test]`.
+  fn button_record(character_id: u16, depth: u16, states: [bool; 4], matrix: Value) -> Value {
+    json!({
+      "state_up": states[0],
+      "state_over": states[1],
+      "state_down": states[2],
+      "state_hit_test": states[3],
+      "character_id": character_id,
+      "depth": depth,
+      "matrix": matrix,
+      "color_transform": {
+        "red_mult": 256,
+        "green_mult": 256,
+        "blue_mult": 256,
+        "alpha_mult": 256,
+        "red_add": 0,
+        "green_add": 0,
+        "blue_add": 0,
+        "alpha_add": 0,
+      },
+      "filters": [],
+      "blend_mode": "normal",
+    })
+  }
+
+  /// Button `20`: the squares when up, the triangle when over or down, and the moved squares as hit area.
+  fn define_button() -> Value {
+    json!({
+      "type": "define-button",
+      "id": 20,
+      "track_as_menu": false,
+      "characters": [
+        button_record(1, 1, [true, false, false, false], matrix_json(0, 0)),
+        button_record(2, 2, [false, true, true, false], matrix_json(0, 0)),
+        button_record(1, 3, [false, false, false, true], matrix_json(100, 0)),
+      ],
+      "actions": [],
+    })
+  }
+
+  fn button(state: ButtonState, children: Vec<DisplayPrimitive>, hit_area: Vec<DisplayPrimitive>) -> DisplayPrimitive {
+    DisplayPrimitive::Button(DisplayButton {
+      matrix: Matrix2D::default(),
+      color_transform: ColorTransform::default(),
+      name: None,
+      state,
+      children,
+      hit_area,
+    })
+  }
+
+  #[test]
+  fn test_button_states() {
+    let mut movie = Movie::new();
+    movie.apply(vec![define_button(), place(5, 20)]);
+    let squares = shape(movie.squares, Matrix2D::default());
+    let triangle = shape(movie.triangle, Matrix2D::default());
+    let hit_area = vec![shape(movie.squares, translate(100.0, 0.0))];
+
+    assert_eq!(
+      movie.show_frame().display_root,
+      vec![button(ButtonState::Up, vec![squares.clone()], hit_area.clone())]
+    );
+    for state in [ButtonState::Over, ButtonState::Down].iter() {
+      assert!(movie.display_list.set_button_state(&[5], *state));
+      assert_eq!(
+        movie.show_frame().display_root,
+        vec![button(*state, vec![triangle.clone()], hit_area.clone())]
+      );
+    }
+    assert!(movie.display_list.set_button_state(&[5], ButtonState::Up));
+    assert_eq!(
+      movie.show_frame().display_root,
+      vec![button(ButtonState::Up, vec![squares], hit_area)]
+    );
+  }
+
+  #[test]
+  fn test_button_paths() {
+    let mut movie = Movie::new();
+    // Sprite `30` holding the button at depth 2
+    let sprite = json!({
+      "type": "define-sprite",
+      "id": 30,
+      "frame_count": 1,
+      "tags": [place(2, 20), {"type": "show-frame"}],
+    });
+    movie.apply(vec![define_button(), sprite, place(1, 1), place(7, 30)]);
+
+    assert!(movie.display_list.set_button_state(&[7, 2], ButtonState::Down));
+    match movie.show_frame().display_root[1] {
+      DisplayPrimitive::Container(ref sprite) => match sprite.children[0] {
+        DisplayPrimitive::Button(ref button) => assert_eq!(button.state, ButtonState::Down),
+        _ => panic!("Expected a button"),
+      },
+      _ => panic!("Expected a container"),
+    }
+
+    // Empty depth, shape, sprite, path through a shape and path past the button
+    for path in [&[][..], &[3], &[1], &[7], &[1, 1], &[7, 2, 1]].iter() {
+      assert!(!movie.display_list.set_button_state(path, ButtonState::Over));
+    }
+  }
 }
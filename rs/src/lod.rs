@@ -0,0 +1,104 @@
+/// Maximum distance between a curve and its tessellation on screen, in pixels.
+pub const SCREEN_TOLERANCE: f32 = 0.1;
+
+/// Maximum number of levels of detail kept for each symbol.
+const MAX_CACHED_LEVELS: usize = 4;
+
+/// Bounds of the levels of detail, the scale is clamped to `[2^MIN_LEVEL, 2^MAX_LEVEL]`.
+const MIN_LEVEL: i8 = -16;
+const MAX_LEVEL: i8 = 16;
+
+/// Level of detail of a tessellation.
+///
+/// The level `n` is used for instances displayed with a scale in `(2^(n-1), 2^n]` (in pixels per twip),
+/// so their tessellation error stays below `SCREEN_TOLERANCE` on screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct LodLevel(pub i8);
+
+impl LodLevel {
+  /// Returns the level of detail for an instance displayed with the provided scale (in pixels per twip).
+  pub fn from_scale(scale: f32) -> Self {
+    if !(scale > 0.0) {
+      return LodLevel(MIN_LEVEL);
+    }
+    let level = scale.log2().ceil();
+    LodLevel(f32::min(f32::max(level, f32::from(MIN_LEVEL)), f32::from(MAX_LEVEL)) as i8)
+  }
+
+  /// Largest scale (in pixels per twip) covered by this level.
+  pub fn max_scale(&self) -> f32 {
+    2f32.powi(i32::from(self.0))
+  }
+
+  /// Tessellation tolerance for this level, in twips.
+  pub fn tolerance(&self) -> f32 {
+    SCREEN_TOLERANCE / self.max_scale()
+  }
+}
+
+/// Cache of the few most recently used levels of detail of a symbol.
+pub struct LodCache<M> {
+  /// Cached levels, the most recently used first
+  levels: Vec<(LodLevel, M)>,
+}
+
+impl<M> LodCache<M> {
+  pub fn new() -> Self {
+    Self { levels: Vec::new() }
+  }
+
+  /// Returns the cached value for `level`, marking it as the most recently used.
+  pub fn get(&mut self, level: LodLevel) -> Option<&M> {
+    let index = self.levels.iter().position(|(cached, _)| *cached == level)?;
+    let entry = self.levels.remove(index);
+    self.levels.insert(0, entry);
+    Some(&self.levels[0].1)
+  }
+
+  /// Adds the value for `level` as the most recently used.
+  ///
+  /// Returns the values removed from the cache: the previous value for `level` and the least recently
+  /// used level if the cache is full.
+  pub fn insert(&mut self, level: LodLevel, value: M) -> Vec<M> {
+    let mut removed: Vec<M> = Vec::new();
+    if let Some(index) = self.levels.iter().position(|(cached, _)| *cached == level) {
+      removed.push(self.levels.remove(index).1);
+    }
+    self.levels.insert(0, (level, value));
+    while self.levels.len() > MAX_CACHED_LEVELS {
+      removed.push(self.levels.pop().unwrap().1);
+    }
+    removed
+  }
+
+  /// Removes the cached value for `level`.
+  pub fn remove(&mut self, level: LodLevel) -> Option<M> {
+    let index = self.levels.iter().position(|(cached, _)| *cached == level)?;
+    Some(self.levels.remove(index).1)
+  }
+
+  /// Removes and drops all the cached levels.
+  pub fn clear(&mut self) -> () {
+    self.levels.clear();
+  }
+
+  /// Removes all the cached levels, returning their values.
+  pub fn drain(&mut self) -> impl Iterator<Item = M> + '_ {
+    self.levels.drain(..).map(|(_, value)| value)
+  }
+
+  pub fn levels(&self) -> impl Iterator<Item = LodLevel> + '_ {
+    self.levels.iter().map(|(level, _)| *level)
+  }
+
+  /// Returns the cached values, without changing their order of use.
+  pub fn values_mut(&mut self) -> impl Iterator<Item = &mut M> + '_ {
+    self.levels.iter_mut().map(|(_, value)| value)
+  }
+}
+
+impl<M> ::std::default::Default for LodCache<M> {
+  fn default() -> Self {
+    Self::new()
+  }
+}
@@ -0,0 +1,127 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use gfx_hal::adapter::AdapterInfo;
+use gfx_hal::device::Device;
+use gfx_hal::Backend;
+use log::warn;
+
+use crate::error::RendererError;
+use crate::shaders::ShaderVariant;
+
+/// Version of the cache file names, to increment when the key changes.
+const CACHE_VERSION: u32 = 1;
+
+/// Returns the path of the pipeline cache file for an adapter, inside `directory`.
+///
+/// The file name depends on the adapter and on the compiled shader variants: caches created by other GPUs or
+/// for other shaders are not reused.
+pub fn cache_path(directory: &Path, adapter: &AdapterInfo) -> PathBuf {
+  let mut adapter_hash = Fnv1a::new();
+  adapter_hash.write(adapter.name.as_bytes());
+  adapter_hash.write(&(adapter.vendor as u64).to_le_bytes());
+  adapter_hash.write(&(adapter.device as u64).to_le_bytes());
+  adapter_hash.write(format!("{:?}", adapter.device_type).as_bytes());
+
+  let mut shader_hash = Fnv1a::new();
+  for variant in ShaderVariant::all().iter() {
+    let spirv = variant.spirv();
+    let glsl_es = variant.glsl_es();
+    shader_hash.write(variant.name().as_bytes());
+    shader_hash.write(spirv.vertex);
+    shader_hash.write(spirv.fragment);
+    shader_hash.write(glsl_es.vertex.as_bytes());
+    shader_hash.write(glsl_es.fragment.as_bytes());
+  }
+
+  directory.join(format!(
+    "pipeline-cache-v{}-{:016x}-{:016x}.bin",
+    CACHE_VERSION,
+    adapter_hash.finish(),
+    shader_hash.finish()
+  ))
+}
+
+/// Reads a cache file, returns `None` if it is missing or unreadable.
+pub fn load(path: &Path) -> Option<Vec<u8>> {
+  match fs::read(path) {
+    Ok(data) => Some(data),
+    Err(ref e) if e.kind() == io::ErrorKind::NotFound => None,
+    Err(e) => {
+      warn!("Failed to read pipeline cache {}: {}", path.display(), e);
+      None
+    }
+  }
+}
+
+/// Writes a cache file.
+///
+/// The file is replaced atomically, so concurrent processes never read a partial cache.
+pub fn save(path: &Path, data: &[u8]) -> () {
+  let temporary_path = path.with_extension(format!("{}.tmp", ::std::process::id()));
+  let result = match path.parent() {
+    Some(parent) => fs::create_dir_all(parent),
+    None => Ok(()),
+  }
+  .and_then(|_| fs::write(&temporary_path, data))
+  .and_then(|_| fs::rename(&temporary_path, path));
+  if let Err(e) = result {
+    warn!("Failed to write pipeline cache {}: {}", path.display(), e);
+    let _ = fs::remove_file(&temporary_path);
+  }
+}
+
+/// Creates a pipeline cache, initialized with the content of the file at `path` if it exists.
+pub(crate) unsafe fn create_pipeline_cache<B: Backend>(
+  device: &B::Device,
+  path: Option<&Path>,
+) -> Result<B::PipelineCache, RendererError> {
+  let data = path.and_then(load);
+  match device.create_pipeline_cache(data.as_ref().map(|data| &data[..])) {
+    Ok(cache) => Ok(cache),
+    Err(e) => match data {
+      // The driver may reject the data, start with an empty cache
+      Some(_) => {
+        warn!("Failed to load pipeline cache: {:?}", e);
+        Ok(device.create_pipeline_cache(None)?)
+      }
+      None => Err(e.into()),
+    },
+  }
+}
+
+/// Destroys a pipeline cache, after saving its content to `path` if provided.
+pub(crate) unsafe fn destroy_pipeline_cache<B: Backend>(
+  device: &B::Device,
+  cache: B::PipelineCache,
+  path: Option<&Path>,
+) -> () {
+  if let Some(path) = path {
+    match device.get_pipeline_cache_data(&cache) {
+      Ok(data) => save(path, &data),
+      Err(e) => warn!("Failed to retrieve pipeline cache data: {:?}", e),
+    }
+  }
+  device.destroy_pipeline_cache(cache);
+}
+
+/// 64-bit FNV-1a hash: unlike `DefaultHasher`, the result is stable across Rust versions.
+struct Fnv1a(u64);
+
+impl Fnv1a {
+  fn new() -> Self {
+    Fnv1a(0xcbf2_9ce4_8422_2325)
+  }
+
+  fn write(&mut self, bytes: &[u8]) -> () {
+    for byte in bytes.iter() {
+      self.0 ^= u64::from(*byte);
+      self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+    }
+  }
+
+  fn finish(&self) -> u64 {
+    self.0
+  }
+}
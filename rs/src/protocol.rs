@@ -0,0 +1,483 @@
+//! Command protocol between a render client and a render server.
+//!
+//! The client implements `ClientAssetStore` and `SwfRenderer` by sending commands through a `Transport`, so
+//! the player logic can run in one thread or process and the rendering in another. The client allocates the
+//! asset ids itself: only `Render` and `Resize` wait for a response from the server.
+
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::mpsc;
+
+use log::warn;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use swf_tree::tags::{DefineMorphShape, DefineShape};
+
+use crate::asset::{AssetIds, ClientAssetStore, MorphShapeId, MovieId, ServerAssetStore, ShapeId};
+use crate::error::RendererError;
+use crate::stage::{
+  ClipLayer, DisplayButton, DisplayContainer, DisplayPrimitive, Stage, StoredMorphShape, StoredShape,
+};
+use crate::swf_renderer::SwfRenderer;
+use crate::viewport::Viewport;
+
+/// Command sent by the client to the server.
+///
+/// The ids are allocated by the client, the server maps them to the ids of its renderer.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Command {
+  CreateMovie(MovieId),
+  ReleaseMovie(MovieId),
+  RegisterShape(MovieId, ShapeId, DefineShape),
+  RegisterMorphShape(MovieId, MorphShapeId, DefineMorphShape),
+  RedefineShape(ShapeId, DefineShape),
+  RedefineMorphShape(MorphShapeId, DefineMorphShape),
+  ReleaseShape(ShapeId),
+  ReleaseMorphShape(MorphShapeId),
+  /// Renders a stage, the server responds once the frame is submitted
+  Render(Stage),
+  /// Resizes the output, the server responds once the renderer is resized
+  Resize(u32, u32),
+  /// Sets how the stage is mapped to the output, without response
+  SetViewport(Viewport),
+}
+
+/// Response of the server to the `Render` and `Resize` commands.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Response {
+  /// Message of the error returned by the renderer, if any
+  pub error: Option<String>,
+}
+
+/// Error of the communication between the client and the server.
+#[derive(Debug)]
+pub enum ProtocolError {
+  /// The other side closed the connection
+  Disconnected,
+  /// Failed to encode or decode a message
+  Encoding(serde_cbor::Error),
+  /// Failed to transfer a message
+  Io(io::Error),
+}
+
+impl fmt::Display for ProtocolError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ProtocolError::Disconnected => write!(f, "Disconnected"),
+      ProtocolError::Encoding(e) => write!(f, "Failed to encode or decode message: {}", e),
+      ProtocolError::Io(e) => write!(f, "Failed to transfer message: {}", e),
+    }
+  }
+}
+
+impl ::std::error::Error for ProtocolError {}
+
+impl From<serde_cbor::Error> for ProtocolError {
+  fn from(e: serde_cbor::Error) -> Self {
+    ProtocolError::Encoding(e)
+  }
+}
+
+/// Bidirectional message channel, sending messages of type `S` and receiving messages of type `R`.
+pub trait Transport<S, R> {
+  fn send(&mut self, message: S) -> Result<(), ProtocolError>;
+
+  /// Waits for the next message, returns `None` once the other side is disconnected.
+  fn receive(&mut self) -> Result<Option<R>, ProtocolError>;
+}
+
+/// In-process transport, for a client and a server running in different threads.
+pub struct ChannelTransport<S, R> {
+  sender: mpsc::Sender<S>,
+  receiver: mpsc::Receiver<R>,
+}
+
+/// Creates the transports of a client and a server connected to each other.
+pub fn channel() -> (ChannelTransport<Command, Response>, ChannelTransport<Response, Command>) {
+  let (command_sender, command_receiver) = mpsc::channel();
+  let (response_sender, response_receiver) = mpsc::channel();
+  (
+    ChannelTransport {
+      sender: command_sender,
+      receiver: response_receiver,
+    },
+    ChannelTransport {
+      sender: response_sender,
+      receiver: command_receiver,
+    },
+  )
+}
+
+impl<S, R> Transport<S, R> for ChannelTransport<S, R> {
+  fn send(&mut self, message: S) -> Result<(), ProtocolError> {
+    self.sender.send(message).map_err(|_| ProtocolError::Disconnected)
+  }
+
+  fn receive(&mut self) -> Result<Option<R>, ProtocolError> {
+    Ok(self.receiver.recv().ok())
+  }
+}
+
+/// Transport over a pair of byte streams (e.g. the pipes of a child process), with the messages encoded
+/// in CBOR.
+///
+/// CBOR is self-describing, as required by the internally tagged enums of `swf_tree`.
+pub struct StreamTransport<I: Read, O: Write> {
+  input: I,
+  output: O,
+}
+
+impl<I: Read, O: Write> StreamTransport<I, O> {
+  pub fn new(input: I, output: O) -> Self {
+    Self { input, output }
+  }
+
+  pub fn into_inner(self) -> (I, O) {
+    (self.input, self.output)
+  }
+}
+
+impl<S: Serialize, R: DeserializeOwned, I: Read, O: Write> Transport<S, R> for StreamTransport<I, O> {
+  fn send(&mut self, message: S) -> Result<(), ProtocolError> {
+    write_message(&mut self.output, &message)
+  }
+
+  fn receive(&mut self) -> Result<Option<R>, ProtocolError> {
+    read_message(&mut self.input)
+  }
+}
+
+/// Writes a CBOR-encoded message to a stream and flushes it.
+pub fn write_message<T: Serialize, W: Write>(output: &mut W, message: &T) -> Result<(), ProtocolError> {
+  serde_cbor::to_writer(&mut *output, message)?;
+  output.flush().map_err(ProtocolError::Io)
+}
+
+/// Reads the next CBOR-encoded message of a stream, returns `None` at the end of the stream.
+pub fn read_message<T: DeserializeOwned, R: Read>(input: &mut R) -> Result<Option<T>, ProtocolError> {
+  let mut deserializer = serde_cbor::Deserializer::from_reader(input);
+  match T::deserialize(&mut deserializer) {
+    Ok(message) => Ok(Some(message)),
+    // The other side closed the stream
+    Err(ref e) if e.is_eof() => Ok(None),
+    Err(e) => Err(e.into()),
+  }
+}
+
+/// Client side of the protocol: forwards the asset registrations and the stages to a server.
+///
+/// The asset registrations do not wait for the server: they always succeed on the client, and their errors
+/// (failing to send them or to register them in the renderer) are returned by the next call to `render` or
+/// `resize`.
+pub struct RenderClient<T: Transport<Command, Response>> {
+  transport: T,
+  asset_ids: AssetIds,
+  /// First error while sending a command without response
+  error: Option<ProtocolError>,
+}
+
+impl<T: Transport<Command, Response>> RenderClient<T> {
+  pub fn new(transport: T) -> Self {
+    Self {
+      transport,
+      asset_ids: AssetIds::new(),
+      error: None,
+    }
+  }
+
+  pub fn into_transport(self) -> T {
+    self.transport
+  }
+
+  fn send(&mut self, command: Command) -> () {
+    if self.error.is_some() {
+      return;
+    }
+    if let Err(e) = self.transport.send(command) {
+      warn!("Failed to send command to the render server: {}", e);
+      self.error = Some(e);
+    }
+  }
+
+  /// Sends a command and waits for its response.
+  fn request(&mut self, command: Command) -> Result<(), RendererError> {
+    if let Some(e) = self.error.take() {
+      return Err(e.into());
+    }
+    self.transport.send(command)?;
+    match self.transport.receive()? {
+      Some(Response { error: None }) => Ok(()),
+      Some(Response { error: Some(message) }) => Err(RendererError::Remote(message)),
+      None => Err(ProtocolError::Disconnected.into()),
+    }
+  }
+}
+
+impl<T: Transport<Command, Response>> ClientAssetStore for RenderClient<T> {
+  fn create_movie(&mut self) -> MovieId {
+    let movie = self.asset_ids.create_movie();
+    self.send(Command::CreateMovie(movie));
+    movie
+  }
+
+  fn release_movie(&mut self, movie: MovieId) -> () {
+    self.asset_ids.release_movie(movie);
+    self.send(Command::ReleaseMovie(movie));
+  }
+
+  fn register_shape(&mut self, movie: MovieId, tag: &DefineShape) -> Result<ShapeId, RendererError> {
+    let id = ShapeId(self.asset_ids.asset_id(movie, tag.id));
+    self.send(Command::RegisterShape(movie, id, tag.clone()));
+    Ok(id)
+  }
+
+  fn register_morph_shape(&mut self, movie: MovieId, tag: &DefineMorphShape) -> Result<MorphShapeId, RendererError> {
+    let id = MorphShapeId(self.asset_ids.asset_id(movie, tag.id));
+    self.send(Command::RegisterMorphShape(movie, id, tag.clone()));
+    Ok(id)
+  }
+
+  fn redefine_shape(&mut self, id: ShapeId, tag: &DefineShape) -> Result<(), RendererError> {
+    self.send(Command::RedefineShape(id, tag.clone()));
+    Ok(())
+  }
+
+  fn redefine_morph_shape(&mut self, id: MorphShapeId, tag: &DefineMorphShape) -> Result<(), RendererError> {
+    self.send(Command::RedefineMorphShape(id, tag.clone()));
+    Ok(())
+  }
+
+  fn release_shape(&mut self, id: ShapeId) -> () {
+    self.send(Command::ReleaseShape(id));
+  }
+
+  fn release_morph_shape(&mut self, id: MorphShapeId) -> () {
+    self.send(Command::ReleaseMorphShape(id));
+  }
+}
+
+impl<T: Transport<Command, Response>> SwfRenderer for RenderClient<T> {
+  fn render(&mut self, stage: Stage) -> Result<(), RendererError> {
+    self.request(Command::Render(stage))
+  }
+
+  fn resize(&mut self, width: u32, height: u32) -> Result<(), RendererError> {
+    self.request(Command::Resize(width, height))
+  }
+
+  fn set_viewport(&mut self, viewport: Viewport) -> () {
+    self.send(Command::SetViewport(viewport));
+  }
+}
+
+/// Server side of the protocol: owns a renderer and executes the commands of a client.
+pub struct RenderServer<R: SwfRenderer + ClientAssetStore> {
+  renderer: R,
+  /// Renderer namespace of each client movie
+  movies: HashMap<MovieId, MovieId>,
+  /// Client movie and renderer id of each client asset id
+  assets: HashMap<usize, (MovieId, usize)>,
+  /// First asset registration error since the last response, sent with the next response
+  asset_error: Option<RendererError>,
+}
+
+impl<R: SwfRenderer + ClientAssetStore> RenderServer<R> {
+  pub fn new(renderer: R) -> Self {
+    Self {
+      renderer,
+      movies: HashMap::new(),
+      assets: HashMap::new(),
+      asset_error: None,
+    }
+  }
+
+  pub fn renderer(&mut self) -> &mut R {
+    &mut self.renderer
+  }
+
+  pub fn into_renderer(self) -> R {
+    self.renderer
+  }
+
+  /// Executes the commands received from `transport` until the client disconnects.
+  pub fn run<T: Transport<Response, Command>>(&mut self, transport: &mut T) -> Result<(), ProtocolError> {
+    while let Some(command) = transport.receive()? {
+      if let Some(response) = self.execute(command) {
+        transport.send(response)?;
+      }
+    }
+    Ok(())
+  }
+
+  /// Executes a command, returns the response to send to the client if the command expects one.
+  pub fn execute(&mut self, command: Command) -> Option<Response> {
+    match command {
+      Command::CreateMovie(movie) => {
+        let renderer_movie = self.renderer.create_movie();
+        self.movies.insert(movie, renderer_movie);
+      }
+      Command::ReleaseMovie(movie) => {
+        if let Some(renderer_movie) = self.movies.remove(&movie) {
+          self.renderer.release_movie(renderer_movie);
+          self.assets.retain(|_, (asset_movie, _)| *asset_movie != movie);
+        }
+      }
+      Command::RegisterShape(movie, id, tag) => {
+        if let Some(renderer_movie) = self.movies.get(&movie) {
+          match self.renderer.register_shape(*renderer_movie, &tag) {
+            Ok(renderer_id) => {
+              self.assets.insert(id.0, (movie, renderer_id.0));
+            }
+            Err(e) => self.set_asset_error(e),
+          }
+        } else {
+          warn!("Ignoring shape of unknown movie: {:?}", movie);
+        }
+      }
+      Command::RegisterMorphShape(movie, id, tag) => {
+        if let Some(renderer_movie) = self.movies.get(&movie) {
+          match self.renderer.register_morph_shape(*renderer_movie, &tag) {
+            Ok(renderer_id) => {
+              self.assets.insert(id.0, (movie, renderer_id.0));
+            }
+            Err(e) => self.set_asset_error(e),
+          }
+        } else {
+          warn!("Ignoring morph shape of unknown movie: {:?}", movie);
+        }
+      }
+      Command::RedefineShape(id, tag) => {
+        if let Some(renderer_id) = self.get_shape(id) {
+          if let Err(e) = self.renderer.redefine_shape(renderer_id, &tag) {
+            self.set_asset_error(e);
+          }
+        }
+      }
+      Command::RedefineMorphShape(id, tag) => {
+        if let Some(renderer_id) = self.get_morph_shape(id) {
+          if let Err(e) = self.renderer.redefine_morph_shape(renderer_id, &tag) {
+            self.set_asset_error(e);
+          }
+        }
+      }
+      Command::ReleaseShape(id) => {
+        if let Some(renderer_id) = self.get_shape(id) {
+          self.renderer.release_shape(renderer_id);
+          self.assets.remove(&id.0);
+        }
+      }
+      Command::ReleaseMorphShape(id) => {
+        if let Some(renderer_id) = self.get_morph_shape(id) {
+          self.renderer.release_morph_shape(renderer_id);
+          self.assets.remove(&id.0);
+        }
+      }
+      Command::Render(stage) => {
+        let stage = Stage {
+          background_color: stage.background_color,
+          display_root: self.resolve_primitives(stage.display_root),
+        };
+        let result = self.renderer.render(stage);
+        return Some(self.respond(result));
+      }
+      Command::Resize(width, height) => {
+        let result = self.renderer.resize(width, height);
+        return Some(self.respond(result));
+      }
+      Command::SetViewport(viewport) => self.renderer.set_viewport(viewport),
+    }
+    None
+  }
+
+  /// Keeps the first registration error, the next ones are only logged.
+  fn set_asset_error(&mut self, error: RendererError) -> () {
+    if self.asset_error.is_some() {
+      warn!("Failed to register asset: {}", error);
+    } else {
+      self.asset_error = Some(error);
+    }
+  }
+
+  /// Builds the response to a request, reporting the pending registration error before its own result.
+  fn respond(&mut self, result: Result<(), RendererError>) -> Response {
+    match self.asset_error.take() {
+      Some(e) => {
+        if let Err(request_error) = result {
+          warn!("Request failed after an asset registration error: {}", request_error);
+        }
+        response(Err(e))
+      }
+      None => response(result),
+    }
+  }
+
+  /// Replaces the client ids of the primitives by the ids of the renderer.
+  ///
+  /// The primitives using unknown ids are removed.
+  fn resolve_primitives(&mut self, primitives: Vec<DisplayPrimitive>) -> Vec<DisplayPrimitive> {
+    primitives
+      .into_iter()
+      .filter_map(|primitive| self.resolve_primitive(primitive))
+      .collect()
+  }
+
+  fn resolve_primitive(&mut self, primitive: DisplayPrimitive) -> Option<DisplayPrimitive> {
+    let resolved = match primitive {
+      DisplayPrimitive::Shape(shape) => {
+        let id = self.get_shape(shape.id)?;
+        DisplayPrimitive::Shape(StoredShape { id, ..shape })
+      }
+      DisplayPrimitive::MorphShape(shape) => {
+        let id = self.get_morph_shape(shape.id)?;
+        DisplayPrimitive::MorphShape(StoredMorphShape { id, ..shape })
+      }
+      DisplayPrimitive::Container(container) => DisplayPrimitive::Container(DisplayContainer {
+        children: self.resolve_primitives(container.children),
+        ..container
+      }),
+      DisplayPrimitive::Button(button) => DisplayPrimitive::Button(DisplayButton {
+        children: self.resolve_primitives(button.children),
+        hit_area: self.resolve_primitives(button.hit_area),
+        ..button
+      }),
+      DisplayPrimitive::Clip(clip) => DisplayPrimitive::Clip(ClipLayer {
+        mask: Box::new(self.resolve_primitive(*clip.mask)?),
+        children: self.resolve_primitives(clip.children),
+        ..clip
+      }),
+    };
+    Some(resolved)
+  }
+}
+
+/// Maps the client ids to the ids of the renderer.
+impl<R: SwfRenderer + ClientAssetStore> ServerAssetStore for RenderServer<R> {
+  type Shape = ShapeId;
+  type MorphShape = MorphShapeId;
+
+  fn get_shape(&mut self, id: ShapeId) -> Option<ShapeId> {
+    let resolved = self.assets.get(&id.0).map(|(_, renderer_id)| ShapeId(*renderer_id));
+    if resolved.is_none() {
+      warn!("Unknown shape: {:?}", id);
+    }
+    resolved
+  }
+
+  fn get_morph_shape(&mut self, id: MorphShapeId) -> Option<MorphShapeId> {
+    let resolved = self
+      .assets
+      .get(&id.0)
+      .map(|(_, renderer_id)| MorphShapeId(*renderer_id));
+    if resolved.is_none() {
+      warn!("Unknown morph shape: {:?}", id);
+    }
+    resolved
+  }
+}
+
+fn response(result: Result<(), RendererError>) -> Response {
+  Response {
+    error: result.err().map(|e| e.to_string()),
+  }
+}
@@ -0,0 +1,162 @@
+//! Recording and replay of render sessions.
+//!
+//! A recording is the sequence of protocol commands received by a renderer: the output size and viewport, the
+//! asset registrations and the rendered stages, encoded like the messages of `StreamTransport`. Replaying it into
+//! another renderer reproduces the session frame by frame, e.g. to investigate a rendering issue reported by a
+//! user.
+
+use std::io::{Read, Write};
+
+use log::warn;
+use swf_tree::tags::{DefineMorphShape, DefineShape};
+
+use crate::asset::{ClientAssetStore, MorphShapeId, MovieId, ShapeId};
+use crate::error::RendererError;
+use crate::protocol::{read_message, write_message, Command, ProtocolError, RenderServer};
+use crate::stage::Stage;
+use crate::swf_renderer::SwfRenderer;
+use crate::viewport::Viewport;
+
+/// Wraps a renderer and records everything it receives to `output`.
+///
+/// The commands use the ids returned by the wrapped renderer: failed registrations have no id and are not
+/// recorded. A recording failure does not interrupt the rendering: the recording stops and the error is
+/// returned by `finish`.
+pub struct Recorder<R: SwfRenderer + ClientAssetStore, W: Write> {
+  renderer: R,
+  output: W,
+  /// First recording error, the recording stops after it
+  error: Option<ProtocolError>,
+}
+
+impl<R: SwfRenderer + ClientAssetStore, W: Write> Recorder<R, W> {
+  /// Starts a recording, `width`, `height` and `viewport` are the current output size and viewport of
+  /// `renderer`.
+  ///
+  /// They are recorded first, so the replay renders to the same output as the recorded session.
+  pub fn new(renderer: R, output: W, width: u32, height: u32, viewport: Viewport) -> Self {
+    let mut recorder = Self {
+      renderer,
+      output,
+      error: None,
+    };
+    recorder.record(&Command::Resize(width, height));
+    recorder.record(&Command::SetViewport(viewport));
+    recorder
+  }
+
+  pub fn renderer(&mut self) -> &mut R {
+    &mut self.renderer
+  }
+
+  /// Stops the recording, returns the renderer and the output or the first recording error.
+  pub fn finish(self) -> Result<(R, W), ProtocolError> {
+    match self.error {
+      Some(e) => Err(e),
+      None => Ok((self.renderer, self.output)),
+    }
+  }
+
+  fn record(&mut self, command: &Command) -> () {
+    if self.error.is_some() {
+      return;
+    }
+    if let Err(e) = write_message(&mut self.output, command) {
+      warn!("Failed to record render command, stopping the recording: {}", e);
+      self.error = Some(e);
+    }
+  }
+}
+
+impl<R: SwfRenderer + ClientAssetStore, W: Write> ClientAssetStore for Recorder<R, W> {
+  fn create_movie(&mut self) -> MovieId {
+    let movie = self.renderer.create_movie();
+    self.record(&Command::CreateMovie(movie));
+    movie
+  }
+
+  fn release_movie(&mut self, movie: MovieId) -> () {
+    self.renderer.release_movie(movie);
+    self.record(&Command::ReleaseMovie(movie));
+  }
+
+  fn register_shape(&mut self, movie: MovieId, tag: &DefineShape) -> Result<ShapeId, RendererError> {
+    let id = self.renderer.register_shape(movie, tag)?;
+    self.record(&Command::RegisterShape(movie, id, tag.clone()));
+    Ok(id)
+  }
+
+  fn register_morph_shape(&mut self, movie: MovieId, tag: &DefineMorphShape) -> Result<MorphShapeId, RendererError> {
+    let id = self.renderer.register_morph_shape(movie, tag)?;
+    self.record(&Command::RegisterMorphShape(movie, id, tag.clone()));
+    Ok(id)
+  }
+
+  fn redefine_shape(&mut self, id: ShapeId, tag: &DefineShape) -> Result<(), RendererError> {
+    self.record(&Command::RedefineShape(id, tag.clone()));
+    self.renderer.redefine_shape(id, tag)
+  }
+
+  fn redefine_morph_shape(&mut self, id: MorphShapeId, tag: &DefineMorphShape) -> Result<(), RendererError> {
+    self.record(&Command::RedefineMorphShape(id, tag.clone()));
+    self.renderer.redefine_morph_shape(id, tag)
+  }
+
+  fn release_shape(&mut self, id: ShapeId) -> () {
+    self.renderer.release_shape(id);
+    self.record(&Command::ReleaseShape(id));
+  }
+
+  fn release_morph_shape(&mut self, id: MorphShapeId) -> () {
+    self.renderer.release_morph_shape(id);
+    self.record(&Command::ReleaseMorphShape(id));
+  }
+}
+
+impl<R: SwfRenderer + ClientAssetStore, W: Write> SwfRenderer for Recorder<R, W> {
+  fn render(&mut self, stage: Stage) -> Result<(), RendererError> {
+    // Record the stage before rendering, so a frame crashing the renderer is part of the recording
+    self.record(&Command::Render(stage.clone()));
+    self.renderer.render(stage)
+  }
+
+  fn resize(&mut self, width: u32, height: u32) -> Result<(), RendererError> {
+    self.record(&Command::Resize(width, height));
+    self.renderer.resize(width, height)
+  }
+  fn set_viewport(&mut self, viewport: Viewport) -> () {
+    self.record(&Command::SetViewport(viewport.clone()));
+    self.renderer.set_viewport(viewport)
+  }
+}
+
+/// Replays a recording into `renderer`, calling `on_frame` with the index of each rendered frame.
+///
+/// The frames are counted from `0`, including the ones failing to render: their errors are logged and
+/// `on_frame` is not called for them. Returns the renderer once the whole recording is replayed.
+pub fn replay<R, I, F>(input: &mut I, renderer: R, mut on_frame: F) -> Result<R, RendererError>
+where
+  R: SwfRenderer + ClientAssetStore,
+  I: Read,
+  F: FnMut(&mut R, usize) -> Result<(), RendererError>,
+{
+  let mut server = RenderServer::new(renderer);
+  let mut frame: usize = 0;
+  while let Some(command) = read_message::<Command, I>(input)? {
+    let is_frame = match command {
+      Command::Render(_) => true,
+      _ => false,
+    };
+    let error = server.execute(command).and_then(|response| response.error);
+    match (error, is_frame) {
+      (Some(e), true) => warn!("Failed to render frame {}: {}", frame, e),
+      (Some(e), false) => warn!("Failed to replay command: {}", e),
+      (None, true) => on_frame(server.renderer(), frame)?,
+      (None, false) => {}
+    }
+    if is_frame {
+      frame += 1;
+    }
+  }
+  Ok(server.into_renderer())
+}
@@ -1,66 +1,160 @@
 use std::collections::HashMap;
 
-use lyon::tessellation::{BuffersBuilder, FillOptions, FillTessellator, FillVertex, VertexBuffers};
+use lyon::tessellation::{BuffersBuilder, FillOptions, FillTessellator, FillVertex, TessellationError, VertexBuffers};
 use swf_tree::FillStyle;
 
-use crate::decode_shape;
+use crate::bounds::{shape_bounds, Bounds};
+use crate::curve::{build_curve_mesh, CurveMode, INTERIOR_CURVE};
+use crate::error::RendererError;
+use crate::lod::{LodCache, LodLevel};
+use crate::stage::{ColorTransform, DisplayPrimitive, Matrix2D, Stage};
 use crate::swf_renderer::Vertex;
+use crate::{decode_define_shape, DecodeMode, Shape, StyledPath};
 
 /// Structure holding all the shape and morph-shape definitions in a
 /// format optimized for the renderer.
 pub struct ShapeStore {
   shapes: HashMap<usize, GfxSymbol>,
+  decode_mode: DecodeMode,
+  curve_mode: CurveMode,
 }
 
 impl ShapeStore {
+  /// Creates a store decoding the shapes leniently, like the Flash Player.
   pub fn new() -> Self {
-    Self { shapes: HashMap::new() }
+    Self::with_decode_mode(DecodeMode::Lenient)
+  }
+
+  pub fn with_decode_mode(decode_mode: DecodeMode) -> Self {
+    Self {
+      shapes: HashMap::new(),
+      decode_mode,
+      curve_mode: CurveMode::Tessellated,
+    }
+  }
+
+  pub fn curve_mode(&self) -> CurveMode {
+    self.curve_mode
+  }
+
+  /// Changes how the curves are rendered, discarding the meshes built for the previous mode.
+  pub fn set_curve_mode(&mut self, curve_mode: CurveMode) -> () {
+    if curve_mode == self.curve_mode {
+      return;
+    }
+    self.curve_mode = curve_mode;
+    for symbol in self.shapes.values_mut() {
+      if let GfxSymbol::Shape(symbol) = symbol {
+        symbol.meshes.clear();
+      }
+    }
+  }
+
+  /// Returns the level of detail of the mesh for an instance displayed with the provided scale
+  /// (in pixels per twip).
+  ///
+  /// Analytic curves do not depend on the scale: they always use a single level.
+  pub fn lod_level(&self, scale: f32) -> LodLevel {
+    match self.curve_mode {
+      CurveMode::Tessellated => LodLevel::from_scale(scale),
+      CurveMode::Analytic => LodLevel(0),
+    }
   }
 
   pub fn get(&self, id: usize) -> Option<&GfxSymbol> {
     self.shapes.get(&id)
   }
 
-  pub fn define_shape(&mut self, tag: &swf_tree::tags::DefineShape) -> usize {
-    let id: usize = tag.id.into();
-    let shape = decode_shape(&tag.shape);
-    let mut mesh: VertexBuffers<Vertex, u32> = VertexBuffers::new();
-    let mut tessellator = FillTessellator::new();
-
-    for path in shape.paths.iter() {
-      let color: [f32; 3] = if let Some(ref fill) = &path.fill {
-        match fill {
-          FillStyle::Solid(ref style) => [
-            (style.color.r as f32) / 255f32,
-            (style.color.g as f32) / 255f32,
-            (style.color.b as f32) / 255f32,
-          ],
-          _ => [0.0, 1.0, 0.0],
-        }
-      } else {
-        [1.0, 0.0, 0.0]
-      };
-
-      // Compute the tessellation.
-      tessellator
-        .tessellate_path(
-          &path.path,
-          &FillOptions::default(),
-          &mut BuffersBuilder::new(&mut mesh, |vertex: FillVertex| Vertex {
-            position: [vertex.position.x, vertex.position.y, 0.0],
-            color,
-          }),
-        )
-        .unwrap();
-    }
+  /// Decodes and stores a shape with the provided global id, replacing any previous definition with the same id.
+  ///
+  /// The character id of the tag is ignored: `id` must come from the asset ids of its movie.
+  /// On failure, the previous definition is kept.
+  pub fn define_shape(&mut self, id: usize, tag: &swf_tree::tags::DefineShape) -> Result<(), RendererError> {
+    let shape = decode_define_shape(tag, self.decode_mode)?;
+    // Tessellate the shape at its original size so tessellation errors are reported immediately
+    let default_level = self.lod_level(1.0 / 20.0);
+    let mut meshes = LodCache::new();
+    meshes.insert(default_level, build_mesh(&shape, self.curve_mode, default_level)?);
 
     let shape_symbol = GfxShapeSymbol {
       bounds: tag.bounds,
-      mesh,
+      tight_bounds: shape_bounds(&shape),
+      shape,
+      meshes,
+    };
+    self.shapes.insert(id, GfxSymbol::Shape(shape_symbol));
+    Ok(())
+  }
+
+  /// Removes a definition, returns `false` if `id` is not defined.
+  pub fn remove(&mut self, id: usize) -> bool {
+    self.shapes.remove(&id).is_some()
+  }
+
+  /// Returns the mesh of a shape for the provided level of detail, tessellating it if needed.
+  pub fn get_mesh(&mut self, id: usize, level: LodLevel) -> Result<&VertexBuffers<Vertex, u32>, RendererError> {
+    let symbol = match self.shapes.get_mut(&id) {
+      Some(GfxSymbol::Shape(symbol)) => symbol,
+      _ => return Err(RendererError::UnknownAsset(id)),
     };
-    let old = self.shapes.insert(id, GfxSymbol::Shape(shape_symbol));
-    debug_assert!(old.is_none());
-    id
+    if symbol.meshes.get(level).is_none() {
+      let mesh = build_mesh(&symbol.shape, self.curve_mode, level)?;
+      symbol.meshes.insert(level, mesh);
+    }
+    Ok(symbol.meshes.get(level).unwrap())
+  }
+}
+
+fn build_mesh(
+  shape: &Shape,
+  curve_mode: CurveMode,
+  level: LodLevel,
+) -> Result<VertexBuffers<Vertex, u32>, TessellationError> {
+  match curve_mode {
+    CurveMode::Tessellated => tessellate_shape(shape, level.tolerance()),
+    CurveMode::Analytic => build_curve_mesh(shape),
+  }
+}
+
+/// Tessellates the fills of a shape, using its fill rule.
+///
+/// `tolerance` is the maximum distance between the curves and the mesh, in twips.
+pub fn tessellate_shape(shape: &Shape, tolerance: f32) -> Result<VertexBuffers<Vertex, u32>, TessellationError> {
+  let mut mesh: VertexBuffers<Vertex, u32> = VertexBuffers::new();
+  let mut tessellator = FillTessellator::new();
+  let options = FillOptions::tolerance(tolerance).with_fill_rule(shape.fill_rule);
+
+  for path in shape.paths.iter() {
+    let color = fill_color(path);
+
+    // Compute the tessellation.
+    tessellator.tessellate_path(
+      &path.path,
+      &options,
+      &mut BuffersBuilder::new(&mut mesh, |vertex: FillVertex| Vertex {
+        position: [vertex.position.x, vertex.position.y, 0.0],
+        color,
+        curve: INTERIOR_CURVE,
+      }),
+    )?;
+  }
+
+  Ok(mesh)
+}
+
+/// Returns the color of the mesh of a path: only solid fills are supported yet.
+pub(crate) fn fill_color(path: &StyledPath) -> [f32; 3] {
+  if let Some(ref fill) = &path.fill {
+    match fill {
+      FillStyle::Solid(ref style) => [
+        (style.color.r as f32) / 255f32,
+        (style.color.g as f32) / 255f32,
+        (style.color.b as f32) / 255f32,
+      ],
+      _ => [0.0, 1.0, 0.0],
+    }
+  } else {
+    [1.0, 0.0, 0.0]
   }
 }
 
@@ -71,7 +165,12 @@ pub enum GfxSymbol {
 
 pub struct GfxShapeSymbol {
   pub bounds: swf_tree::Rect,
-  pub mesh: VertexBuffers<Vertex, u32>,
+  /// Exact bounds of `shape` (`None` if it is empty)
+  pub tight_bounds: Option<Bounds>,
+  /// Decoded shape, kept for geometry queries such as hit-testing
+  pub shape: Shape,
+  /// Tessellations of `shape`, by level of detail
+  pub meshes: LodCache<VertexBuffers<Vertex, u32>>,
 }
 
 pub struct GfxMorphShapeSymbol {
@@ -79,11 +178,58 @@ pub struct GfxMorphShapeSymbol {
 }
 
 pub enum DisplayItem {
-  Shape(usize, swf_tree::Matrix),
+  Shape(usize, Matrix2D, ColorTransform),
 }
 
 pub trait Renderer {
-  fn set_stage(&mut self, shape: DisplayItem) -> ();
+  /// Sets the items to render, in painter's order (back to front).
+  fn set_stage(&mut self, display_list: Vec<DisplayItem>) -> ();
+}
+
+/// Flattens the display tree of a stage into items in painter's order, with their world transforms.
+///
+/// Only the static shapes are drawn: morph shapes, blend modes, filters and clipping are not supported yet,
+/// the children of clip layers are drawn unclipped.
+pub fn flatten_stage(stage: &Stage) -> Vec<DisplayItem> {
+  let mut items: Vec<DisplayItem> = Vec::new();
+  flatten_primitives(
+    &stage.display_root,
+    &Matrix2D::default(),
+    &ColorTransform::default(),
+    &mut items,
+  );
+  items
+}
+
+fn flatten_primitives(
+  primitives: &[DisplayPrimitive],
+  matrix: &Matrix2D,
+  color_transform: &ColorTransform,
+  items: &mut Vec<DisplayItem>,
+) -> () {
+  for primitive in primitives.iter() {
+    match primitive {
+      DisplayPrimitive::Shape(ref shape) => items.push(DisplayItem::Shape(
+        shape.id.0,
+        matrix * &shape.matrix,
+        color_transform.multiply(&shape.color_transform),
+      )),
+      DisplayPrimitive::MorphShape(_) => {}
+      DisplayPrimitive::Container(ref container) => flatten_primitives(
+        &container.children,
+        &(matrix * &container.matrix),
+        &color_transform.multiply(&container.color_transform),
+        items,
+      ),
+      DisplayPrimitive::Button(ref button) => flatten_primitives(
+        &button.children,
+        &(matrix * &button.matrix),
+        &color_transform.multiply(&button.color_transform),
+        items,
+      ),
+      DisplayPrimitive::Clip(ref clip) => flatten_primitives(&clip.children, matrix, color_transform, items),
+    }
+  }
 }
 
 /// Image metadata
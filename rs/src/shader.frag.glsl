@@ -1,9 +1,30 @@
 #version 450
 
-layout (location = 0) in vec3 inColor;
+// Variants (see `build.rs`):
+// - `FILL_SOLID`: color interpolated from the vertices
+// - `ANALYTIC_CURVES`: keep the covered side of the curves, with anti-aliasing
+
+layout (location = 0) in vec4 inColor;
+// Loop-Blinn curve coordinates `(u, v, sign)`: the fragment is covered where `sign * (u^2 - v) <= 0`
+layout (location = 1) in vec3 inCurve;
 
 layout (location = 0) out vec4 outFragColor;
 
 void main() {
-    outFragColor = vec4(inColor, 1.0);
+    float coverage = 1.0;
+#ifdef ANALYTIC_CURVES
+    float f = inCurve.z * (inCurve.x * inCurve.x - inCurve.y);
+    // Approximate signed distance to the curve, in pixels
+    vec2 du = vec2(dFdx(inCurve.x), dFdy(inCurve.x));
+    vec2 dv = vec2(dFdx(inCurve.y), dFdy(inCurve.y));
+    vec2 gradient = inCurve.z * (2.0 * inCurve.x * du - dv);
+    float distance = f / max(length(gradient), 1e-6);
+    coverage = clamp(0.5 - distance, 0.0, 1.0);
+    if (coverage <= 0.0) {
+        discard;
+    }
+#endif
+#ifdef FILL_SOLID
+    outFragColor = vec4(inColor.rgb, inColor.a * coverage);
+#endif
 }
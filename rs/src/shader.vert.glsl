@@ -2,18 +2,29 @@
 
 layout (location = 0) in vec3 inPos;
 layout (location = 1) in vec3 inColor;
+layout (location = 2) in vec3 inCurve;
+// Per-instance world matrix, as its two first rows
+layout (location = 3) in vec3 inMatrixX;
+layout (location = 4) in vec3 inMatrixY;
+// Per-instance color transform: `color * mult + add`
+layout (location = 5) in vec4 inColorMult;
+layout (location = 6) in vec4 inColorAdd;
 
-layout (location = 0) out vec3 outColor;
+layout (location = 0) out vec4 outColor;
+layout (location = 1) out vec3 outCurve;
 
 out gl_PerVertex {
     vec4 gl_Position;
 };
 
 layout(push_constant) uniform PushConsts {
-    mat4 mvp;
+    mat4 viewProjection;
 } pushConsts;
 
 void main() {
-    outColor = inColor;
-    gl_Position = pushConsts.mvp * vec4(inPos.xyz, 1.0);
+    outColor = clamp(vec4(inColor, 1.0) * inColorMult + inColorAdd, 0.0, 1.0);
+    outCurve = inCurve;
+    vec3 local = vec3(inPos.xy, 1.0);
+    vec2 world = vec2(dot(inMatrixX, local), dot(inMatrixY, local));
+    gl_Position = pushConsts.viewProjection * vec4(world, inPos.z, 1.0);
 }
@@ -0,0 +1,150 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::Cursor;
+
+use gfx_hal::pso::Stage;
+
+use crate::curve::CurveMode;
+use crate::error::RendererError;
+
+/// Kind of fill supported by a shader variant.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FillKind {
+  /// Color interpolated from the vertices
+  Solid,
+}
+
+/// Identifies a precompiled shader variant, see `build.rs`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ShaderVariant {
+  pub fill: FillKind,
+  /// Evaluate the curve coordinates of the vertices in the fragment shader
+  pub analytic_curves: bool,
+}
+
+/// Sources of the vertex and fragment stages of a shader variant.
+pub struct ShaderSources<T: 'static + ?Sized> {
+  pub vertex: &'static T,
+  pub fragment: &'static T,
+}
+
+/// Embeds the compiled stages of a variant, checked in `src/shaders/`.
+macro_rules! shader_variant {
+  ($name:expr) => {
+    (
+      ShaderSources {
+        vertex: &include_bytes!(concat!("shaders/", $name, ".vert.spv"))[..],
+        fragment: &include_bytes!(concat!("shaders/", $name, ".frag.spv"))[..],
+      },
+      ShaderSources {
+        vertex: include_str!(concat!("shaders/", $name, ".vert.es.glsl")),
+        fragment: include_str!(concat!("shaders/", $name, ".frag.es.glsl")),
+      },
+    )
+  };
+}
+
+impl ShaderVariant {
+  /// Returns the variant drawing solid fills with the provided curve mode.
+  pub fn for_curve_mode(curve_mode: CurveMode) -> Self {
+    Self {
+      fill: FillKind::Solid,
+      analytic_curves: curve_mode == CurveMode::Analytic,
+    }
+  }
+
+  /// Returns every precompiled variant.
+  pub fn all() -> Vec<Self> {
+    vec![
+      Self::for_curve_mode(CurveMode::Tessellated),
+      Self::for_curve_mode(CurveMode::Analytic),
+    ]
+  }
+
+  /// Name of the variant, used for the compiled files.
+  pub fn name(&self) -> &'static str {
+    match (self.fill, self.analytic_curves) {
+      (FillKind::Solid, false) => "solid",
+      (FillKind::Solid, true) => "solid-curves",
+    }
+  }
+
+  /// Returns the SPIR-V binaries of the variant.
+  ///
+  /// Use `gfx_hal::pso::read_spirv` to get properly aligned words.
+  pub fn spirv(&self) -> ShaderSources<[u8]> {
+    self.sources().0
+  }
+
+  /// Returns the GLSL ES 3.0 sources of the variant, for the GL backend.
+  pub fn glsl_es(&self) -> ShaderSources<str> {
+    self.sources().1
+  }
+
+  fn sources(&self) -> (ShaderSources<[u8]>, ShaderSources<str>) {
+    match (self.fill, self.analytic_curves) {
+      (FillKind::Solid, false) => shader_variant!("solid"),
+      (FillKind::Solid, true) => shader_variant!("solid-curves"),
+    }
+  }
+}
+
+/// Creates the shader modules of the variants from the sources matching the backend.
+pub trait ShaderBackend: gfx_hal::Backend {
+  /// Creates the module of the `Vertex` or `Fragment` stage of a variant.
+  unsafe fn create_shader_module(
+    device: &Self::Device,
+    variant: ShaderVariant,
+    stage: Stage,
+  ) -> Result<Self::ShaderModule, RendererError>;
+}
+
+/// The native backends load the SPIR-V binaries.
+#[cfg(not(target_arch = "wasm32"))]
+impl<B: gfx_hal::Backend> ShaderBackend for B {
+  unsafe fn create_shader_module(
+    device: &B::Device,
+    variant: ShaderVariant,
+    stage: Stage,
+  ) -> Result<B::ShaderModule, RendererError> {
+    use gfx_hal::device::Device;
+
+    let spirv = variant.spirv();
+    let binary = match stage {
+      Stage::Vertex => spirv.vertex,
+      Stage::Fragment => spirv.fragment,
+      _ => return Err(RendererError::Gfx("Unsupported shader stage")),
+    };
+    let words =
+      gfx_hal::pso::read_spirv(Cursor::new(binary)).map_err(|_| RendererError::Gfx("Failed to read SPIR-V shader"))?;
+    device
+      .create_shader_module(&words)
+      .map_err(|e| shader_error(e, "Failed to create shader module"))
+  }
+}
+
+/// The GL backend of the web build compiles the GLSL ES sources, so it does not translate SPIR-V at runtime.
+#[cfg(target_arch = "wasm32")]
+impl ShaderBackend for gfx_backend_gl::Backend {
+  unsafe fn create_shader_module(
+    device: &gfx_backend_gl::Device,
+    variant: ShaderVariant,
+    stage: Stage,
+  ) -> Result<<gfx_backend_gl::Backend as gfx_hal::Backend>::ShaderModule, RendererError> {
+    let glsl_es = variant.glsl_es();
+    let source = match stage {
+      Stage::Vertex => glsl_es.vertex,
+      Stage::Fragment => glsl_es.fragment,
+      _ => return Err(RendererError::Gfx("Unsupported shader stage")),
+    };
+    device
+      .create_shader_module_from_source(source.as_bytes(), stage)
+      .map_err(|e| shader_error(e, "Failed to compile GLSL ES shader"))
+  }
+}
+
+fn shader_error(error: gfx_hal::device::ShaderError, message: &'static str) -> RendererError {
+  match error {
+    gfx_hal::device::ShaderError::OutOfMemory(_) => RendererError::OutOfMemory,
+    _ => RendererError::Gfx(message),
+  }
+}
@@ -0,0 +1,45 @@
+#version 300 es
+precision mediump float;
+precision highp int;
+
+struct _15
+{
+    highp vec4 outFragColor;
+};
+
+in highp vec4 v_0;
+in highp vec3 v_1;
+layout(location = 0) out highp vec4 _120;
+highp vec4 inColor = vec4(0.0);
+highp vec3 inCurve = vec3(0.0);
+highp vec4 outFragColor = vec4(0.0);
+
+void shader_main()
+{
+    highp vec2 gradient = vec2(0.0);
+    highp float f = 0.0;
+    highp vec2 dv = vec2(0.0);
+    highp float coverage = 1.0;
+    highp float _distance = 0.0;
+    highp vec2 du = vec2(0.0);
+    f = inCurve.z * ((inCurve.x * inCurve.x) - inCurve.y);
+    du = vec2(dFdx(inCurve.x), dFdy(inCurve.x));
+    dv = vec2(dFdx(inCurve.y), dFdy(inCurve.y));
+    gradient = ((du * (2.0 * inCurve.x)) - dv) * inCurve.z;
+    _distance = f / max(length(gradient), 9.9999999747524270787835121154785e-07);
+    coverage = clamp(0.5 - _distance, 0.0, 1.0);
+    if (coverage <= 0.0)
+    {
+        discard;
+    }
+    outFragColor = vec4(inColor.xyz, inColor.w * coverage);
+}
+
+void main()
+{
+    inColor = v_0;
+    inCurve = v_1;
+    shader_main();
+    _120 = _15(outFragColor).outFragColor;
+}
+
@@ -0,0 +1,68 @@
+#version 300 es
+
+struct PushConsts
+{
+    mat4 viewProjection;
+};
+
+struct _17
+{
+    vec4 outColor;
+    vec3 outCurve;
+    vec4 _m2;
+};
+
+struct _39
+{
+    PushConsts _m0;
+};
+
+uniform _39 pushConsts;
+
+layout(location = 0) in vec3 _100;
+layout(location = 1) in vec3 _103;
+layout(location = 2) in vec3 _105;
+layout(location = 3) in vec3 _107;
+layout(location = 4) in vec3 _109;
+layout(location = 5) in vec4 _111;
+layout(location = 6) in vec4 _114;
+out vec4 v_0;
+out vec3 v_1;
+vec3 inPos = vec3(0.0);
+vec3 inColor = vec3(0.0);
+vec3 inCurve = vec3(0.0);
+vec3 inMatrixX = vec3(0.0);
+vec3 inMatrixY = vec3(0.0);
+vec4 inColorMult = vec4(0.0);
+vec4 inColorAdd = vec4(0.0);
+vec4 outColor = vec4(0.0);
+vec3 outCurve = vec3(0.0);
+vec4 _RESERVED_IDENTIFIER_FIXUP_gl_Position = vec4(0.0);
+
+void shader_main()
+{
+    vec3 local = vec3(0.0);
+    vec2 world = vec2(0.0);
+    outColor = clamp((vec4(inColor, 1.0) * inColorMult) + inColorAdd, vec4(0.0), vec4(1.0));
+    outCurve = inCurve;
+    local = vec3(inPos.xy, 1.0);
+    world = vec2(dot(inMatrixX, local), dot(inMatrixY, local));
+    _RESERVED_IDENTIFIER_FIXUP_gl_Position = pushConsts._m0.viewProjection * vec4(world, inPos.z, 1.0);
+}
+
+void main()
+{
+    inPos = _100;
+    inColor = _103;
+    inCurve = _105;
+    inMatrixX = _107;
+    inMatrixY = _109;
+    inColorMult = _111;
+    inColorAdd = _114;
+    shader_main();
+    _17 _128 = _17(outColor, outCurve, _RESERVED_IDENTIFIER_FIXUP_gl_Position);
+    v_0 = _128.outColor;
+    v_1 = _128.outCurve;
+    gl_Position = _128._m2;
+}
+
@@ -0,0 +1,30 @@
+#version 300 es
+precision mediump float;
+precision highp int;
+
+struct _6
+{
+    highp vec4 outFragColor;
+};
+
+in highp vec4 v_0;
+in highp vec3 v_1;
+layout(location = 0) out highp vec4 _41;
+highp vec4 inColor = vec4(0.0);
+highp vec3 inCurve = vec3(0.0);
+highp vec4 outFragColor = vec4(0.0);
+
+void shader_main()
+{
+    highp float coverage = 1.0;
+    outFragColor = vec4(inColor.xyz, inColor.w * coverage);
+}
+
+void main()
+{
+    inColor = v_0;
+    inCurve = v_1;
+    shader_main();
+    _41 = _6(outFragColor).outFragColor;
+}
+
@@ -1,13 +1,28 @@
 use crate::asset::{MorphShapeId, ShapeId};
-use swf_tree::StraightSRgba8;
+use crate::bounds::Bounds;
+use lyon::math::Point;
+use serde::{Deserialize, Serialize};
+use swf_tree::{BlendMode, ColorTransformWithAlpha, Filter, StraightSRgba8};
 
 /// Represents a stage state
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Stage {
   pub background_color: StraightSRgba8,
   pub display_root: Vec<DisplayPrimitive>,
 }
 
+impl Stage {
+  /// Encodes the stage in a compact binary form (CBOR).
+  pub fn to_bytes(&self) -> Result<Vec<u8>, serde_cbor::Error> {
+    serde_cbor::to_vec(self)
+  }
+
+  /// Decodes a stage encoded with `to_bytes`.
+  pub fn from_bytes(bytes: &[u8]) -> Result<Self, serde_cbor::Error> {
+    serde_cbor::from_slice(bytes)
+  }
+}
+
 /// Represents a 2D transformation matrix.
 ///
 /// The coefficients are ordered as in `swf_tree::Matrix` and represent the following matrix:
@@ -16,7 +31,9 @@ pub struct Stage {
 /// [c2 c1 c5]
 /// [0  0  1 ]
 /// ```
-#[derive(Debug, Clone)]
+///
+/// A point `(x, y)` is mapped to `(c0 * x + c3 * y + c4, c2 * x + c1 * y + c5)`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Matrix2D(pub [f32; 6]);
 
 impl ::std::default::Default for Matrix2D {
@@ -25,35 +42,279 @@ impl ::std::default::Default for Matrix2D {
   }
 }
 
+/// Converts a SWF matrix.
+///
+/// The conversion is exact for scale and skew coefficients in `(-256, 256)` and
+/// translations in `(-2^24, 2^24)` twips, which covers the matrices found in practice.
+impl From<&swf_tree::Matrix> for Matrix2D {
+  fn from(matrix: &swf_tree::Matrix) -> Self {
+    Self([
+      f64::from(matrix.scale_x) as f32,
+      f64::from(matrix.scale_y) as f32,
+      f64::from(matrix.rotate_skew0) as f32,
+      f64::from(matrix.rotate_skew1) as f32,
+      matrix.translate_x as f32,
+      matrix.translate_y as f32,
+    ])
+  }
+}
+
+impl Matrix2D {
+  /// Returns the matrix applying `other` first, then `self`.
+  pub fn multiply(&self, other: &Matrix2D) -> Matrix2D {
+    let [a0, d0, b0, c0, tx0, ty0] = self.0;
+    let [a1, d1, b1, c1, tx1, ty1] = other.0;
+    Matrix2D([
+      a0 * a1 + c0 * b1,
+      b0 * c1 + d0 * d1,
+      b0 * a1 + d0 * b1,
+      a0 * c1 + c0 * d1,
+      a0 * tx1 + c0 * ty1 + tx0,
+      b0 * tx1 + d0 * ty1 + ty0,
+    ])
+  }
+
+  pub fn determinant(&self) -> f32 {
+    let [a, d, b, c, _, _] = self.0;
+    a * d - b * c
+  }
+
+  /// Returns the largest factor by which the matrix scales the length of the axes.
+  pub fn max_scale(&self) -> f32 {
+    let [a, d, b, c, _, _] = self.0;
+    f32::max(a.hypot(b), c.hypot(d))
+  }
+
+  /// Returns the inverse matrix, or `None` if the matrix is not invertible.
+  pub fn invert(&self) -> Option<Matrix2D> {
+    let [a, d, b, c, tx, ty] = self.0;
+    let det = self.determinant();
+    if det == 0.0 || !det.is_finite() {
+      return None;
+    }
+    Some(Matrix2D([
+      d / det,
+      a / det,
+      -b / det,
+      -c / det,
+      (c * ty - d * tx) / det,
+      (b * tx - a * ty) / det,
+    ]))
+  }
+
+  pub fn transform_point(&self, point: Point) -> Point {
+    let [a, d, b, c, tx, ty] = self.0;
+    Point::new(a * point.x + c * point.y + tx, b * point.x + d * point.y + ty)
+  }
+
+  /// Returns the axis-aligned bounds of the transformed corners of `bounds`.
+  pub fn transform_bounds(&self, bounds: &Bounds) -> Bounds {
+    let mut result = Bounds::from_point(self.transform_point(Point::new(bounds.x_min, bounds.y_min)));
+    result.extend(self.transform_point(Point::new(bounds.x_max, bounds.y_min)));
+    result.extend(self.transform_point(Point::new(bounds.x_min, bounds.y_max)));
+    result.extend(self.transform_point(Point::new(bounds.x_max, bounds.y_max)));
+    result
+  }
+
+  /// Returns the matrix as a column-major 4x4 matrix, for use in shaders.
+  pub fn to_mat4(&self) -> [f32; 16] {
+    let [a, d, b, c, tx, ty] = self.0;
+    [a, b, 0.0, 0.0, c, d, 0.0, 0.0, 0.0, 0.0, 1.0, 0.0, tx, ty, 0.0, 1.0]
+  }
+
+  /// Decomposes the matrix into translation, scale, rotation and skew.
+  ///
+  /// Reflections are represented by a skew of `PI`.
+  pub fn decompose(&self) -> DecomposedMatrix {
+    let [a, d, b, c, tx, ty] = self.0;
+    let rotation = f32::atan2(b, a);
+    let skew_x = f32::atan2(-c, d);
+    let mut skew = skew_x - rotation;
+    if skew > ::std::f32::consts::PI {
+      skew -= 2.0 * ::std::f32::consts::PI;
+    } else if skew <= -::std::f32::consts::PI {
+      skew += 2.0 * ::std::f32::consts::PI;
+    }
+    DecomposedMatrix {
+      translate_x: tx,
+      translate_y: ty,
+      scale_x: f32::hypot(a, b),
+      scale_y: f32::hypot(c, d),
+      rotation,
+      skew,
+    }
+  }
+
+  /// Builds a matrix from its decomposition, inverse of `decompose`.
+  pub fn compose(decomposed: &DecomposedMatrix) -> Matrix2D {
+    let rotation_x = decomposed.rotation + decomposed.skew;
+    Matrix2D([
+      decomposed.scale_x * f32::cos(decomposed.rotation),
+      decomposed.scale_y * f32::cos(rotation_x),
+      decomposed.scale_x * f32::sin(decomposed.rotation),
+      -decomposed.scale_y * f32::sin(rotation_x),
+      decomposed.translate_x,
+      decomposed.translate_y,
+    ])
+  }
+}
+
+impl ::std::ops::Mul for &Matrix2D {
+  type Output = Matrix2D;
+
+  fn mul(self, other: &Matrix2D) -> Matrix2D {
+    self.multiply(other)
+  }
+}
+
+/// Represents the components of a 2D transformation matrix.
+///
+/// The matrix first scales, then skews the y axis by `skew`, rotates by `rotation` and translates.
+/// Angles are in radians.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecomposedMatrix {
+  pub translate_x: f32,
+  pub translate_y: f32,
+  pub scale_x: f32,
+  pub scale_y: f32,
+  pub rotation: f32,
+  pub skew: f32,
+}
+
+/// Represents a color transformation.
+///
+/// Each channel `c` (in the range `[0, 1]`) is transformed as `c * mult + add`, in the order RGBA.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ColorTransform {
+  pub mult: [f32; 4],
+  pub add: [f32; 4],
+}
+
+impl ::std::default::Default for ColorTransform {
+  fn default() -> Self {
+    Self {
+      mult: [1.0, 1.0, 1.0, 1.0],
+      add: [0.0, 0.0, 0.0, 0.0],
+    }
+  }
+}
+
+impl ColorTransform {
+  /// Returns the color transform applying `other` first, then `self`.
+  pub fn multiply(&self, other: &ColorTransform) -> ColorTransform {
+    let mut result = ColorTransform::default();
+    for i in 0..4 {
+      result.mult[i] = self.mult[i] * other.mult[i];
+      result.add[i] = self.mult[i] * other.add[i] + self.add[i];
+    }
+    result
+  }
+}
+
+impl From<&ColorTransformWithAlpha> for ColorTransform {
+  fn from(cx: &ColorTransformWithAlpha) -> Self {
+    Self {
+      mult: [
+        f64::from(cx.red_mult) as f32,
+        f64::from(cx.green_mult) as f32,
+        f64::from(cx.blue_mult) as f32,
+        f64::from(cx.alpha_mult) as f32,
+      ],
+      add: [
+        f32::from(cx.red_add) / 255.0,
+        f32::from(cx.green_add) / 255.0,
+        f32::from(cx.blue_add) / 255.0,
+        f32::from(cx.alpha_add) / 255.0,
+      ],
+    }
+  }
+}
+
 /// Represents the interpolation ratio of a morph shape.
 ///
 /// A value of `0` indicates that the shape is in its start state.
 /// A value of `core::16::MAX` indicates that the shape is its end state.
 /// Intermediate values correspond to a linear interpolation between these two states.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct MorphRatio(pub u16);
 
 /// Represents a static shape retrieved from the asset store.
 ///
 /// The shape must first be registered with `register_shape`
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct StoredShape {
   pub id: ShapeId,
   pub matrix: Matrix2D,
+  pub color_transform: ColorTransform,
+  pub name: Option<String>,
 }
 
 /// Represents a morph shape retrieved from the asset store.
 ///
 /// The shape must first be registered with `register_morph_shape`
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct StoredMorphShape {
   pub id: MorphShapeId,
   pub matrix: Matrix2D,
+  pub color_transform: ColorTransform,
   pub ratio: MorphRatio,
+  pub name: Option<String>,
+}
+
+/// Represents a group of primitives sharing a transform, such as a sprite instance.
+///
+/// The matrix, color transform, blend mode and filters apply to all the children.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisplayContainer {
+  pub matrix: Matrix2D,
+  pub color_transform: ColorTransform,
+  pub blend_mode: BlendMode,
+  pub filters: Vec<Filter>,
+  pub name: Option<String>,
+  pub children: Vec<DisplayPrimitive>,
+}
+
+/// Represents the displayed state of a button.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ButtonState {
+  Up,
+  Over,
+  Down,
+}
+
+/// Represents a button instance.
+///
+/// `children` are the characters of the current `state`. `hit_area` holds the characters
+/// of the hit-test state: they are never drawn but let the host detect pointer input
+/// and pick the state to display.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DisplayButton {
+  pub matrix: Matrix2D,
+  pub color_transform: ColorTransform,
+  pub name: Option<String>,
+  pub state: ButtonState,
+  pub children: Vec<DisplayPrimitive>,
+  pub hit_area: Vec<DisplayPrimitive>,
+}
+
+/// Represents a clipping layer.
+///
+/// The `mask` is not drawn: it only restricts the visible area of the `children`.
+/// The children are the primitives placed at the depths between the mask and `clip_depth` (inclusive).
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ClipLayer {
+  pub clip_depth: u16,
+  pub mask: Box<DisplayPrimitive>,
+  pub children: Vec<DisplayPrimitive>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "kebab-case")]
 pub enum DisplayPrimitive {
   Shape(StoredShape),
   MorphShape(StoredMorphShape),
+  Container(DisplayContainer),
+  Button(DisplayButton),
+  Clip(ClipLayer),
 }
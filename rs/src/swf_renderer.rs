@@ -1,7 +1,15 @@
-use crate::stage::Stage;
+use crate::error::RendererError;
+use crate::stage::{ColorTransform, Matrix2D, Stage};
+use crate::viewport::Viewport;
 
 pub trait SwfRenderer {
-  fn render(&mut self, stage: Stage) -> ();
+  fn render(&mut self, stage: Stage) -> Result<(), RendererError>;
+
+  /// Resizes the output to `width` by `height` pixels.
+  fn resize(&mut self, width: u32, height: u32) -> Result<(), RendererError>;
+
+  /// Sets how the stage is mapped to the output.
+  fn set_viewport(&mut self, viewport: Viewport) -> ();
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -9,4 +17,30 @@ pub trait SwfRenderer {
 pub struct Vertex {
   pub position: [f32; 3],
   pub color: [f32; 3],
+  /// Curve coordinates `(u, v, sign)` evaluated by the fragment shader, see `crate::curve`
+  pub curve: [f32; 3],
+}
+
+/// Per-instance data of a draw call.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct Instance {
+  /// First row of the world matrix: `x' = a * x + c * y + tx`
+  pub matrix_x: [f32; 3],
+  /// Second row of the world matrix: `y' = b * x + d * y + ty`
+  pub matrix_y: [f32; 3],
+  pub color_mult: [f32; 4],
+  pub color_add: [f32; 4],
+}
+
+impl Instance {
+  pub fn new(matrix: &Matrix2D, color_transform: &ColorTransform) -> Self {
+    let [a, d, b, c, tx, ty] = matrix.0;
+    Self {
+      matrix_x: [a, c, tx],
+      matrix_y: [b, d, ty],
+      color_mult: color_transform.mult,
+      color_add: color_transform.add,
+    }
+  }
 }
@@ -0,0 +1,601 @@
+use std::collections::{BTreeMap, HashMap};
+use std::iter::Peekable;
+use std::rc::Rc;
+
+use log::warn;
+use swf_tree::tags::{DefineButton, DefineSprite, PlaceObject, RemoveObject};
+use swf_tree::{BlendMode, ButtonRecord, Filter, SRgb8, StraightSRgba8, Tag};
+
+use crate::asset::{ClientAssetStore, MorphShapeId, MovieId, ShapeId};
+use crate::stage::{
+  ButtonState, ClipLayer, ColorTransform, DisplayButton, DisplayContainer, DisplayPrimitive, Matrix2D, MorphRatio,
+  Stage, StoredMorphShape, StoredShape,
+};
+
+/// Renderer asset or definition corresponding to a SWF character id.
+#[derive(Debug, Clone)]
+pub enum Character {
+  Shape(ShapeId),
+  MorphShape(MorphShapeId),
+  Sprite(Rc<SpriteDefinition>),
+  Button(Rc<ButtonDefinition>),
+}
+
+/// Sprite definition, with its control tags grouped by frame.
+#[derive(Debug)]
+pub struct SpriteDefinition {
+  frames: Vec<Vec<Tag>>,
+}
+
+impl SpriteDefinition {
+  pub fn new(tag: &DefineSprite) -> Self {
+    let mut frames: Vec<Vec<Tag>> = Vec::new();
+    let mut frame: Vec<Tag> = Vec::new();
+    for tag in tag.tags.iter() {
+      match tag {
+        Tag::ShowFrame => frames.push(::std::mem::replace(&mut frame, Vec::new())),
+        tag => frame.push(tag.clone()),
+      }
+    }
+    if !frame.is_empty() || frames.is_empty() {
+      frames.push(frame);
+    }
+    Self { frames }
+  }
+
+  pub fn frame_count(&self) -> usize {
+    self.frames.len()
+  }
+}
+
+/// Independent timeline of a sprite instance.
+#[derive(Debug, Clone)]
+struct SpriteInstance {
+  definition: Rc<SpriteDefinition>,
+  /// Index of the current frame
+  frame: usize,
+  timeline: Timeline,
+}
+
+impl SpriteInstance {
+  fn new(definition: Rc<SpriteDefinition>, characters: &HashMap<u16, Character>) -> Self {
+    let mut instance = Self {
+      definition,
+      frame: 0,
+      timeline: Timeline::new(),
+    };
+    instance.run_frame(characters);
+    instance
+  }
+
+  /// Moves to the next frame, looping back to the first frame after the last one.
+  fn advance(&mut self, characters: &HashMap<u16, Character>) -> () {
+    self.timeline.advance(characters);
+    if self.definition.frame_count() <= 1 {
+      return;
+    }
+    self.frame = (self.frame + 1) % self.definition.frame_count();
+    if self.frame == 0 {
+      self.rewind();
+    }
+    self.run_frame(characters);
+  }
+
+  /// Prepares the display list for a loop to the first frame.
+  ///
+  /// As in Flash, the objects also placed by the first frame (same depth and character) are kept with their
+  /// state, including the frame cursors of nested sprites: the first frame only resets their properties. The
+  /// other objects are removed.
+  fn rewind(&mut self) -> () {
+    let placed: HashMap<u16, u16> = self.definition.frames[0]
+      .iter()
+      .filter_map(|tag| match tag {
+        Tag::PlaceObject(ref tag) if !tag.is_update => tag.character_id.map(|character_id| (tag.depth, character_id)),
+        _ => None,
+      })
+      .collect();
+    let removed: Vec<u16> = self
+      .timeline
+      .layers
+      .iter()
+      .filter(|(depth, object)| placed.get(depth) != Some(&object.character_id))
+      .map(|(depth, _)| *depth)
+      .collect();
+    for depth in removed.iter() {
+      self.timeline.layers.remove(depth);
+    }
+  }
+
+  fn run_frame(&mut self, characters: &HashMap<u16, Character>) -> () {
+    let definition = Rc::clone(&self.definition);
+    for tag in definition.frames[self.frame].iter() {
+      match tag {
+        Tag::PlaceObject(ref tag) => self.timeline.place_object(characters, tag),
+        Tag::RemoveObject(ref tag) => self.timeline.remove_object(tag),
+        _ => {}
+      }
+    }
+  }
+}
+
+/// Button definition, with the character records of all its states.
+#[derive(Debug)]
+pub struct ButtonDefinition {
+  records: Vec<ButtonRecord>,
+}
+
+impl ButtonDefinition {
+  pub fn new(tag: &DefineButton) -> Self {
+    Self {
+      records: tag.characters.clone(),
+    }
+  }
+
+  /// Builds the display list of the records matching `filter`.
+  fn timeline<F>(&self, characters: &HashMap<u16, Character>, filter: F) -> Timeline
+  where
+    F: Fn(&ButtonRecord) -> bool,
+  {
+    let mut timeline = Timeline::new();
+    for record in self.records.iter() {
+      if !filter(record) {
+        continue;
+      }
+      match characters.get(&record.character_id) {
+        Some(character) => {
+          let mut object = DisplayObject::new(record.character_id, character.clone(), characters);
+          object.matrix = Matrix2D::from(&record.matrix);
+          object.color_transform = ColorTransform::from(&record.color_transform);
+          object.blend_mode = record.blend_mode;
+          object.filters = record.filters.clone();
+          timeline.layers.insert(record.depth, object);
+        }
+        None => warn!("Ignoring button record: unknown character id {}", record.character_id),
+      }
+    }
+    timeline
+  }
+}
+
+fn is_in_state(record: &ButtonRecord, state: ButtonState) -> bool {
+  match state {
+    ButtonState::Up => record.state_up,
+    ButtonState::Over => record.state_over,
+    ButtonState::Down => record.state_down,
+  }
+}
+
+/// Button instance, with the display list of its current state.
+#[derive(Debug, Clone)]
+struct ButtonInstance {
+  definition: Rc<ButtonDefinition>,
+  state: ButtonState,
+  timeline: Timeline,
+  hit_area: Timeline,
+}
+
+impl ButtonInstance {
+  fn new(definition: Rc<ButtonDefinition>, characters: &HashMap<u16, Character>) -> Self {
+    let state = ButtonState::Up;
+    let timeline = definition.timeline(characters, |record| is_in_state(record, state));
+    let hit_area = definition.timeline(characters, |record| record.state_hit_test);
+    Self {
+      definition,
+      state,
+      timeline,
+      hit_area,
+    }
+  }
+
+  fn set_state(&mut self, state: ButtonState, characters: &HashMap<u16, Character>) -> () {
+    if state != self.state {
+      self.state = state;
+      self.timeline = self
+        .definition
+        .timeline(characters, |record| is_in_state(record, state));
+    }
+  }
+}
+
+/// Character instance placed at some depth of the display list.
+#[derive(Debug, Clone)]
+struct DisplayObject {
+  character_id: u16,
+  character: Character,
+  matrix: Matrix2D,
+  color_transform: ColorTransform,
+  ratio: u16,
+  name: Option<String>,
+  clip_depth: Option<u16>,
+  blend_mode: BlendMode,
+  filters: Vec<Filter>,
+  sprite: Option<SpriteInstance>,
+  button: Option<ButtonInstance>,
+}
+
+impl DisplayObject {
+  fn new(character_id: u16, character: Character, characters: &HashMap<u16, Character>) -> Self {
+    let sprite = match character {
+      Character::Sprite(ref definition) => Some(SpriteInstance::new(Rc::clone(definition), characters)),
+      _ => None,
+    };
+    let button = match character {
+      Character::Button(ref definition) => Some(ButtonInstance::new(Rc::clone(definition), characters)),
+      _ => None,
+    };
+    Self {
+      character_id,
+      character,
+      matrix: Matrix2D::default(),
+      color_transform: ColorTransform::default(),
+      ratio: 0,
+      name: None,
+      clip_depth: None,
+      blend_mode: BlendMode::Normal,
+      filters: Vec::new(),
+      sprite,
+      button,
+    }
+  }
+
+  /// Resets the properties set by the placement tags, keeping the state of the sprite or button instance.
+  fn reset_placement(&mut self) -> () {
+    self.matrix = Matrix2D::default();
+    self.color_transform = ColorTransform::default();
+    self.ratio = 0;
+    self.name = None;
+    self.clip_depth = None;
+    self.blend_mode = BlendMode::Normal;
+    self.filters = Vec::new();
+  }
+
+  fn to_primitive(&self) -> DisplayPrimitive {
+    let has_effects = match self.blend_mode {
+      BlendMode::Normal => !self.filters.is_empty(),
+      _ => true,
+    };
+    match self.character {
+      // Sprites are already containers holding the blend mode and filters
+      Character::Sprite(_) => self.to_simple_primitive(&self.matrix, &self.color_transform),
+      _ if has_effects => DisplayPrimitive::Container(DisplayContainer {
+        matrix: self.matrix.clone(),
+        color_transform: self.color_transform.clone(),
+        blend_mode: self.blend_mode,
+        filters: self.filters.clone(),
+        name: None,
+        children: vec![self.to_simple_primitive(&Matrix2D::default(), &ColorTransform::default())],
+      }),
+      _ => self.to_simple_primitive(&self.matrix, &self.color_transform),
+    }
+  }
+
+  fn to_simple_primitive(&self, matrix: &Matrix2D, color_transform: &ColorTransform) -> DisplayPrimitive {
+    match self.character {
+      Character::Shape(id) => DisplayPrimitive::Shape(StoredShape {
+        id,
+        matrix: matrix.clone(),
+        color_transform: color_transform.clone(),
+        name: self.name.clone(),
+      }),
+      Character::MorphShape(id) => DisplayPrimitive::MorphShape(StoredMorphShape {
+        id,
+        matrix: matrix.clone(),
+        color_transform: color_transform.clone(),
+        ratio: MorphRatio(self.ratio),
+        name: self.name.clone(),
+      }),
+      Character::Sprite(_) => DisplayPrimitive::Container(DisplayContainer {
+        matrix: matrix.clone(),
+        color_transform: color_transform.clone(),
+        blend_mode: self.blend_mode,
+        filters: self.filters.clone(),
+        name: self.name.clone(),
+        children: match self.sprite {
+          Some(ref sprite) => sprite.timeline.primitives(),
+          None => Vec::new(),
+        },
+      }),
+      Character::Button(_) => {
+        let (state, children, hit_area) = match self.button {
+          Some(ref button) => (button.state, button.timeline.primitives(), button.hit_area.primitives()),
+          None => (ButtonState::Up, Vec::new(), Vec::new()),
+        };
+        DisplayPrimitive::Button(DisplayButton {
+          matrix: matrix.clone(),
+          color_transform: color_transform.clone(),
+          name: self.name.clone(),
+          state,
+          children,
+          hit_area,
+        })
+      }
+    }
+  }
+}
+
+/// Depth-ordered display list of a single timeline (the root movie or a sprite instance).
+#[derive(Debug, Clone)]
+struct Timeline {
+  layers: BTreeMap<u16, DisplayObject>,
+}
+
+impl Timeline {
+  fn new() -> Self {
+    Self {
+      layers: BTreeMap::new(),
+    }
+  }
+
+  fn place_object(&mut self, characters: &HashMap<u16, Character>, tag: &PlaceObject) -> () {
+    let character: Option<(u16, Character)> = match tag.character_id {
+      None => None,
+      Some(character_id) => match characters.get(&character_id) {
+        Some(character) => Some((character_id, character.clone())),
+        None => {
+          warn!("Ignoring PlaceObject: unknown character id {}", character_id);
+          return;
+        }
+      },
+    };
+
+    let object: &mut DisplayObject = if tag.is_update {
+      match self.layers.get_mut(&tag.depth) {
+        Some(object) => {
+          if let Some((character_id, character)) = character {
+            if character_id != object.character_id {
+              *object = DisplayObject {
+                matrix: object.matrix.clone(),
+                color_transform: object.color_transform.clone(),
+                ratio: object.ratio,
+                name: object.name.clone(),
+                clip_depth: object.clip_depth,
+                blend_mode: object.blend_mode,
+                filters: object.filters.clone(),
+                ..DisplayObject::new(character_id, character, characters)
+              };
+            }
+          }
+          object
+        }
+        None => {
+          warn!("Ignoring PlaceObject: no object to update at depth {}", tag.depth);
+          return;
+        }
+      }
+    } else {
+      match character {
+        Some((character_id, character)) => {
+          let is_kept = self
+            .layers
+            .get(&tag.depth)
+            .map_or(false, |object| object.character_id == character_id);
+          if is_kept {
+            // Placed again after a rewind: keep the instance, the tag sets its properties
+            self.layers.get_mut(&tag.depth).unwrap().reset_placement();
+          } else {
+            let object = DisplayObject::new(character_id, character, characters);
+            self.layers.insert(tag.depth, object);
+          }
+          self.layers.get_mut(&tag.depth).unwrap()
+        }
+        None => {
+          warn!("Ignoring PlaceObject: missing character id at depth {}", tag.depth);
+          return;
+        }
+      }
+    };
+
+    if let Some(ref matrix) = tag.matrix {
+      object.matrix = Matrix2D::from(matrix);
+    }
+    if let Some(ref color_transform) = tag.color_transform {
+      object.color_transform = ColorTransform::from(color_transform);
+    }
+    if let Some(ratio) = tag.ratio {
+      object.ratio = ratio;
+    }
+    if let Some(ref name) = tag.name {
+      object.name = Some(name.clone());
+    }
+    if let Some(clip_depth) = tag.clip_depth {
+      object.clip_depth = Some(clip_depth);
+    }
+    if let Some(blend_mode) = tag.blend_mode {
+      object.blend_mode = blend_mode;
+    }
+    if let Some(ref filters) = tag.filters {
+      object.filters = filters.clone();
+    }
+  }
+
+  fn remove_object(&mut self, tag: &RemoveObject) -> () {
+    match self.layers.get(&tag.depth) {
+      Some(object) if tag.character_id.map_or(true, |id| id == object.character_id) => {
+        self.layers.remove(&tag.depth);
+      }
+      Some(_) => warn!("Ignoring RemoveObject: character mismatch at depth {}", tag.depth),
+      None => warn!("Ignoring RemoveObject: no object at depth {}", tag.depth),
+    }
+  }
+
+  /// Advances the timelines of all the sprite instances in this display list.
+  fn advance(&mut self, characters: &HashMap<u16, Character>) -> () {
+    for object in self.layers.values_mut() {
+      if let Some(ref mut sprite) = object.sprite {
+        sprite.advance(characters);
+      }
+      if let Some(ref mut button) = object.button {
+        button.timeline.advance(characters);
+      }
+    }
+  }
+
+  /// Returns the object found by following `path`, a list of depths starting in this display list.
+  fn get_mut(&mut self, path: &[u16]) -> Option<&mut DisplayObject> {
+    let (depth, rest) = path.split_first()?;
+    let object = self.layers.get_mut(depth)?;
+    if rest.is_empty() {
+      return Some(object);
+    }
+    if let Some(ref mut sprite) = object.sprite {
+      return sprite.timeline.get_mut(rest);
+    }
+    if let Some(ref mut button) = object.button {
+      return button.timeline.get_mut(rest);
+    }
+    None
+  }
+
+  fn primitives(&self) -> Vec<DisplayPrimitive> {
+    let mut layers = self.layers.iter().peekable();
+    collect_primitives(&mut layers, None)
+  }
+}
+
+/// Interpreter for the display list tags of a movie.
+///
+/// Feed it the tags of the root timeline in order: each `ShowFrame` produces the `Stage`
+/// to pass to `SwfRenderer::render`. Sprite instances advance their own timeline on every frame.
+/// Buttons are displayed in their up state until the host selects another state.
+///
+/// The assets are registered in the namespace of the movie, so several movies can share a store and be
+/// composited in one stage (e.g. by nesting their primitives in containers).
+pub struct DisplayList {
+  movie: MovieId,
+  characters: HashMap<u16, Character>,
+  root: Timeline,
+  background_color: StraightSRgba8,
+}
+
+impl DisplayList {
+  /// Creates the display list of a movie, see `ClientAssetStore::create_movie`.
+  pub fn new(movie: MovieId) -> Self {
+    Self {
+      movie,
+      characters: HashMap::new(),
+      root: Timeline::new(),
+      background_color: StraightSRgba8 {
+        r: 255,
+        g: 255,
+        b: 255,
+        a: 255,
+      },
+    }
+  }
+
+  pub fn movie(&self) -> MovieId {
+    self.movie
+  }
+
+  /// Associates a SWF character id with a registered asset or definition.
+  pub fn define_character(&mut self, id: u16, character: Character) -> () {
+    self.characters.insert(id, character);
+  }
+
+  /// Applies a single tag.
+  ///
+  /// Shape and morph shape definitions are registered in `store`, in the namespace of the movie. The
+  /// definitions failing to register are logged and ignored: they keep their previous character, if any.
+  /// Returns the stage to render when the tag is `ShowFrame`.
+  pub fn apply_tag<S: ClientAssetStore>(&mut self, store: &mut S, tag: &Tag) -> Option<Stage> {
+    match tag {
+      Tag::DefineShape(ref tag) => match store.register_shape(self.movie, tag) {
+        Ok(id) => self.define_character(tag.id, Character::Shape(id)),
+        Err(e) => warn!("Failed to register shape {}: {}", tag.id, e),
+      },
+      Tag::DefineMorphShape(ref tag) => match store.register_morph_shape(self.movie, tag) {
+        Ok(id) => self.define_character(tag.id, Character::MorphShape(id)),
+        Err(e) => warn!("Failed to register morph shape {}: {}", tag.id, e),
+      },
+      Tag::DefineSprite(ref tag) => {
+        self.define_character(tag.id, Character::Sprite(Rc::new(SpriteDefinition::new(tag))));
+      }
+      Tag::DefineButton(ref tag) => {
+        self.define_character(tag.id, Character::Button(Rc::new(ButtonDefinition::new(tag))));
+      }
+      Tag::PlaceObject(ref tag) => self.place_object(tag),
+      Tag::RemoveObject(ref tag) => self.remove_object(tag),
+      Tag::SetBackgroundColor(ref tag) => self.set_background_color(tag.color),
+      Tag::ShowFrame => return Some(self.show_frame()),
+      _ => {}
+    }
+    None
+  }
+
+  pub fn place_object(&mut self, tag: &PlaceObject) -> () {
+    self.root.place_object(&self.characters, tag)
+  }
+
+  pub fn remove_object(&mut self, tag: &RemoveObject) -> () {
+    self.root.remove_object(tag)
+  }
+
+  pub fn set_background_color(&mut self, color: SRgb8) -> () {
+    self.background_color = StraightSRgba8 {
+      r: color.r,
+      g: color.g,
+      b: color.b,
+      a: 255,
+    };
+  }
+
+  /// Selects the displayed state of a button.
+  ///
+  /// `path` is the list of depths leading to the button, starting from the root timeline.
+  /// Returns `false` if there is no button at this path.
+  pub fn set_button_state(&mut self, path: &[u16], state: ButtonState) -> bool {
+    match self.root.get_mut(path) {
+      Some(DisplayObject {
+        button: Some(ref mut button),
+        ..
+      }) => {
+        button.set_state(state, &self.characters);
+        true
+      }
+      _ => false,
+    }
+  }
+
+  /// Ends the current frame: returns its stage and advances the nested sprite timelines.
+  pub fn show_frame(&mut self) -> Stage {
+    let stage = self.stage();
+    self.root.advance(&self.characters);
+    stage
+  }
+
+  /// Returns the current state of the display list.
+  pub fn stage(&self) -> Stage {
+    Stage {
+      background_color: self.background_color,
+      display_root: self.root.primitives(),
+    }
+  }
+}
+
+/// Collects the primitives up to `max_depth` (inclusive), nesting clipped objects in their clip layer.
+fn collect_primitives<'a, I>(layers: &mut Peekable<I>, max_depth: Option<u16>) -> Vec<DisplayPrimitive>
+where
+  I: Iterator<Item = (&'a u16, &'a DisplayObject)>,
+{
+  let mut primitives: Vec<DisplayPrimitive> = Vec::new();
+  while let Some((depth, _)) = layers.peek() {
+    if max_depth.map_or(false, |max_depth| **depth > max_depth) {
+      break;
+    }
+    let (_, object) = layers.next().unwrap();
+    let primitive = object.to_primitive();
+    match object.clip_depth {
+      Some(clip_depth) => {
+        let clip_depth = max_depth.map_or(clip_depth, |max_depth| u16::min(clip_depth, max_depth));
+        let children = collect_primitives(layers, Some(clip_depth));
+        primitives.push(DisplayPrimitive::Clip(ClipLayer {
+          clip_depth,
+          mask: Box::new(primitive),
+          children,
+        }));
+      }
+      None => primitives.push(primitive),
+    }
+  }
+  primitives
+}
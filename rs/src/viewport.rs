@@ -0,0 +1,182 @@
+use serde::{Deserialize, Serialize};
+use swf_tree::StraightSRgba8;
+
+use crate::bounds::Bounds;
+use crate::stage::Matrix2D;
+
+/// Number of twips per pixel at a scale of 100%.
+const TWIPS_PER_PIXEL: f32 = 20.0;
+
+/// Represents how the movie stage is scaled to fit the output, as in Flash's `Stage.scaleMode`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ScaleMode {
+  /// Scale uniformly so the whole stage is visible, letterboxing the remaining area.
+  ShowAll,
+  /// Scale uniformly so the stage covers the whole output, cropping the overflow.
+  NoBorder,
+  /// Scale each axis independently so the stage matches the output exactly.
+  ExactFit,
+  /// Keep the size of the stage (one pixel per 20 twips).
+  NoScale,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HorizontalAlign {
+  Left,
+  Center,
+  Right,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum VerticalAlign {
+  Top,
+  Middle,
+  Bottom,
+}
+
+/// Represents a rectangle in output pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PixelRect {
+  pub x: i32,
+  pub y: i32,
+  pub width: i32,
+  pub height: i32,
+}
+
+impl PixelRect {
+  /// Returns the smallest rectangle of whole pixels containing `bounds` (in pixels).
+  pub fn from_bounds(bounds: &Bounds) -> Self {
+    let x_min = bounds.x_min.floor() as i32;
+    let y_min = bounds.y_min.floor() as i32;
+    PixelRect {
+      x: x_min,
+      y: y_min,
+      width: bounds.x_max.ceil() as i32 - x_min,
+      height: bounds.y_max.ceil() as i32 - y_min,
+    }
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.width <= 0 || self.height <= 0
+  }
+
+  /// Returns the overlapping area, with a zero size if the rectangles are disjoint.
+  pub fn intersection(&self, other: &PixelRect) -> Self {
+    let x_min = i32::max(self.x, other.x);
+    let y_min = i32::max(self.y, other.y);
+    let x_max = i32::min(self.x + self.width, other.x + other.width);
+    let y_max = i32::min(self.y + self.height, other.y + other.height);
+    PixelRect {
+      x: x_min,
+      y: y_min,
+      width: i32::max(0, x_max - x_min),
+      height: i32::max(0, y_max - y_min),
+    }
+  }
+}
+
+/// Maps the movie stage (in twips) to the output surface (in pixels).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Viewport {
+  /// Stage rectangle of the movie, in twips (usually the frame size from the SWF header)
+  pub stage_rect: swf_tree::Rect,
+  pub scale_mode: ScaleMode,
+  pub horizontal_align: HorizontalAlign,
+  pub vertical_align: VerticalAlign,
+  /// Color of the output area outside of the stage
+  pub letterbox_color: StraightSRgba8,
+}
+
+impl Viewport {
+  /// Creates a viewport showing all of `stage_rect`, centered, with a black letterbox.
+  pub fn new(stage_rect: swf_tree::Rect) -> Self {
+    Self {
+      stage_rect,
+      scale_mode: ScaleMode::ShowAll,
+      horizontal_align: HorizontalAlign::Center,
+      vertical_align: VerticalAlign::Middle,
+      letterbox_color: StraightSRgba8 {
+        r: 0,
+        g: 0,
+        b: 0,
+        a: 255,
+      },
+    }
+  }
+
+  /// Returns the matrix from stage coordinates (twips) to output coordinates (pixels).
+  pub fn stage_matrix(&self, width: u32, height: u32) -> Matrix2D {
+    let (scale_x, scale_y) = self.scale(width, height);
+    let (offset_x, offset_y) = self.offset(width, height, scale_x, scale_y);
+    Matrix2D([
+      scale_x,
+      scale_y,
+      0.0,
+      0.0,
+      offset_x - scale_x * (self.stage_rect.x_min as f32),
+      offset_y - scale_y * (self.stage_rect.y_min as f32),
+    ])
+  }
+
+  /// Returns the area of the output covered by the stage, clamped to the output.
+  ///
+  /// The rest of the output is letterboxed.
+  pub fn visible_rect(&self, width: u32, height: u32) -> PixelRect {
+    let (scale_x, scale_y) = self.scale(width, height);
+    let (offset_x, offset_y) = self.offset(width, height, scale_x, scale_y);
+    let x_min = f32::max(0.0, offset_x.floor()) as i32;
+    let y_min = f32::max(0.0, offset_y.floor()) as i32;
+    let x_max = f32::min(width as f32, (offset_x + self.stage_width() * scale_x).ceil()) as i32;
+    let y_max = f32::min(height as f32, (offset_y + self.stage_height() * scale_y).ceil()) as i32;
+    PixelRect {
+      x: x_min,
+      y: y_min,
+      width: i32::max(0, x_max - x_min),
+      height: i32::max(0, y_max - y_min),
+    }
+  }
+
+  fn stage_width(&self) -> f32 {
+    (self.stage_rect.x_max - self.stage_rect.x_min) as f32
+  }
+
+  fn stage_height(&self) -> f32 {
+    (self.stage_rect.y_max - self.stage_rect.y_min) as f32
+  }
+
+  /// Returns the scale (in pixels per twip) on each axis.
+  fn scale(&self, width: u32, height: u32) -> (f32, f32) {
+    let no_scale = 1.0 / TWIPS_PER_PIXEL;
+    if self.stage_width() <= 0.0 || self.stage_height() <= 0.0 {
+      return (no_scale, no_scale);
+    }
+    let fit_x = (width as f32) / self.stage_width();
+    let fit_y = (height as f32) / self.stage_height();
+    match self.scale_mode {
+      ScaleMode::ShowAll => (f32::min(fit_x, fit_y), f32::min(fit_x, fit_y)),
+      ScaleMode::NoBorder => (f32::max(fit_x, fit_y), f32::max(fit_x, fit_y)),
+      ScaleMode::ExactFit => (fit_x, fit_y),
+      ScaleMode::NoScale => (no_scale, no_scale),
+    }
+  }
+
+  /// Returns the position (in pixels) of the top-left corner of the stage.
+  fn offset(&self, width: u32, height: u32, scale_x: f32, scale_y: f32) -> (f32, f32) {
+    let free_x = (width as f32) - self.stage_width() * scale_x;
+    let free_y = (height as f32) - self.stage_height() * scale_y;
+    let offset_x = match self.horizontal_align {
+      HorizontalAlign::Left => 0.0,
+      HorizontalAlign::Center => free_x / 2.0,
+      HorizontalAlign::Right => free_x,
+    };
+    let offset_y = match self.vertical_align {
+      VerticalAlign::Top => 0.0,
+      VerticalAlign::Middle => free_y / 2.0,
+      VerticalAlign::Bottom => free_y,
+    };
+    (offset_x, offset_y)
+  }
+}
@@ -61,7 +61,7 @@ pub fn wasm_start() {
 pub fn create_renderer(canvas: web_sys::HtmlCanvasElement) -> RendererHandle {
   let surface = back::Surface::from_canvas(canvas);
   let adapter = GfxRenderer::get_adapter(&surface, &surface).expect("Failed to find a GPU adapter supporting graphics");
-  let renderer: GfxRenderer<back::Backend> = GfxRenderer::new(adapter, surface);
+  let renderer: GfxRenderer<back::Backend> = GfxRenderer::new(adapter, surface).expect("Failed to create renderer");
   let mut store = GLOBAL_RENDERER_STORE.lock().expect("Failed to acquire global store");
   store.add(renderer)
 }
@@ -93,7 +93,9 @@ impl RendererHandle {
         },
         display_root: Vec::new(),
       };
-      r.render(stage)
+      if let Err(e) = r.render(stage) {
+        error!("{}", e);
+      }
     })
   }
 }